@@ -0,0 +1,32 @@
+# Bake the models the game loads into assets/baked.
+# Run again after changing an asset or the grid spacing.
+# With --check nothing is written, it fails if a baked file is out of date.
+set -e
+BAKE=${BAKE:-"cargo run -q --release -p model --bin bake --"}
+CHECK=""
+if [ "$1" = "--check" ]; then
+    CHECK="--check"
+fi
+mkdir -p assets/baked
+
+STALE=0
+bake() {
+    $BAKE $CHECK "$@" || STALE=1
+}
+
+# The units and grass are pixel art so they are kept sharp up close.
+PIXELS="--filter nearest --mipmaps box"
+SMOOTH="--filter linear --mipmaps box"
+# The shadow and road are exported as masked but fade out at their edges.
+FADES="--alpha-mode blend"
+
+bake $SMOOTH $FADES --alpha 0.5 assets/drop_shadow.glb assets/baked/drop_shadow.bake
+bake $PIXELS assets/cat_final.glb assets/baked/cat_final.bake
+bake $PIXELS assets/donut.glb assets/baked/donut.bake
+bake $SMOOTH $FADES assets/road.glb assets/baked/road.bake
+bake $PIXELS assets/grass.glb assets/baked/grass.bake
+bake $SMOOTH assets/select_model.glb assets/baked/select_model.bake
+bake $SMOOTH assets/attack.glb assets/baked/attack.bake
+bake assets/ascii5.png assets/baked/ascii5.bake
+
+exit $STALE
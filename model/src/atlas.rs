@@ -0,0 +1,416 @@
+//! Packs many textures into one so models can be drawn without switching textures.
+//!
+//! Each texture gets a border of `padding` pixels copied from its own edges. Filtering
+//! near the edge of a region then picks up the texture itself instead of whatever was
+//! packed next to it.
+//!
+//! Textures are also lined up on multiples of the padding when it is a power of two.
+//! Each texture can then be shrunk on its own for the mip levels until the padding
+//! runs out, so small levels don't mix neighbouring textures either.
+use super::*;
+
+///Where one texture ended up in the atlas, in pixels. Doesn't include the padding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Region {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+///Texture coordinates further out than this past the edges are taken to repeat the
+///texture rather than be off by rounding.
+const UV_TOLERANCE: f32 = 1e-3;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AtlasError {
+    ///A model wraps its texture around, which a region of an atlas can't do.
+    Repeats { texture: usize, uv: [f32; 2] },
+}
+
+impl std::fmt::Display for AtlasError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AtlasError::Repeats { texture, uv } => write!(
+                f,
+                "texture {} repeats at {:?} so it can't go in an atlas",
+                texture, uv
+            ),
+        }
+    }
+}
+
+impl std::error::Error for AtlasError {}
+
+///The first texture coordinate that is outside the texture.
+pub fn repeats(tex_coords: &[[f32; 2]]) -> Option<[f32; 2]> {
+    let outside = |a: f32| !(-UV_TOLERANCE..=1.0 + UV_TOLERANCE).contains(&a);
+    tex_coords
+        .iter()
+        .find(|[u, v]| outside(*u) || outside(*v))
+        .copied()
+}
+
+#[derive(Debug)]
+pub struct Atlas {
+    pub image: Img,
+    ///One per texture, in the order they were given.
+    pub regions: Vec<Region>,
+    pub padding: u32,
+}
+
+//How many mip levels still have at least one pixel of padding.
+fn mip_levels(padding: u32) -> u32 {
+    if padding == 0 {
+        0
+    } else {
+        padding.ilog2()
+    }
+}
+
+//A row of textures. Textures are put in the first row they fit in.
+struct Shelf {
+    y: u32,
+    height: u32,
+    used: u32,
+}
+
+impl Atlas {
+    ///Pack the textures of models, given with the texture coordinates that use them.
+    ///Models that repeat their texture are rejected, since [`Atlas::remap`] would clamp
+    ///them to its edges.
+    pub fn pack_models(
+        textures: &[Img],
+        tex_coords: &[&[[f32; 2]]],
+        padding: u32,
+    ) -> Result<Atlas, AtlasError> {
+        for (texture, a) in tex_coords.iter().enumerate() {
+            if let Some(uv) = repeats(a) {
+                return Err(AtlasError::Repeats { texture, uv });
+            }
+        }
+        Ok(Atlas::pack(textures, padding))
+    }
+
+    pub fn pack(textures: &[Img], padding: u32) -> Atlas {
+        let align = 1 << mip_levels(padding);
+        let padded = |t: &Img| {
+            (
+                (t.width + padding * 2).next_multiple_of(align),
+                (t.height + padding * 2).next_multiple_of(align),
+            )
+        };
+
+        //Tallest first so rows waste less space.
+        let mut order: Vec<_> = (0..textures.len()).collect();
+        order.sort_by_key(|&k| std::cmp::Reverse((textures[k].height, textures[k].width)));
+
+        let area: u32 = textures.iter().map(|t| padded(t).0 * padded(t).1).sum();
+        let widest = textures.iter().map(|t| padded(t).0).max().unwrap_or(1);
+        let width = widest
+            .max((area as f64).sqrt().ceil() as u32)
+            .next_power_of_two();
+
+        let mut shelves: Vec<Shelf> = vec![];
+        let mut spots = vec![(0, 0); textures.len()];
+        for k in order {
+            let (w, h) = padded(&textures[k]);
+            let shelf = match shelves
+                .iter_mut()
+                .find(|s| h <= s.height && s.used + w <= width)
+            {
+                Some(s) => s,
+                None => {
+                    let y = shelves.last().map(|s| s.y + s.height).unwrap_or(0);
+                    shelves.push(Shelf {
+                        y,
+                        height: h,
+                        used: 0,
+                    });
+                    shelves.last_mut().unwrap()
+                }
+            };
+            spots[k] = (shelf.used, shelf.y);
+            shelf.used += w;
+        }
+        let height = shelves.last().map(|s| s.y + s.height).unwrap_or(0).max(1);
+
+        let mut data = vec![0; width as usize * height as usize * 4];
+        let regions = textures
+            .iter()
+            .zip(spots)
+            .map(|(t, (x, y))| {
+                blit(&mut data, width, t, x, y, padding);
+                Region {
+                    x: x + padding,
+                    y: y + padding,
+                    width: t.width,
+                    height: t.height,
+                }
+            })
+            .collect();
+
+        Atlas {
+            image: Img {
+                width,
+                height,
+                data,
+            },
+            regions,
+            padding,
+        }
+    }
+
+    ///How many mip levels [`Atlas::mipmaps`] makes.
+    pub fn mip_levels(&self) -> u32 {
+        mip_levels(self.padding)
+    }
+
+    ///The mip levels below the atlas image. Every texture is shrunk on its own with
+    ///`filter` and put where its region ends up at that level, with what is left of
+    ///the padding. `textures` have to be the ones the atlas was packed with.
+    pub fn mipmaps(&self, textures: &[Img], filter: mipmap::MipFilter) -> Vec<Img> {
+        let levels = self.mip_levels();
+        let chains: Vec<_> = textures
+            .iter()
+            .map(|t| mipmap::mipmaps(t, filter, levels as usize))
+            .collect();
+
+        (1..=levels)
+            .map(|k| {
+                let width = (self.image.width >> k).max(1);
+                let height = (self.image.height >> k).max(1);
+                let padding = self.padding >> k;
+                let mut data = vec![0; width as usize * height as usize * 4];
+                for ((t, chain), r) in textures.iter().zip(chains.iter()).zip(self.regions.iter()) {
+                    //Tiny textures stop shrinking at one pixel.
+                    let level = chain.get(k as usize - 1).or(chain.last()).unwrap_or(t);
+                    let x = (r.x - self.padding) >> k;
+                    let y = (r.y - self.padding) >> k;
+                    blit(&mut data, width, level, x, y, padding);
+                }
+                Img {
+                    width,
+                    height,
+                    data,
+                }
+            })
+            .collect()
+    }
+
+    ///The corners of a region in texture coordinates, `[u0, v0, u1, v1]`.
+    pub fn uv_rect(&self, texture: usize) -> [f32; 4] {
+        let r = self.regions[texture];
+        let w = self.image.width as f32;
+        let h = self.image.height as f32;
+        [
+            r.x as f32 / w,
+            r.y as f32 / h,
+            (r.x + r.width) as f32 / w,
+            (r.y + r.height) as f32 / h,
+        ]
+    }
+
+    ///Move texture coordinates of a model into its region of the atlas.
+    ///Textures can't repeat inside an atlas so coordinates are clamped to the edges.
+    ///[`Atlas::pack_models`] makes sure that only shaves off rounding errors.
+    pub fn remap(&self, texture: usize, tex_coords: &[[f32; 2]]) -> Vec<[f32; 2]> {
+        let [u0, v0, u1, v1] = self.uv_rect(texture);
+        tex_coords
+            .iter()
+            .map(|[u, v]| {
+                [
+                    u0 + (u1 - u0) * u.clamp(0.0, 1.0),
+                    v0 + (v1 - v0) * v.clamp(0.0, 1.0),
+                ]
+            })
+            .collect()
+    }
+}
+
+//Copy a texture into the atlas with its edge pixels stretched out over the padding.
+fn blit(data: &mut [u8], width: u32, t: &Img, x: u32, y: u32, padding: u32) {
+    if t.width == 0 || t.height == 0 {
+        return;
+    }
+    let w = t.width + padding * 2;
+    let h = t.height + padding * 2;
+    for py in 0..h {
+        let sy = py.saturating_sub(padding).min(t.height - 1);
+        for px in 0..w {
+            let sx = px.saturating_sub(padding).min(t.width - 1);
+            let src = (sy * t.width + sx) as usize * 4;
+            let dst = ((y + py) * width + x + px) as usize * 4;
+            data[dst..dst + 4].copy_from_slice(&t.data[src..src + 4]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //Every pixel is its index so it is easy to tell where it came from.
+    fn numbered(width: u32, height: u32, tag: u8) -> Img {
+        Img {
+            width,
+            height,
+            data: (0..width * height)
+                .flat_map(|a| [tag, (a % 256) as u8, (a / 256) as u8, 255])
+                .collect(),
+        }
+    }
+
+    fn pixel(img: &Img, x: u32, y: u32) -> [u8; 4] {
+        let k = (y * img.width + x) as usize * 4;
+        img.data[k..k + 4].try_into().unwrap()
+    }
+
+    #[test]
+    fn regions_dont_overlap() {
+        let textures: Vec<_> = [(64, 64), (64, 64), (32, 32), (256, 16), (1, 1), (16, 16)]
+            .iter()
+            .enumerate()
+            .map(|(k, &(w, h))| numbered(w, h, k as u8))
+            .collect();
+        let padding = 2;
+        let atlas = Atlas::pack(&textures, padding);
+
+        let grow = |r: &Region| {
+            (
+                r.x - padding,
+                r.y - padding,
+                r.x + r.width + padding,
+                r.y + r.height + padding,
+            )
+        };
+        for (k, a) in atlas.regions.iter().enumerate() {
+            let (x0, y0, x1, y1) = grow(a);
+            assert!(x1 <= atlas.image.width && y1 <= atlas.image.height);
+            for b in atlas.regions[k + 1..].iter() {
+                let (bx0, by0, bx1, by1) = grow(b);
+                assert!(x1 <= bx0 || bx1 <= x0 || y1 <= by0 || by1 <= y0);
+            }
+        }
+
+        for (t, r) in textures.iter().zip(atlas.regions.iter()) {
+            assert_eq!((r.width, r.height), (t.width, t.height));
+            for y in 0..t.height {
+                for x in 0..t.width {
+                    assert_eq!(pixel(&atlas.image, r.x + x, r.y + y), pixel(t, x, y));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn padding_copies_edges() {
+        let textures = [numbered(4, 3, 1), numbered(5, 5, 2)];
+        let atlas = Atlas::pack(&textures, 2);
+        let r = atlas.regions[0];
+        let t = &textures[0];
+
+        for k in 1..=2 {
+            assert_eq!(pixel(&atlas.image, r.x - k, r.y + 1), pixel(t, 0, 1));
+            assert_eq!(pixel(&atlas.image, r.x + 3 + k, r.y + 2), pixel(t, 3, 2));
+            assert_eq!(pixel(&atlas.image, r.x + 2, r.y - k), pixel(t, 2, 0));
+            assert_eq!(pixel(&atlas.image, r.x + 2, r.y + 2 + k), pixel(t, 2, 2));
+            assert_eq!(pixel(&atlas.image, r.x - k, r.y - k), pixel(t, 0, 0));
+        }
+    }
+
+    #[test]
+    fn remaps_into_region() {
+        let textures = [numbered(8, 8, 1), numbered(16, 4, 2)];
+        let atlas = Atlas::pack(&textures, 1);
+        let r = atlas.regions[1];
+        let (w, h) = (atlas.image.width as f32, atlas.image.height as f32);
+
+        let uvs = atlas.remap(1, &[[0.0, 0.0], [1.0, 1.0], [0.5, 0.25], [-1.0, 2.0]]);
+        assert_eq!(uvs[0], [r.x as f32 / w, r.y as f32 / h]);
+        assert_eq!(uvs[1], [(r.x + 16) as f32 / w, (r.y + 4) as f32 / h]);
+        assert_eq!(uvs[2], [(r.x + 8) as f32 / w, (r.y + 1) as f32 / h]);
+        assert_eq!(uvs[3], [r.x as f32 / w, (r.y + 4) as f32 / h]);
+    }
+
+    #[test]
+    fn rejects_repeating_models() {
+        let textures = [numbered(8, 8, 1), numbered(8, 8, 2)];
+        let inside: &[[f32; 2]] = &[[0.0, 0.0], [1.0, 1.0], [1.0001, -0.0001]];
+        let outside: &[[f32; 2]] = &[[0.5, 0.5], [2.0, 0.5]];
+
+        assert!(Atlas::pack_models(&textures, &[inside, inside], 1).is_ok());
+        assert_eq!(
+            Atlas::pack_models(&textures, &[inside, outside], 1).unwrap_err(),
+            AtlasError::Repeats {
+                texture: 1,
+                uv: [2.0, 0.5]
+            }
+        );
+    }
+
+    #[test]
+    fn mipmaps_dont_mix_textures() {
+        let solid = |w: u32, h: u32, p: [u8; 4]| Img {
+            width: w,
+            height: h,
+            data: p.repeat((w * h) as usize),
+        };
+        let colours = [[255, 0, 0, 255], [0, 255, 0, 255], [0, 0, 255, 255]];
+        let textures = [
+            solid(13, 7, colours[0]),
+            solid(3, 20, colours[1]),
+            solid(1, 1, colours[2]),
+        ];
+        let padding = 4;
+        let atlas = Atlas::pack(&textures, padding);
+        for r in atlas.regions.iter() {
+            assert_eq!((r.x - padding) % 4, 0);
+            assert_eq!((r.y - padding) % 4, 0);
+        }
+
+        for filter in [mipmap::MipFilter::Box, mipmap::MipFilter::Lanczos] {
+            let levels = atlas.mipmaps(&textures, filter);
+            assert_eq!(levels.len(), 2);
+            for (k, level) in (1..).zip(levels.iter()) {
+                assert_eq!(level.width, atlas.image.width >> k);
+                //The whole padded region of each texture is its own colour.
+                for (r, c) in atlas.regions.iter().zip(colours) {
+                    let p = padding >> k;
+                    let x = r.x >> k;
+                    let y = r.y >> k;
+                    let w = (r.width >> k).max(1);
+                    let h = (r.height >> k).max(1);
+                    for py in y - p..y + h + p {
+                        for px in x - p..x + w + p {
+                            assert_eq!(pixel(level, px, py), c, "{:?} {}", filter, k);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    //The texture of this model has colours right up to its edges that used to bleed.
+    #[cfg(feature = "load")]
+    #[test]
+    fn packs_bundled_textures() {
+        let load = |bytes: &[u8]| load_glb(bytes).unwrap().gen(None).unwrap().1;
+        let textures = [
+            load(include_bytes!("../../assets/cat_texture_bleed.glb")),
+            load(include_bytes!("../../assets/grass.glb")),
+            load(include_bytes!("../../assets/road.glb")),
+        ];
+        let atlas = Atlas::pack(&textures, 2);
+
+        for (t, r) in textures.iter().zip(atlas.regions.iter()) {
+            for y in 0..t.height {
+                assert_eq!(pixel(&atlas.image, r.x - 1, r.y + y), pixel(t, 0, y));
+                assert_eq!(
+                    pixel(&atlas.image, r.x + r.width, r.y + y),
+                    pixel(t, t.width - 1, y)
+                );
+            }
+        }
+    }
+}
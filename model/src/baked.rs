@@ -0,0 +1,616 @@
+//! A compact format for models that were loaded and transformed ahead of time.
+//!
+//! Everything is little endian and padded to four bytes so the vertex data can be
+//! used straight out of the file without copying or parsing. The pixels of the texture
+//! are deflated, or stored as they are if that comes out smaller, so they are inflated
+//! into memory of their own when loaded. Layout:
+//!
+//! ```text
+//! "GLBK"  version  vertices  index_width  indices  ranges  width  height  spacing  sampling
+//! encoding
+//! matrix           16 f32
+//! positions        vertices * 3 f32
+//! normals          vertices * 3 f32
+//! tex_coords       vertices * 2 f32
+//! indices          indices * index_width bytes, padded
+//! ranges           ranges * (start, count, texture, alpha, cutoff) u32
+//! pixels           width * height rgba, deflated if encoding is 1
+//! ```
+//!
+//! Textures are stored at their own size. Instead of being scaled up to stay sharp,
+//! `sampling` says how the texture wants to be filtered, see [`Sampling::to_bits`]. A range with
+//! no texture has `u32::MAX` for it. `alpha` is 0 for opaque, 1 for mask and 2 for
+//! blend and `cutoff` is the f32 bits of the mask cutoff. Textures are premultiplied.
+use super::*;
+use mipmap::Sampling;
+
+const MAGIC: &[u8; 4] = b"GLBK";
+pub const VERSION: u32 = 5;
+const HEADER: usize = 4 + 10 * 4 + 16 * 4;
+
+const RAW: u32 = 0;
+const DEFLATE: u32 = 1;
+
+//How far the spacing of the grid can be from what a model was baked for.
+const SPACING_TOLERANCE: f32 = 1e-4;
+
+///Bytes with the alignment a baked model needs. Use [`include_baked!`] to make one.
+#[repr(C, align(4))]
+pub struct Aligned<B: ?Sized>(pub B);
+
+///Like `include_bytes!` but lined up so [`Baked::from_bytes`] can use it without copying.
+#[macro_export]
+macro_rules! include_baked {
+    ($path:expr) => {{
+        static BYTES: &$crate::baked::Aligned<[u8]> =
+            &$crate::baked::Aligned(*include_bytes!($path));
+        &BYTES.0
+    }};
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum BakeError {
+    ///Doesn't start with the magic bytes.
+    NotBaked,
+    Version(u32),
+    ///The file is shorter than its header says.
+    Truncated,
+    ///The bytes don't start on a four byte boundary.
+    Misaligned,
+    ///The pixels don't add up to the size of the texture.
+    BadPixels,
+    BadEncoding(u32),
+    ///Baked for a grid with a different spacing.
+    Spacing {
+        baked: f32,
+        grid: f32,
+    },
+    ///A filter this version doesn't know about.
+    BadSampling(u32),
+    BadAlphaMode(u32),
+    ///An index past the last vertex.
+    BadIndex(u32),
+    ///A range that goes past the last index, or the last vertex without indices.
+    BadRange(usize),
+    BigEndian,
+}
+
+impl std::fmt::Display for BakeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BakeError::NotBaked => write!(f, "not a baked model"),
+            BakeError::Version(a) => write!(
+                f,
+                "baked with version {} but this is version {}, bake it again",
+                a, VERSION
+            ),
+            BakeError::Truncated => write!(f, "the baked model is cut short"),
+            BakeError::Misaligned => write!(f, "the baked model isn't aligned to four bytes"),
+            BakeError::BadPixels => write!(f, "the pixels don't fill the texture"),
+            BakeError::BadEncoding(a) => write!(f, "unknown pixel encoding {}", a),
+            BakeError::Spacing { baked, grid } => write!(
+                f,
+                "baked for a grid spacing of {} but the grid is {}, run bake.sh again",
+                baked, grid
+            ),
+            BakeError::BadSampling(a) => write!(f, "unknown texture filter {:#x}", a),
+            BakeError::BadAlphaMode(a) => write!(f, "unknown alpha mode {}", a),
+            BakeError::BadIndex(a) => write!(f, "index {} is past the last vertex", a),
+            BakeError::BadRange(a) => write!(f, "range {} is past the last index", a),
+            BakeError::BigEndian => write!(f, "baked models only load on little endian machines"),
+        }
+    }
+}
+
+impl std::error::Error for BakeError {}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BakedIndices<'a> {
+    None,
+    U16(&'a [u16]),
+    U32(&'a [u32]),
+}
+
+///A baked model that borrows its vertices from the file. The pixels are decoded.
+#[derive(Debug, Clone)]
+pub struct Baked<'a> {
+    ///The grid spacing it was baked for.
+    pub spacing: f32,
+    pub matrix: cgmath::Matrix4<f32>,
+    pub positions: &'a [[f32; 3]],
+    pub normals: &'a [[f32; 3]],
+    pub tex_coords: &'a [[f32; 2]],
+    pub indices: BakedIndices<'a>,
+    pub ranges: Vec<DrawRange>,
+    pub width: u32,
+    pub height: u32,
+    pub sampling: Sampling,
+    pub pixels: std::borrow::Cow<'a, [u8]>,
+}
+
+fn put(out: &mut Vec<u8>, words: &[u32]) {
+    for w in words {
+        out.extend(w.to_le_bytes());
+    }
+}
+
+fn put_f32(out: &mut Vec<u8>, a: &[f32]) {
+    for w in a {
+        out.extend(w.to_le_bytes());
+    }
+}
+
+///Write a model and its texture in the baked format.
+pub fn bake(data: &ModelData, texture: &Img, spacing: f32, sampling: Sampling) -> Vec<u8> {
+    let (index_width, index_count) = match &data.indices {
+        None => (0, 0),
+        Some(Indices::U16(a)) => (2, a.len()),
+        Some(Indices::U32(a)) => (4, a.len()),
+    };
+
+    let deflated = miniz_oxide::deflate::compress_to_vec(&texture.data, 10);
+    let (encoding, pixels) = if deflated.len() < texture.data.len() {
+        (DEFLATE, &deflated[..])
+    } else {
+        (RAW, &texture.data[..])
+    };
+
+    let mut out =
+        Vec::with_capacity(HEADER + data.positions.len() * 32 + index_count * 4 + pixels.len());
+    out.extend(MAGIC);
+    put(
+        &mut out,
+        &[
+            VERSION,
+            data.positions.len() as u32,
+            index_width,
+            index_count as u32,
+            data.ranges.len() as u32,
+            texture.width,
+            texture.height,
+            spacing.to_bits(),
+            sampling.to_bits(),
+            encoding,
+        ],
+    );
+    let m: &[f32; 16] = data.matrix.as_ref();
+    put_f32(&mut out, m);
+
+    put_f32(&mut out, data.positions.as_flattened());
+    put_f32(&mut out, data.normals.as_flattened());
+    put_f32(&mut out, data.tex_coords.as_flattened());
+
+    match &data.indices {
+        None => {}
+        Some(Indices::U16(a)) => {
+            for i in a {
+                out.extend(i.to_le_bytes());
+            }
+            out.resize(out.len().next_multiple_of(4), 0);
+        }
+        Some(Indices::U32(a)) => put(&mut out, a),
+    }
+
+    for r in data.ranges.iter() {
+        let (alpha, cutoff) = match r.alpha {
+            AlphaMode::Opaque => (0, 0.0),
+            AlphaMode::Mask { cutoff } => (1, cutoff),
+            AlphaMode::Blend => (2, 0.0),
+        };
+        put(
+            &mut out,
+            &[
+                r.start as u32,
+                r.count as u32,
+                r.texture.map(|a| a as u32).unwrap_or(u32::MAX),
+                alpha,
+                cutoff.to_bits(),
+            ],
+        );
+    }
+
+    out.extend_from_slice(pixels);
+    out
+}
+
+//Hands out sections of the file one after the other.
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take<T: bytemuck::Pod>(&mut self, count: usize) -> Result<&'a [T], BakeError> {
+        let len = count
+            .checked_mul(std::mem::size_of::<T>())
+            .ok_or(BakeError::Truncated)?;
+        if len > self.bytes.len() {
+            return Err(BakeError::Truncated);
+        }
+        let (a, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        bytemuck::try_cast_slice(a).map_err(|_| BakeError::Misaligned)
+    }
+
+    fn skip(&mut self, len: usize) -> Result<(), BakeError> {
+        self.take::<u8>(len).map(|_| ())
+    }
+}
+
+impl<'a> Baked<'a> {
+    ///Read a baked model without copying its vertices. The bytes must be aligned to four
+    ///bytes, which [`include_baked!`] takes care of.
+    pub fn from_bytes(bytes: &'a [u8]) -> Result<Baked<'a>, BakeError> {
+        if cfg!(target_endian = "big") {
+            return Err(BakeError::BigEndian);
+        }
+        if bytes.len() < 4 || &bytes[..4] != MAGIC {
+            return Err(BakeError::NotBaked);
+        }
+
+        let mut r = Reader { bytes: &bytes[4..] };
+        let version = r.take::<u32>(1)?[0];
+        if version != VERSION {
+            return Err(BakeError::Version(version));
+        }
+        let &[vertices, index_width, index_count, ranges, width, height, spacing, sampling, encoding] =
+            r.take::<u32>(9)?
+        else {
+            unreachable!()
+        };
+        let sampling = Sampling::from_bits(sampling).ok_or(BakeError::BadSampling(sampling))?;
+
+        let matrix = cgmath::Matrix4::from(r.take::<[[f32; 4]; 4]>(1)?[0]);
+
+        let vertices = vertices as usize;
+        let positions = r.take(vertices)?;
+        let normals = r.take(vertices)?;
+        let tex_coords = r.take(vertices)?;
+
+        let index_count = index_count as usize;
+        let indices = match index_width {
+            0 => BakedIndices::None,
+            2 => {
+                let a = r.take(index_count)?;
+                //An odd number of 16 bit indices is padded to four bytes.
+                r.skip(index_count % 2 * 2)?;
+                BakedIndices::U16(a)
+            }
+            4 => BakedIndices::U32(r.take(index_count)?),
+            _ => return Err(BakeError::NotBaked),
+        };
+
+        let ranges = r
+            .take::<[u32; 5]>(ranges as usize)?
+            .iter()
+            .map(|&[start, count, texture, alpha, cutoff]| {
+                let alpha = match alpha {
+                    0 => AlphaMode::Opaque,
+                    1 => AlphaMode::Mask {
+                        cutoff: f32::from_bits(cutoff),
+                    },
+                    2 => AlphaMode::Blend,
+                    _ => return Err(BakeError::BadAlphaMode(alpha)),
+                };
+                Ok(DrawRange {
+                    start: start as usize,
+                    count: count as usize,
+                    texture: (texture != u32::MAX).then_some(texture as usize),
+                    alpha,
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        //Checked here so a damaged file can't reach the gpu.
+        let bad_index = match indices {
+            BakedIndices::None => None,
+            BakedIndices::U16(a) => a.iter().map(|&i| i as u32).find(|&i| i >= vertices as u32),
+            BakedIndices::U32(a) => a.iter().copied().find(|&i| i as usize >= vertices),
+        };
+        if let Some(i) = bad_index {
+            return Err(BakeError::BadIndex(i));
+        }
+        let drawable = match indices {
+            BakedIndices::None => vertices,
+            _ => index_count,
+        };
+        for (k, a) in ranges.iter().enumerate() {
+            if a.start
+                .checked_add(a.count)
+                .is_none_or(|end| end > drawable)
+            {
+                return Err(BakeError::BadRange(k));
+            }
+        }
+
+        let size = (width as usize)
+            .checked_mul(height as usize)
+            .and_then(|a| a.checked_mul(4))
+            .ok_or(BakeError::BadPixels)?;
+        let pixels = match encoding {
+            RAW => std::borrow::Cow::Borrowed(r.bytes),
+            DEFLATE => {
+                //Stop inflating as soon as it is bigger than the texture.
+                miniz_oxide::inflate::decompress_to_vec_with_limit(r.bytes, size)
+                    .map_err(|_| BakeError::BadPixels)?
+                    .into()
+            }
+            _ => return Err(BakeError::BadEncoding(encoding)),
+        };
+        if pixels.len() != size {
+            return Err(BakeError::BadPixels);
+        }
+
+        Ok(Baked {
+            spacing: f32::from_bits(spacing),
+            matrix,
+            positions,
+            normals,
+            tex_coords,
+            indices,
+            ranges,
+            width,
+            height,
+            sampling,
+            pixels,
+        })
+    }
+
+    ///Copy the model out of the file.
+    pub fn to_model(&self) -> ModelData {
+        ModelData {
+            matrix: self.matrix,
+            positions: self.positions.to_vec(),
+            indices: match self.indices {
+                BakedIndices::None => None,
+                BakedIndices::U16(a) => Some(Indices::U16(a.to_vec())),
+                BakedIndices::U32(a) => Some(Indices::U32(a.to_vec())),
+            },
+            normals: self.normals.to_vec(),
+            tex_coords: self.tex_coords.to_vec(),
+            ranges: self.ranges.clone(),
+        }
+    }
+
+    ///Make sure it was baked for a grid with this spacing.
+    pub fn check_spacing(&self, grid: f32) -> Result<(), BakeError> {
+        if (self.spacing - grid).abs() > SPACING_TOLERANCE * grid.abs().max(1.0) {
+            return Err(BakeError::Spacing {
+                baked: self.spacing,
+                grid,
+            });
+        }
+        Ok(())
+    }
+
+    ///The latest pass any of its ranges needs, like [`ModelData::pass`].
+    pub fn pass(&self) -> Pass {
+        ranges_pass(&self.ranges)
+    }
+
+    ///Like [`ModelData::cutoff`].
+    pub fn cutoff(&self) -> f32 {
+        mask_cutoff(&self.ranges)
+    }
+
+    pub fn texture(&self) -> Img {
+        Img {
+            width: self.width,
+            height: self.height,
+            data: self.pixels.to_vec(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn model(indices: Option<Indices>) -> ModelData {
+        ModelData {
+            matrix: cgmath::Matrix4::from_scale(2.0),
+            positions: vec![[0.0, 1.0, 2.0], [3.0, 4.0, 5.0], [6.0, 7.0, 8.0]],
+            indices,
+            normals: vec![[0.0, 0.0, 1.0]; 3],
+            tex_coords: vec![[0.0, 0.5], [1.0, 0.5], [0.5, 1.0]],
+            ranges: vec![DrawRange {
+                start: 0,
+                count: 3,
+                texture: Some(2),
+                alpha: AlphaMode::Mask { cutoff: 0.25 },
+            }],
+        }
+    }
+
+    //Copy into memory that is aligned the way include_baked! would.
+    fn aligned(bytes: &[u8]) -> Vec<u32> {
+        let mut words = vec![0u32; bytes.len().div_ceil(4)];
+        bytemuck::cast_slice_mut::<u32, u8>(&mut words)[..bytes.len()].copy_from_slice(bytes);
+        words
+    }
+
+    fn round_trip(data: &ModelData) {
+        let tex = Img {
+            width: 2,
+            height: 1,
+            data: vec![1, 2, 3, 4, 5, 6, 7, 8],
+        };
+        let sampling = Sampling {
+            filter: mipmap::Filter::Nearest,
+            mipmaps: Some(mipmap::MipFilter::Lanczos),
+        };
+        let bytes = bake(data, &tex, 31.25, sampling);
+        let words = aligned(&bytes);
+        let b = Baked::from_bytes(&bytemuck::cast_slice::<u32, u8>(&words)[..bytes.len()]).unwrap();
+
+        let back = b.to_model();
+        assert_eq!(back.matrix, data.matrix);
+        assert_eq!(back.positions, data.positions);
+        assert_eq!(back.normals, data.normals);
+        assert_eq!(back.tex_coords, data.tex_coords);
+        assert_eq!(back.indices, data.indices);
+        assert_eq!(back.ranges, data.ranges);
+        assert_eq!(b.cutoff(), 0.25);
+        assert_eq!(b.spacing, 31.25);
+        assert_eq!((b.width, b.height), (2, 1));
+        assert_eq!(b.sampling, sampling);
+        assert_eq!(b.texture().data, tex.data);
+    }
+
+    #[test]
+    fn round_trips() {
+        round_trip(&model(None));
+        round_trip(&model(Some(Indices::U16(vec![0, 1, 2]))));
+        round_trip(&model(Some(Indices::U32(vec![2, 1, 0]))));
+    }
+
+    #[test]
+    fn deflates_pixels() {
+        let mut data = vec![7; 300 * 4];
+        data.extend([1, 2, 3, 4]);
+        let tex = Img {
+            width: 301,
+            height: 1,
+            data,
+        };
+        let bytes = bake(&model(None), &tex, 1.0, Sampling::default());
+        assert!(bytes.len() < tex.data.len());
+        assert_eq!(
+            u32::from_le_bytes(bytes[40..44].try_into().unwrap()),
+            DEFLATE
+        );
+
+        let words = aligned(&bytes);
+        let b = Baked::from_bytes(&bytemuck::cast_slice::<u32, u8>(&words)[..bytes.len()]).unwrap();
+        assert_eq!(b.texture().data, tex.data);
+
+        let cut = &bytemuck::cast_slice::<u32, u8>(&words)[..bytes.len() - 3];
+        assert_eq!(Baked::from_bytes(cut).unwrap_err(), BakeError::BadPixels);
+    }
+
+    #[test]
+    fn noise_is_stored_raw() {
+        let tex = Img {
+            width: 2,
+            height: 1,
+            data: vec![1, 2, 3, 4, 5, 6, 7, 8],
+        };
+        let bytes = bake(&model(None), &tex, 1.0, Sampling::default());
+        assert_eq!(u32::from_le_bytes(bytes[40..44].try_into().unwrap()), RAW);
+        assert!(bytes.ends_with(&tex.data));
+    }
+
+    #[test]
+    fn checks_spacing() {
+        let bytes = bake(
+            &model(None),
+            &single_tex(),
+            1000.0 / 32.0,
+            Sampling::default(),
+        );
+        let words = aligned(&bytes);
+        let b = Baked::from_bytes(&bytemuck::cast_slice::<u32, u8>(&words)[..bytes.len()]).unwrap();
+        //Worked out a different way so it may not be bit for bit the same.
+        assert_eq!(b.check_spacing(31.25 + 1e-6), Ok(()));
+        assert_eq!(
+            b.check_spacing(32.0),
+            Err(BakeError::Spacing {
+                baked: 31.25,
+                grid: 32.0
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_bad_files() {
+        let bytes = bake(&model(None), &single_tex(), 1.0, Sampling::default());
+        let words = aligned(&bytes);
+        let bytes: &[u8] = &bytemuck::cast_slice::<u32, u8>(&words)[..bytes.len()];
+
+        assert_eq!(Baked::from_bytes(b"GLTF").unwrap_err(), BakeError::NotBaked);
+        assert_eq!(
+            Baked::from_bytes(&bytes[..20]).unwrap_err(),
+            BakeError::Truncated
+        );
+        assert_eq!(
+            Baked::from_bytes(&bytes[..bytes.len() - 1]).unwrap_err(),
+            BakeError::BadPixels
+        );
+
+        let mut old = words.clone();
+        old[1] = VERSION + 1;
+        assert_eq!(
+            Baked::from_bytes(bytemuck::cast_slice::<u32, u8>(&old)).unwrap_err(),
+            BakeError::Version(VERSION + 1)
+        );
+
+        let mut unknown = words.clone();
+        unknown[9] = 3;
+        assert_eq!(
+            Baked::from_bytes(bytemuck::cast_slice::<u32, u8>(&unknown)).unwrap_err(),
+            BakeError::BadSampling(3)
+        );
+
+        let mut unknown = words.clone();
+        unknown[10] = 5;
+        assert_eq!(
+            Baked::from_bytes(bytemuck::cast_slice::<u32, u8>(&unknown)).unwrap_err(),
+            BakeError::BadEncoding(5)
+        );
+
+        //The alpha mode of the only range, after the header and three vertices.
+        let mut unknown = words.clone();
+        unknown[(HEADER + 3 * 32) / 4 + 3] = 7;
+        assert_eq!(
+            Baked::from_bytes(bytemuck::cast_slice::<u32, u8>(&unknown)).unwrap_err(),
+            BakeError::BadAlphaMode(7)
+        );
+
+        let mut shifted = vec![0u8];
+        shifted.extend_from_slice(bytes);
+        let shifted = aligned(&shifted);
+        let off = &bytemuck::cast_slice::<u32, u8>(&shifted)[1..bytes.len() + 1];
+        assert_eq!(Baked::from_bytes(off).unwrap_err(), BakeError::Misaligned);
+    }
+
+    #[test]
+    fn rejects_bad_indices() {
+        let load = |data: &ModelData| {
+            let bytes = bake(data, &single_tex(), 1.0, Sampling::default());
+            let words = aligned(&bytes);
+            Baked::from_bytes(&bytemuck::cast_slice::<u32, u8>(&words)[..bytes.len()]).map(|_| ())
+        };
+
+        assert_eq!(
+            load(&model(Some(Indices::U16(vec![0, 1, 3])))),
+            Err(BakeError::BadIndex(3))
+        );
+        assert_eq!(
+            load(&model(Some(Indices::U32(vec![0, 1, u32::MAX])))),
+            Err(BakeError::BadIndex(u32::MAX))
+        );
+
+        let mut long = model(Some(Indices::U16(vec![0, 1, 2])));
+        long.ranges[0].count = 6;
+        assert_eq!(load(&long), Err(BakeError::BadRange(0)));
+
+        //Without indices the ranges are over the vertices.
+        let mut long = model(None);
+        long.ranges[0].start = 1;
+        assert_eq!(load(&long), Err(BakeError::BadRange(0)));
+
+        let mut huge = model(None);
+        huge.ranges.push(DrawRange {
+            start: u32::MAX as usize,
+            count: u32::MAX as usize,
+            ..huge.ranges[0]
+        });
+        assert_eq!(load(&huge), Err(BakeError::BadRange(1)));
+    }
+
+    #[test]
+    fn include_baked_is_aligned() {
+        let bytes = crate::include_baked!("../../assets/road.glb");
+        assert_eq!(bytes.as_ptr() as usize % 4, 0);
+        assert_eq!(bytes.len(), 1940);
+    }
+}
@@ -0,0 +1,140 @@
+//! Turns gltf files and images into baked models that load without any parsing.
+//!
+//! Models are moved into place with `gen_ext` the same way the game does when it loads
+//! a gltf, so the spacing has to match the grid of the game. A png, jpeg or ktx2 image
+//! becomes a texture with no vertices.
+//!
+//! ```text
+//! bake [--spacing S] [--alpha A] [--alpha-mode opaque|mask|blend]
+//!      [--filter nearest|linear] [--mipmaps none|box|lanczos] [--check] INPUT OUTPUT
+//! ```
+//!
+//! Textures are stored at their own size. Pixel art stays sharp with `--filter nearest`.
+//! `--filter` and `--mipmaps` are stored with the texture for the game to use.
+//! `--alpha-mode` replaces the alpha mode of every material, for models exported as
+//! masked that really need blending. Textures are stored premultiplied. `--check` leaves
+//! OUTPUT alone and fails if it isn't what INPUT bakes to.
+use model::baked;
+use model::mipmap::{Filter, MipFilter, Sampling};
+use std::path::Path;
+
+//Same as the grid of the game.
+const SPACING: f32 = 1000.0 / 32.0;
+
+struct Options {
+    spacing: f32,
+    alpha: Option<f64>,
+    alpha_mode: Option<model::AlphaMode>,
+    sampling: Sampling,
+    check: bool,
+}
+
+const IMAGES: &[&str] = &["png", "jpg", "jpeg", "ktx2"];
+
+fn bake(input: &Path, options: &Options) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let bytes = std::fs::read(input)?;
+
+    let image = input
+        .extension()
+        .and_then(|a| a.to_str())
+        .is_some_and(|a| IMAGES.contains(&a.to_ascii_lowercase().as_str()));
+    if image {
+        let mut texture = model::load_texture(&bytes)?;
+        model::premultiply(&mut texture);
+        let empty = model::ModelData {
+            matrix: cgmath::Matrix4::from_scale(1.0),
+            positions: vec![],
+            indices: None,
+            normals: vec![],
+            tex_coords: vec![],
+            ranges: vec![],
+        };
+        return Ok(baked::bake(&empty, &texture, 0.0, options.sampling));
+    }
+
+    let mut doop = model::load_gltf(&bytes, input.parent())?;
+    doop.alpha_mode = options.alpha_mode;
+    let (data, texture) = doop.gen_ext(options.spacing, options.alpha)?;
+    Ok(baked::bake(
+        &data,
+        &texture,
+        options.spacing,
+        options.sampling,
+    ))
+}
+
+fn main() {
+    let mut options = Options {
+        spacing: SPACING,
+        alpha: None,
+        alpha_mode: None,
+        sampling: Sampling::default(),
+        check: false,
+    };
+    let mut files = vec![];
+
+    let mut args = std::env::args().skip(1);
+    while let Some(a) = args.next() {
+        let mut number = |name: &str| {
+            args.next()
+                .and_then(|a| a.parse::<f64>().ok())
+                .unwrap_or_else(|| panic!("{} needs a number", name))
+        };
+        match a.as_str() {
+            "--spacing" => options.spacing = number("--spacing") as f32,
+            "--alpha" => options.alpha = Some(number("--alpha")),
+            "--check" => options.check = true,
+            "--alpha-mode" => {
+                options.alpha_mode = Some(match args.next().as_deref() {
+                    Some("opaque") => model::AlphaMode::Opaque,
+                    //The default cutoff of gltf.
+                    Some("mask") => model::AlphaMode::Mask { cutoff: 0.5 },
+                    Some("blend") => model::AlphaMode::Blend,
+                    _ => panic!("--alpha-mode needs opaque, mask or blend"),
+                })
+            }
+            "--filter" => {
+                options.sampling.filter = match args.next().as_deref() {
+                    Some("nearest") => Filter::Nearest,
+                    Some("linear") => Filter::Linear,
+                    _ => panic!("--filter needs nearest or linear"),
+                }
+            }
+            "--mipmaps" => {
+                options.sampling.mipmaps = match args.next().as_deref() {
+                    Some("none") => None,
+                    Some("box") => Some(MipFilter::Box),
+                    Some("lanczos") => Some(MipFilter::Lanczos),
+                    _ => panic!("--mipmaps needs none, box or lanczos"),
+                }
+            }
+            _ => files.push(a),
+        }
+    }
+
+    let [input, output] = &files[..] else {
+        eprintln!(
+            "usage: bake [--spacing S] [--alpha A] \
+             [--alpha-mode opaque|mask|blend] [--filter nearest|linear] [--mipmaps none|box|lanczos] \
+             [--check] INPUT OUTPUT"
+        );
+        std::process::exit(2);
+    };
+
+    match bake(Path::new(input), &options) {
+        Ok(bytes) if options.check => {
+            if std::fs::read(output).ok().as_deref() != Some(&bytes[..]) {
+                eprintln!("{} is out of date with {}, run bake.sh", output, input);
+                std::process::exit(1);
+            }
+        }
+        Ok(bytes) => {
+            std::fs::write(output, &bytes).expect("could not write the output");
+            println!("{} -> {} ({} bytes)", input, output, bytes.len());
+        }
+        Err(e) => {
+            eprintln!("{}: {}", input, e);
+            std::process::exit(1);
+        }
+    }
+}
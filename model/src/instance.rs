@@ -0,0 +1,177 @@
+//! Per-instance data for drawing many copies of a model in one call.
+//!
+//! Every instance is its model matrix followed by its flags and alpha, all as f32 so
+//! the buffer can be uploaded as it is:
+//!
+//! ```text
+//! matrix   16 f32, column major
+//! flags    1 f32 holding the bits of [`Flags`]
+//! alpha    1 f32, the whole instance is faded by it
+//! ```
+use cgmath::Matrix4;
+
+///Floats per instance.
+pub const FLOATS: usize = 18;
+///Bytes from one instance to the next.
+pub const STRIDE: usize = FLOATS * 4;
+///Where the flags start in an instance, in bytes.
+pub const FLAGS_OFFSET: usize = 16 * 4;
+///Where the alpha starts in an instance, in bytes.
+pub const ALPHA_OFFSET: usize = 17 * 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Flags {
+    pub grayscale: bool,
+    ///Shade by the normals instead of drawing the texture as it is.
+    pub lighting: bool,
+}
+
+impl Flags {
+    pub const GRAYSCALE: u32 = 1;
+    pub const LIGHTING: u32 = 2;
+
+    pub fn bits(self) -> u32 {
+        let mut a = 0;
+        if self.grayscale {
+            a |= Flags::GRAYSCALE;
+        }
+        if self.lighting {
+            a |= Flags::LIGHTING;
+        }
+        a
+    }
+
+    pub fn from_bits(a: u32) -> Flags {
+        Flags {
+            grayscale: a & Flags::GRAYSCALE != 0,
+            lighting: a & Flags::LIGHTING != 0,
+        }
+    }
+}
+
+///A list of instances that is built up every frame and uploaded in one go.
+#[derive(Debug, Clone, Default)]
+pub struct Instances {
+    data: Vec<f32>,
+}
+
+impl Instances {
+    pub fn new() -> Instances {
+        Instances::default()
+    }
+
+    ///Forget every instance but keep the memory for the next frame.
+    pub fn clear(&mut self) {
+        self.data.clear();
+    }
+
+    pub fn push(&mut self, matrix: &Matrix4<f32>, flags: Flags) {
+        self.push_faded(matrix, flags, 1.0);
+    }
+
+    ///An instance that is only partly drawn, from 0 for not at all to 1 for fully.
+    pub fn push_faded(&mut self, matrix: &Matrix4<f32>, flags: Flags, alpha: f32) {
+        let m: &[f32; 16] = matrix.as_ref();
+        self.data.extend_from_slice(m);
+        //Small integers are exact as floats.
+        self.data.push(flags.bits() as f32);
+        self.data.push(alpha);
+    }
+
+    ///An instance moved to `pos` and scaled by `scale` around its origin.
+    pub fn push_at(&mut self, pos: [f32; 3], scale: f32, flags: Flags) {
+        let m = Matrix4::from_translation(pos.into()) * Matrix4::from_scale(scale);
+        self.push(&m, flags);
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.len() / FLOATS
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    pub fn matrix(&self, k: usize) -> Matrix4<f32> {
+        let m: &[[f32; 4]; 4] = bytemuck::from_bytes(bytemuck::cast_slice(
+            &self.data[k * FLOATS..k * FLOATS + 16],
+        ));
+        (*m).into()
+    }
+
+    pub fn flags(&self, k: usize) -> Flags {
+        Flags::from_bits(self.data[k * FLOATS + 16] as u32)
+    }
+
+    pub fn alpha(&self, k: usize) -> f32 {
+        self.data[k * FLOATS + 17]
+    }
+
+    pub fn as_slice(&self) -> &[f32] {
+        &self.data
+    }
+
+    ///The bytes to upload to the instance buffer.
+    pub fn as_bytes(&self) -> &[u8] {
+        bytemuck::cast_slice(&self.data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::Transform;
+
+    #[test]
+    fn lays_out_instances() {
+        let mut a = Instances::new();
+        assert!(a.is_empty());
+        let m = Matrix4::from_translation([1.0, 2.0, 3.0].into());
+        let flags = Flags {
+            grayscale: true,
+            lighting: false,
+        };
+        a.push(&m, flags);
+        a.push(&Matrix4::from_scale(2.0), Flags::default());
+
+        assert_eq!(a.len(), 2);
+        assert_eq!(a.as_slice().len(), 2 * FLOATS);
+        assert_eq!(a.as_bytes().len(), 2 * STRIDE);
+        //Column major, the translation is in the last column.
+        assert_eq!(a.as_slice()[12..16], [1.0, 2.0, 3.0, 1.0]);
+        assert_eq!(a.as_slice()[FLAGS_OFFSET / 4], 1.0);
+        assert_eq!(a.matrix(0), m);
+        assert_eq!(a.flags(0), flags);
+        assert_eq!(a.flags(1), Flags::default());
+        assert_eq!(a.alpha(0), 1.0);
+
+        a.push_faded(&m, flags, 0.25);
+        assert_eq!(a.as_slice()[2 * FLOATS + ALPHA_OFFSET / 4], 0.25);
+        assert_eq!(a.alpha(2), 0.25);
+        assert_eq!(a.flags(2), flags);
+
+        a.clear();
+        assert!(a.is_empty());
+    }
+
+    #[test]
+    fn push_at_moves_and_scales() {
+        let mut a = Instances::new();
+        a.push_at([10.0, 20.0, -1.0], 0.5, Flags::default());
+        let p = a.matrix(0).transform_point([2.0, 4.0, 0.0].into());
+        assert_eq!(p, [11.0, 22.0, -1.0].into());
+    }
+
+    #[test]
+    fn flag_bits_round_trip() {
+        for grayscale in [false, true] {
+            for lighting in [false, true] {
+                let f = Flags {
+                    grayscale,
+                    lighting,
+                };
+                assert_eq!(Flags::from_bits(f.bits()), f);
+            }
+        }
+    }
+}
@@ -0,0 +1,219 @@
+//! Reads uncompressed KTX2 textures, with any mip levels they come with.
+//!
+//! Only plain 8 bit RGB and RGBA 2D textures are supported. Block compressed and
+//! supercompressed files need a transcoder which would be too big for the game.
+use super::*;
+
+const IDENTIFIER: [u8; 12] = [
+    0xAB, b'K', b'T', b'X', b' ', b'2', b'0', 0xBB, b'\r', b'\n', 0x1A, b'\n',
+];
+//Up to the end of the level index, which starts at byte 80.
+const HEADER: usize = 80;
+
+//Vulkan formats.
+const R8G8B8_UNORM: u32 = 23;
+const R8G8B8_SRGB: u32 = 29;
+const R8G8B8A8_UNORM: u32 = 37;
+const R8G8B8A8_SRGB: u32 = 43;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Ktx2Error {
+    ///Doesn't start with the KTX2 identifier.
+    NotKtx2,
+    ///The file is shorter than its header says.
+    Truncated,
+    ///A `VkFormat` other than 8 bit RGB or RGBA.
+    Format(u32),
+    Supercompressed(u32),
+    ///3D textures, cube maps and arrays.
+    NotFlat,
+    ///The size of a level doesn't fit in memory.
+    TooBig,
+}
+
+impl std::fmt::Display for Ktx2Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Ktx2Error::NotKtx2 => write!(f, "not a ktx2 file"),
+            Ktx2Error::Truncated => write!(f, "the ktx2 file is cut short"),
+            Ktx2Error::Format(a) => write!(f, "vulkan format {} isn't 8 bit rgb or rgba", a),
+            Ktx2Error::Supercompressed(a) => {
+                write!(f, "supercompression scheme {} isn't supported", a)
+            }
+            Ktx2Error::NotFlat => write!(f, "only plain 2d textures are supported"),
+            Ktx2Error::TooBig => write!(f, "the ktx2 texture is too big"),
+        }
+    }
+}
+
+impl std::error::Error for Ktx2Error {}
+
+pub fn is_ktx2(bytes: &[u8]) -> bool {
+    bytes.starts_with(&IDENTIFIER)
+}
+
+fn word(bytes: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap())
+}
+
+fn long(bytes: &[u8], at: usize) -> Result<usize, Ktx2Error> {
+    let a = u64::from_le_bytes(bytes[at..at + 8].try_into().unwrap());
+    a.try_into().map_err(|_| Ktx2Error::Truncated)
+}
+
+///The base texture followed by the mip levels stored in the file, largest first.
+pub fn load(bytes: &[u8]) -> Result<Vec<Img>, Ktx2Error> {
+    if !is_ktx2(bytes) {
+        return Err(Ktx2Error::NotKtx2);
+    }
+    if bytes.len() < HEADER {
+        return Err(Ktx2Error::Truncated);
+    }
+
+    let format = word(bytes, 12);
+    let channels = match format {
+        R8G8B8_UNORM | R8G8B8_SRGB => 3,
+        R8G8B8A8_UNORM | R8G8B8A8_SRGB => 4,
+        _ => return Err(Ktx2Error::Format(format)),
+    };
+    let width = word(bytes, 20);
+    let height = word(bytes, 24);
+    let depth = word(bytes, 28);
+    let layers = word(bytes, 32);
+    let faces = word(bytes, 36);
+    if height == 0 || depth != 0 || layers != 0 || faces != 1 {
+        return Err(Ktx2Error::NotFlat);
+    }
+    //Zero means the loader should make the mip levels itself.
+    let levels = word(bytes, 40).max(1) as usize;
+    let scheme = word(bytes, 44);
+    if scheme != 0 {
+        return Err(Ktx2Error::Supercompressed(scheme));
+    }
+
+    let index = levels.checked_mul(24).ok_or(Ktx2Error::Truncated)?;
+    if bytes.len() - HEADER < index {
+        return Err(Ktx2Error::Truncated);
+    }
+    (0..levels)
+        .map(|k| {
+            //Past 32 levels every side is down to 1.
+            let w = width.checked_shr(k as u32).unwrap_or(0).max(1);
+            let h = height.checked_shr(k as u32).unwrap_or(0).max(1);
+            let at = HEADER + k * 24;
+            let offset = long(bytes, at)?;
+            let len = long(bytes, at + 8)?;
+            //Also has to fit once rgb is widened to rgba.
+            let area = (w as usize)
+                .checked_mul(h as usize)
+                .filter(|a| a.checked_mul(4).is_some())
+                .ok_or(Ktx2Error::TooBig)?;
+            let size = area * channels;
+            let pixels = offset
+                .checked_add(len)
+                .and_then(|end| bytes.get(offset..end))
+                .filter(|a| a.len() >= size)
+                .ok_or(Ktx2Error::Truncated)?;
+
+            let data = if channels == 4 {
+                pixels[..size].to_vec()
+            } else {
+                pixels[..size]
+                    .chunks_exact(3)
+                    .flat_map(|p| [p[0], p[1], p[2], 255])
+                    .collect()
+            };
+            Ok(Img {
+                width: w,
+                height: h,
+                data,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //A file with the given levels stored one after the other after the index.
+    fn file(format: u32, width: u32, height: u32, levels: &[Vec<u8>]) -> Vec<u8> {
+        let mut out = IDENTIFIER.to_vec();
+        for w in [format, 1, width, height, 0, 0, 1, levels.len() as u32, 0] {
+            out.extend(w.to_le_bytes());
+        }
+        //Data format and key value descriptors, unused.
+        out.resize(HEADER, 0);
+        let mut offset = HEADER + levels.len() * 24;
+        for l in levels {
+            out.extend((offset as u64).to_le_bytes());
+            out.extend((l.len() as u64).to_le_bytes());
+            out.extend((l.len() as u64).to_le_bytes());
+            offset += l.len();
+        }
+        for l in levels {
+            out.extend(l);
+        }
+        out
+    }
+
+    #[test]
+    fn reads_rgba_levels() {
+        let base: Vec<u8> = (0..2 * 2 * 4).collect();
+        let bytes = file(R8G8B8A8_SRGB, 2, 2, &[base.clone(), vec![9, 8, 7, 6]]);
+        let levels = load(&bytes).unwrap();
+        assert_eq!(levels.len(), 2);
+        assert_eq!((levels[0].width, levels[0].height), (2, 2));
+        assert_eq!(levels[0].data, base);
+        assert_eq!((levels[1].width, levels[1].height), (1, 1));
+        assert_eq!(levels[1].data, [9, 8, 7, 6]);
+    }
+
+    #[test]
+    fn rgb_becomes_opaque() {
+        let bytes = file(R8G8B8_UNORM, 2, 1, &[vec![1, 2, 3, 4, 5, 6]]);
+        let levels = load(&bytes).unwrap();
+        assert_eq!(levels[0].data, [1, 2, 3, 255, 4, 5, 6, 255]);
+    }
+
+    #[test]
+    fn rejects_unsupported() {
+        let bytes = file(R8G8B8A8_UNORM, 2, 1, &[vec![0; 8]]);
+        assert_eq!(load(b"\x89PNG").unwrap_err(), Ktx2Error::NotKtx2);
+        assert_eq!(load(&bytes[..40]).unwrap_err(), Ktx2Error::Truncated);
+        assert_eq!(
+            load(&bytes[..bytes.len() - 1]).unwrap_err(),
+            Ktx2Error::Truncated
+        );
+
+        //BC7.
+        let bc7 = file(145, 4, 4, &[vec![0; 16]]);
+        assert_eq!(load(&bc7).unwrap_err(), Ktx2Error::Format(145));
+
+        let mut zstd = bytes.clone();
+        zstd[44] = 2;
+        assert_eq!(load(&zstd).unwrap_err(), Ktx2Error::Supercompressed(2));
+
+        let mut cube = bytes;
+        cube[36] = 6;
+        assert_eq!(load(&cube).unwrap_err(), Ktx2Error::NotFlat);
+    }
+
+    #[test]
+    fn huge_sizes_are_errors() {
+        let bytes = file(R8G8B8A8_UNORM, u32::MAX, u32::MAX, &[vec![0; 4]]);
+        assert_eq!(load(&bytes).unwrap_err(), Ktx2Error::TooBig);
+
+        let mut many = file(R8G8B8A8_UNORM, 1, 1, &[vec![0; 4]]);
+        many[40..44].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert_eq!(load(&many).unwrap_err(), Ktx2Error::Truncated);
+    }
+
+    #[test]
+    fn more_levels_than_bits() {
+        let bytes = file(R8G8B8A8_UNORM, 2, 2, &vec![vec![0; 16]; 40]);
+        let levels = load(&bytes).unwrap();
+        assert_eq!(levels.len(), 40);
+        assert_eq!((levels[39].width, levels[39].height), (1, 1));
+    }
+}
@@ -1,29 +1,283 @@
+pub mod atlas;
+pub mod baked;
+pub mod instance;
+pub mod ktx2;
 pub mod matrix;
-use cgmath::Transform;
+pub mod mipmap;
+pub mod popup;
+#[cfg(feature = "load")]
+pub mod skin;
+pub mod text;
+use cgmath::SquareMatrix;
+#[cfg(feature = "load")]
+use cgmath::{InnerSpace, Matrix, Transform};
+#[cfg(feature = "load")]
 use gltf::image::Source;
-use image::imageops::FilterType;
+#[cfg(feature = "load")]
+use std::path::Path;
 
+///Something that went wrong loading a model.
+#[derive(Debug)]
+pub enum LoadError {
+    #[cfg(feature = "load")]
+    Gltf(gltf::Error),
+    ///An image could not be decoded.
+    #[cfg(feature = "load")]
+    Image {
+        image: usize,
+        error: image::ImageError,
+    },
+    ///An image refers to a file but there is no directory to look for it in.
+    ExternalImage {
+        image: usize,
+        uri: String,
+    },
+    ///A KTX2 image could not be read.
+    Ktx2 {
+        image: usize,
+        error: ktx2::Ktx2Error,
+    },
+    ///An image file could not be read.
+    Io {
+        uri: String,
+        error: std::io::Error,
+    },
+    ///An image is embedded in a data URI that isn't base64.
+    BadDataUri {
+        image: usize,
+    },
+    ///Only triangles can be drawn.
+    #[cfg(feature = "load")]
+    UnsupportedMode {
+        mesh: usize,
+        primitive: usize,
+        mode: gltf::mesh::Mode,
+    },
+    NoPositions {
+        mesh: usize,
+        primitive: usize,
+    },
+    ///An index points past the end of the vertices.
+    IndexOutOfRange {
+        mesh: usize,
+        primitive: usize,
+        index: u32,
+    },
+    ///Same as above for a part that was put together by hand.
+    PartIndexOutOfRange {
+        part: usize,
+        index: u32,
+    },
+    ///More vertices than a 32 bit index can address.
+    TooManyVertices(usize),
+    ///None of the nodes have a mesh.
+    NoMeshes,
+    ///There is no skin with this index.
+    NoSkin(usize),
+    ///A skin is missing inverse bind matrices or its joints loop around.
+    BadSkin(usize),
+    ///An animation channel has no keyframes or they don't line up.
+    BadAnimation {
+        animation: usize,
+        channel: usize,
+    },
+    ///A skinned mesh doesn't say which joints move its vertices.
+    NoWeights {
+        mesh: usize,
+        primitive: usize,
+    },
+    ///A skinned mesh is moved by a joint its skin doesn't have.
+    JointOutOfRange {
+        mesh: usize,
+        primitive: usize,
+        joint: u16,
+    },
+}
+
+impl std::fmt::Display for LoadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            #[cfg(feature = "load")]
+            LoadError::Gltf(e) => write!(f, "invalid gltf: {}", e),
+            #[cfg(feature = "load")]
+            LoadError::Image { image, error } => {
+                write!(f, "could not decode image {}: {}", image, error)
+            }
+            LoadError::ExternalImage { image, uri } => write!(
+                f,
+                "image {} is in an external file {:?} but no base directory was given",
+                image, uri
+            ),
+            LoadError::Ktx2 { image, error } => {
+                write!(f, "could not read ktx2 image {}: {}", image, error)
+            }
+            LoadError::Io { uri, error } => write!(f, "could not read {:?}: {}", uri, error),
+            LoadError::BadDataUri { image } => {
+                write!(f, "image {} has a data uri that isn't base64", image)
+            }
+            #[cfg(feature = "load")]
+            LoadError::UnsupportedMode {
+                mesh,
+                primitive,
+                mode,
+            } => write!(
+                f,
+                "primitive {} of mesh {} is drawn as {:?}, only triangles are supported",
+                primitive, mesh, mode
+            ),
+            LoadError::NoPositions { mesh, primitive } => {
+                write!(
+                    f,
+                    "primitive {} of mesh {} has no positions",
+                    primitive, mesh
+                )
+            }
+            LoadError::IndexOutOfRange {
+                mesh,
+                primitive,
+                index,
+            } => write!(
+                f,
+                "primitive {} of mesh {} uses vertex {} which doesn't exist",
+                primitive, mesh, index
+            ),
+            LoadError::PartIndexOutOfRange { part, index } => {
+                write!(f, "part {} uses vertex {} which doesn't exist", part, index)
+            }
+            LoadError::TooManyVertices(a) => write!(f, "{} vertices are too many to index", a),
+            LoadError::NoMeshes => write!(f, "there is nothing to draw"),
+            LoadError::NoSkin(a) => write!(f, "there is no skin {}", a),
+            LoadError::BadSkin(a) => write!(f, "the joints of skin {} don't form a tree", a),
+            LoadError::BadAnimation { animation, channel } => write!(
+                f,
+                "channel {} of animation {} has keyframes that don't line up",
+                channel, animation
+            ),
+            LoadError::NoWeights { mesh, primitive } => write!(
+                f,
+                "primitive {} of mesh {} is skinned but has no joint weights",
+                primitive, mesh
+            ),
+            LoadError::JointOutOfRange {
+                mesh,
+                primitive,
+                joint,
+            } => write!(
+                f,
+                "primitive {} of mesh {} is moved by joint {} which its skin doesn't have",
+                primitive, mesh, joint
+            ),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+#[cfg(feature = "load")]
+impl From<gltf::Error> for LoadError {
+    fn from(a: gltf::Error) -> Self {
+        LoadError::Gltf(a)
+    }
+}
+
+#[cfg(feature = "load")]
 #[derive(Debug)]
 pub struct Doop {
     pub document: gltf::Document,
     pub buffers: Vec<gltf::buffer::Data>,
-    pub images: Vec<gltf::image::Data>,
+    pub images: Vec<image::RgbaImage>,
+    ///Used instead of the alpha mode of every material when set.
+    pub alpha_mode: Option<AlphaMode>,
 }
 
+#[cfg(feature = "load")]
 //TODO wouldnt it be amazing if this was a const function????
-pub fn load_glb(bytes: &[u8]) -> Doop {
+pub fn load_glb(bytes: &[u8]) -> Result<Doop, LoadError> {
     //Use https://www.gltfeditor.com/ also
     //Use https://gltf.report/ to compress it to the binary format!!!!
 
     //TODO discard normal verticies if not used???
 
-    let (document, buffers, images) = gltf::import_slice(bytes).unwrap();
-    //log!(format!("{:?}", document));
-    Doop {
+    load_gltf(bytes, None)
+}
+
+///Load a .glb or .gltf file. Files it refers to are looked up relative to `base`.
+///Images embedded in the file or in data URIs work without one.
+#[cfg(feature = "load")]
+pub fn load_gltf(bytes: &[u8], base: Option<&Path>) -> Result<Doop, LoadError> {
+    let gltf::Gltf { document, blob } = gltf::Gltf::from_slice(bytes)?;
+    let buffers = gltf::import_buffers(&document, base, blob)?;
+
+    let images = document
+        .images()
+        .map(|img| {
+            let index = img.index();
+            let encoded = match img.source() {
+                Source::View { view, .. } => {
+                    let parent_buffer_data = &buffers[view.buffer().index()].0;
+                    parent_buffer_data[view.offset()..view.offset() + view.length()].to_vec()
+                }
+                Source::Uri { uri, .. } => {
+                    if let Some(rest) = uri.strip_prefix("data:") {
+                        let (_, data) = rest
+                            .split_once(";base64,")
+                            .ok_or(LoadError::BadDataUri { image: index })?;
+                        base64_decode(data).ok_or(LoadError::BadDataUri { image: index })?
+                    } else if let Some(base) = base {
+                        std::fs::read(base.join(uri)).map_err(|error| LoadError::Io {
+                            uri: uri.to_string(),
+                            error,
+                        })?
+                    } else {
+                        return Err(LoadError::ExternalImage {
+                            image: index,
+                            uri: uri.to_string(),
+                        });
+                    }
+                }
+            };
+
+            let Img {
+                width,
+                height,
+                data,
+            } = decode_image(index, &encoded)?;
+            Ok(image::RgbaImage::from_raw(width, height, data).unwrap())
+        })
+        .collect::<Result<_, _>>()?;
+
+    Ok(Doop {
         document,
         buffers,
         images,
+        alpha_mode: None,
+    })
+}
+
+#[cfg(feature = "load")]
+//Standard base64 as used in data URIs. Padding is optional.
+fn base64_decode(a: &str) -> Option<Vec<u8>> {
+    let mut res = Vec::with_capacity(a.len() * 3 / 4);
+    let mut acc = 0u32;
+    let mut bits = 0;
+    for c in a.bytes().take_while(|&c| c != b'=') {
+        let v = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            _ => return None,
+        } as u32;
+        acc = acc << 6 | v;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            res.push((acc >> bits) as u8);
+            acc &= (1 << bits) - 1;
+        }
     }
+    Some(res)
 }
 
 #[derive(Debug)]
@@ -33,6 +287,31 @@ pub struct Img {
     pub data: Vec<u8>,
 }
 
+///Multiply the colour of every pixel by its alpha. Textures are kept premultiplied so
+///filtering and blending don't pick up the colour of see-through pixels.
+pub fn premultiply(img: &mut Img) {
+    for p in img.data.chunks_exact_mut(4) {
+        let a = p[3] as u32;
+        for c in p[..3].iter_mut() {
+            *c = ((*c as u32 * a + 127) / 255) as u8;
+        }
+    }
+}
+
+#[cfg(feature = "load")]
+//Opaque textures lose their alpha and masked ones are cut to fully drawn or not at all.
+fn apply_alpha_mode(img: &mut Img, mode: AlphaMode) {
+    let alpha = |a: u8| match mode {
+        AlphaMode::Opaque => 255,
+        AlphaMode::Mask { cutoff } if a as f32 / 255.0 >= cutoff => 255,
+        AlphaMode::Mask { .. } => 0,
+        AlphaMode::Blend => a,
+    };
+    for p in img.data.chunks_exact_mut(4) {
+        p[3] = alpha(p[3]);
+    }
+}
+
 pub fn single_tex() -> Img {
     Img {
         width: 1,
@@ -41,20 +320,374 @@ pub fn single_tex() -> Img {
     }
 }
 
+#[cfg(feature = "load")]
+//A one pixel texture for primitives that only have a colour.
+fn colour_tex(c: [f32; 4]) -> Img {
+    Img {
+        width: 1,
+        height: 1,
+        data: c
+            .iter()
+            .map(|a| (a.clamp(0.0, 1.0) * 255.0) as u8)
+            .collect(),
+    }
+}
+
+///Vertex indices. 16 bit ones are used whenever every index fits.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Indices {
+    U16(Vec<u16>),
+    U32(Vec<u32>),
+}
+
+impl Indices {
+    pub fn new(a: Vec<u32>) -> Indices {
+        if a.iter().all(|&x| x <= u16::MAX as u32) {
+            Indices::U16(a.into_iter().map(|x| x as u16).collect())
+        } else {
+            Indices::U32(a)
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            Indices::U16(a) => a.len(),
+            Indices::U32(a) => a.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn to_u32(&self) -> Vec<u32> {
+        match self {
+            Indices::U16(a) => a.iter().map(|&x| x as u32).collect(),
+            Indices::U32(a) => a.clone(),
+        }
+    }
+}
+
+///How the alpha of a texture is used, from the `alphaMode` of a gltf material.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum AlphaMode {
+    ///Alpha is ignored.
+    #[default]
+    Opaque,
+    ///Pixels are drawn fully when their alpha reaches `cutoff` and not at all otherwise.
+    Mask { cutoff: f32 },
+    ///Blended with whatever is behind it.
+    Blend,
+}
+
+///Which group of draws a model belongs in. Passes are drawn in this order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Pass {
+    Opaque,
+    ///Writes depth like opaque geometry but needs blending so cut out pixels vanish.
+    Mask,
+    ///Drawn last without writing depth so it doesn't hide what is behind it.
+    Blend,
+}
+
+impl AlphaMode {
+    pub fn pass(self) -> Pass {
+        match self {
+            AlphaMode::Opaque => Pass::Opaque,
+            AlphaMode::Mask { .. } => Pass::Mask,
+            AlphaMode::Blend => Pass::Blend,
+        }
+    }
+}
+
+///The latest pass any of the ranges needs.
+pub fn ranges_pass(ranges: &[DrawRange]) -> Pass {
+    ranges
+        .iter()
+        .map(|a| a.alpha.pass())
+        .max()
+        .unwrap_or(Pass::Opaque)
+}
+
+///The highest cutoff of the masked ranges, or 0 if none are masked so nothing is cut.
+pub fn mask_cutoff(ranges: &[DrawRange]) -> f32 {
+    ranges
+        .iter()
+        .filter_map(|a| match a.alpha {
+            AlphaMode::Mask { cutoff } => Some(cutoff),
+            _ => None,
+        })
+        .fold(0.0, f32::max)
+}
+
+///Indices that are drawn together with the same texture.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DrawRange {
+    pub start: usize,
+    pub count: usize,
+    ///Index into the textures of the scene.
+    pub texture: Option<usize>,
+    pub alpha: AlphaMode,
+}
+
+///Part of a model that is drawn on its own, with only the vertices it uses.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Chunk {
+    pub positions: Vec<[f32; 3]>,
+    pub normals: Vec<[f32; 3]>,
+    pub tex_coords: Vec<[f32; 2]>,
+    pub indices: Vec<u16>,
+    pub texture: Option<usize>,
+    pub alpha: AlphaMode,
+}
+
 #[derive(Debug)]
 pub struct ModelData {
     pub matrix: cgmath::Matrix4<f32>,
     pub positions: Vec<[f32; 3]>,
-    pub indices: Option<Vec<u16>>,
+    pub indices: Option<Indices>,
     pub normals: Vec<[f32; 3]>,
     pub tex_coords: Vec<[f32; 2]>,
+    ///One range per primitive the model was made from.
+    pub ranges: Vec<DrawRange>,
 }
 
+impl ModelData {
+    ///Every triangle gets its own vertices so no index buffer is needed.
+    ///Used for meshes that are too big for 16 bit indices.
+    pub fn unindexed(&self) -> ModelData {
+        let Some(indices) = &self.indices else {
+            return ModelData {
+                matrix: self.matrix,
+                positions: self.positions.clone(),
+                indices: None,
+                normals: self.normals.clone(),
+                tex_coords: self.tex_coords.clone(),
+                ranges: self.ranges.clone(),
+            };
+        };
+        let indices = indices.to_u32();
+        let pick = |a: &[[f32; 3]]| indices.iter().map(|&i| a[i as usize]).collect();
+
+        ModelData {
+            matrix: self.matrix,
+            positions: pick(&self.positions),
+            indices: None,
+            normals: pick(&self.normals),
+            tex_coords: indices
+                .iter()
+                .map(|&i| self.tex_coords[i as usize])
+                .collect(),
+            //Indices become vertices one to one so the ranges stay the same.
+            ranges: self.ranges.clone(),
+        }
+    }
+
+    ///One chunk per range with 16 bit indices, for drawing with index buffers that can't
+    ///take bigger ones. Ranges that use more vertices than that are split further.
+    pub fn chunks(&self) -> Vec<Chunk> {
+        let all = self.indices.as_ref().map(|a| a.to_u32());
+        let mut chunks = vec![];
+        for r in self.ranges.iter() {
+            let indices: Vec<u32> = match &all {
+                Some(a) => a[r.start..r.start + r.count].to_vec(),
+                None => (r.start as u32..(r.start + r.count) as u32).collect(),
+            };
+
+            let empty = Chunk {
+                texture: r.texture,
+                alpha: r.alpha,
+                ..Chunk::default()
+            };
+            let mut chunk = empty.clone();
+            //Where each vertex of the model is in the current chunk.
+            let mut local = std::collections::HashMap::new();
+            for tri in indices.chunks(3) {
+                let new = tri.iter().filter(|a| !local.contains_key(*a)).count();
+                if local.len() + new > u16::MAX as usize + 1 {
+                    local.clear();
+                    chunks.push(std::mem::replace(&mut chunk, empty.clone()));
+                }
+                for &i in tri {
+                    let k = *local.entry(i).or_insert_with(|| {
+                        let i = i as usize;
+                        chunk.positions.push(self.positions[i]);
+                        chunk.normals.push(self.normals[i]);
+                        chunk.tex_coords.push(self.tex_coords[i]);
+                        chunk.positions.len() - 1
+                    });
+                    chunk.indices.push(k as u16);
+                }
+            }
+            chunks.push(chunk);
+        }
+        chunks
+    }
+
+    ///The latest pass any of its ranges needs. The whole model is drawn in it.
+    pub fn pass(&self) -> Pass {
+        ranges_pass(&self.ranges)
+    }
+
+    ///Pixels with less alpha than this are cut out when the whole model is drawn at once.
+    pub fn cutoff(&self) -> f32 {
+        mask_cutoff(&self.ranges)
+    }
+}
+
+///Combine parts into one model, keeping a draw range for each.
+pub fn merge(parts: &[Part]) -> Result<ModelData, LoadError> {
+    let matrix = parts
+        .first()
+        .map(|a| a.data.matrix)
+        .unwrap_or(cgmath::Matrix4::identity());
+
+    let total: usize = parts.iter().map(|a| a.data.positions.len()).sum();
+    if u32::try_from(total).is_err() {
+        return Err(LoadError::TooManyVertices(total));
+    }
+
+    let mut positions = Vec::with_capacity(total);
+    let mut indices = Vec::new();
+    let mut tex_coords = Vec::with_capacity(total);
+    let mut normals = Vec::with_capacity(total);
+    let mut ranges = Vec::with_capacity(parts.len());
+
+    for (k, part) in parts.iter().enumerate() {
+        let data = &part.data;
+        let n = data.positions.len();
+        let offset = positions.len() as u32;
+
+        let start = indices.len();
+        match &data.indices {
+            Some(i) => {
+                for x in i.to_u32() {
+                    if x as usize >= n {
+                        return Err(LoadError::PartIndexOutOfRange { part: k, index: x });
+                    }
+                    indices.push(offset + x);
+                }
+            }
+            None => indices.extend((0..n as u32).map(|x| offset + x)),
+        }
+        ranges.push(DrawRange {
+            start,
+            count: indices.len() - start,
+            texture: part.texture,
+            alpha: part.alpha,
+        });
+
+        positions.extend_from_slice(&data.positions);
+        normals.extend_from_slice(&data.normals);
+        tex_coords.extend_from_slice(&data.tex_coords);
+    }
+
+    Ok(ModelData {
+        matrix,
+        positions,
+        indices: Some(Indices::new(indices)),
+        normals,
+        tex_coords,
+        ranges,
+    })
+}
+
+///One primitive of a mesh, already moved into place by its node.
+#[derive(Debug)]
+pub struct Part {
+    pub data: ModelData,
+    ///Index into the textures of the scene.
+    pub texture: Option<usize>,
+    pub base_colour: [f32; 4],
+    pub alpha: AlphaMode,
+}
+
+///Everything in a gltf file that can be drawn.
+#[derive(Debug)]
+pub struct Scene {
+    pub parts: Vec<Part>,
+    pub textures: Vec<Img>,
+}
+
+#[cfg(feature = "load")]
+//Turn strips and fans into plain triangles.
+fn triangle_list(mode: gltf::mesh::Mode, i: Vec<u32>) -> Option<Vec<u32>> {
+    use gltf::mesh::Mode;
+    match mode {
+        Mode::Triangles => Some(i),
+        Mode::TriangleStrip => Some(
+            (2..i.len())
+                .flat_map(|k| {
+                    //Every other triangle is flipped to keep the winding the same.
+                    if k % 2 == 0 {
+                        [i[k - 2], i[k - 1], i[k]]
+                    } else {
+                        [i[k - 1], i[k - 2], i[k]]
+                    }
+                })
+                .collect(),
+        ),
+        Mode::TriangleFan => Some((2..i.len()).flat_map(|k| [i[0], i[k - 1], i[k]]).collect()),
+        _ => None,
+    }
+}
+
+#[cfg(feature = "load")]
+//Smooth normals from the triangles around each vertex.
+fn generate_normals(positions: &[[f32; 3]], indices: &[u32]) -> Vec<[f32; 3]> {
+    let mut normals = vec![cgmath::Vector3::new(0.0, 0.0, 0.0); positions.len()];
+    for t in indices.chunks_exact(3) {
+        let [a, b, c] = [t[0], t[1], t[2]].map(|a| cgmath::Vector3::from(positions[a as usize]));
+        //Not normalized so bigger triangles count for more.
+        let n = (b - a).cross(c - a);
+        for &k in t {
+            normals[k as usize] += n;
+        }
+    }
+    normals
+        .into_iter()
+        .map(|n| {
+            if n.magnitude2() > 0.0 {
+                n.normalize().into()
+            } else {
+                [0.0, 0.0, 1.0]
+            }
+        })
+        .collect()
+}
+
+#[cfg(feature = "load")]
+//Project onto the two longest sides of the bounding box.
+fn generate_tex_coords(positions: &[[f32; 3]]) -> Vec<[f32; 2]> {
+    let mut min = [f32::MAX; 3];
+    let mut max = [f32::MIN; 3];
+    for p in positions {
+        for k in 0..3 {
+            min[k] = min[k].min(p[k]);
+            max[k] = max[k].max(p[k]);
+        }
+    }
+    let size = [0, 1, 2].map(|k| (max[k] - min[k]).max(f32::EPSILON));
+    let mut axis = [0, 1, 2];
+    axis.sort_by(|a, b| size[*b].partial_cmp(&size[*a]).unwrap());
+    let [u, v, _] = axis;
+
+    positions
+        .iter()
+        .map(|p| [(p[u] - min[u]) / size[u], (p[v] - min[v]) / size[v]])
+        .collect()
+}
+
+#[cfg(feature = "load")]
 impl Doop {
-    pub fn gen_ext(&self, ss: f32, foo: usize, custom_alpha: Option<f64>) -> (ModelData, Img) {
+    pub fn gen_ext(
+        &self,
+        ss: f32,
+        custom_alpha: Option<f64>,
+    ) -> Result<(ModelData, Img), LoadError> {
         use matrix::*;
         use std::f32::consts::PI;
-        let (mut m, tex) = self.gen(foo, custom_alpha);
+        let (mut m, tex) = self.gen(custom_alpha)?;
 
         let v = ss;
         let s = matrix::translation(v / 2.0, v / 2.0, 0.0)
@@ -72,175 +705,509 @@ impl Doop {
             *p = kk.transform_point((*p).into()).into();
         }
 
-        (m, tex)
+        Ok((m, tex))
     }
-    //TODO return a read only reference instead!
-    pub fn gen(&self, foo: usize, custom_alpha: Option<f64>) -> (ModelData, Img) {
-        // TODO use this: https://www.nayuki.io/page/png-file-chunk-inspector
-        let mut positions = Vec::new();
-        let mut indices = Vec::new();
-        let mut offset = 0;
-        let mut tex_coords = Vec::new();
-        let mut normals = Vec::new();
-
-        let texture = if let Some(texture) = self.document.textures().next() {
-            //log!("found a texture!");
-            let g_img = texture.source();
-
-            let buffers = &self.buffers;
-            match g_img.source() {
-                Source::View { view, .. } => {
-                    let parent_buffer_data = &buffers[view.buffer().index()].0;
-                    let data = &parent_buffer_data[view.offset()..view.offset() + view.length()];
-                    //log!(format!("{:?}",data));
 
-                    //data is in png format, need to decode it to rgba pixels for webgl
+    ///Decode a texture at its own size. Alpha isn't premultiplied yet.
+    fn texture(&self, texture: gltf::Texture, custom_alpha: Option<f64>) -> Img {
+        let mut rgba_image = self.images[texture.source().index()].clone();
+        let width = rgba_image.width();
+        let height = rgba_image.height();
 
-                    //use image::GenericImageView;
-                    let image =
-                        image::load_from_memory_with_format(data, image::ImageFormat::Png).unwrap();
-
-                    //unpremultiply here?
+        if let Some(custom_alpha) = custom_alpha {
+            for a in rgba_image.pixels_mut() {
+                a.0[3] = (a.0[3] as f64 * custom_alpha).round().clamp(0.0, 255.0) as u8;
+            }
+        }
 
-                    let width = image.width();
-                    let height = image.height();
-                    //TODO pass as argument
-                    let image =
-                        image.resize(width * foo as u32, height * foo as u32, FilterType::Nearest);
+        let data = rgba_image.into_raw();
 
-                    let width = image.width();
-                    let height = image.height();
-                    let mut rgba_image = image.to_rgba8();
+        Img {
+            width,
+            height,
+            data,
+        }
+    }
 
-                    if let Some(custom_alpha) = custom_alpha {
-                        for a in rgba_image.pixels_mut() {
-                            let mut alpha = a.0[3] as f64 / 256.0;
-                            alpha *= custom_alpha;
-                            a.0[3] = (alpha * 256.0) as u8;
-                        }
-                    }
+    fn part(
+        &self,
+        mesh: usize,
+        p: gltf::Primitive,
+        matrix: cgmath::Matrix4<f32>,
+    ) -> Result<Part, LoadError> {
+        let primitive = p.index();
+        let reader = p.reader(|buffer| Some(&self.buffers[buffer.index()]));
 
-                    let data = rgba_image.into_raw();
+        let positions: Vec<[f32; 3]> = reader
+            .read_positions()
+            .ok_or(LoadError::NoPositions { mesh, primitive })?
+            .collect();
 
-                    Img {
-                        width,
-                        height,
-                        data,
-                    }
-                }
-                _ => {
-                    panic!("not supported")
-                }
-            }
-        } else {
-            panic!("no texture!");
+        let indices = match reader.read_indices() {
+            Some(i) => i.into_u32().collect(),
+            None => (0..positions.len() as u32).collect(),
         };
+        let indices = triangle_list(p.mode(), indices).ok_or(LoadError::UnsupportedMode {
+            mesh,
+            primitive,
+            mode: p.mode(),
+        })?;
+        if let Some(&index) = indices.iter().find(|&&a| a as usize >= positions.len()) {
+            return Err(LoadError::IndexOutOfRange {
+                mesh,
+                primitive,
+                index,
+            });
+        }
 
-        for mesh in self.document.meshes() {
-            for p in mesh.primitives() {
-                //only support triangles
-                assert_eq!(p.mode(), gltf::mesh::Mode::Triangles);
+        let material = p.material();
+        let alpha = self.alpha_mode.unwrap_or(match material.alpha_mode() {
+            gltf::material::AlphaMode::Opaque => AlphaMode::Opaque,
+            gltf::material::AlphaMode::Mask => AlphaMode::Mask {
+                cutoff: material.alpha_cutoff().unwrap_or(0.5),
+            },
+            gltf::material::AlphaMode::Blend => AlphaMode::Blend,
+        });
+        let pbr = material.pbr_metallic_roughness();
+        let info = pbr.base_color_texture();
 
-                let reader = p.reader(|buffer| Some(&self.buffers[buffer.index()]));
+        let tex_coords =
+            match reader.read_tex_coords(info.as_ref().map(|a| a.tex_coord()).unwrap_or(0)) {
+                Some(t) => t.into_f32().collect(),
+                None => generate_tex_coords(&positions),
+            };
 
-                let p: Vec<_> = reader.read_positions().unwrap().collect();
+        let normals = match reader.read_normals() {
+            Some(t) => t.collect(),
+            None => generate_normals(&positions, &indices),
+        };
 
-                let i: Vec<_> = reader
-                    .read_indices()
-                    .unwrap()
-                    .into_u32()
-                    .map(|x| offset + (x as u16))
-                    .collect();
+        //Normals need the inverse transpose so they stay at right angles after scaling.
+        let m3 = cgmath::Matrix3::from_cols(
+            matrix.x.truncate(),
+            matrix.y.truncate(),
+            matrix.z.truncate(),
+        );
+        let normal_matrix = m3.invert().map(|a| a.transpose()).unwrap_or(m3);
 
-                if let Some(t) = reader.read_tex_coords(0) {
-                    tex_coords.extend(t.into_f32().map(|[x, y]| [x, y]))
-                } else {
-                    //if texture.is_some(){
-                    panic!("no texture coords!");
-                    //}
-                };
+        let positions = positions
+            .into_iter()
+            .map(|p| matrix.transform_point(p.into()).into())
+            .collect();
 
-                if let Some(t) = reader.read_normals() {
-                    normals.extend(t);
+        let normals = normals
+            .into_iter()
+            .map(|n| {
+                let n = normal_matrix * cgmath::Vector3::from(n);
+                if n.magnitude2() > 0.0 {
+                    n.normalize().into()
                 } else {
-                    panic!("no normals!");
+                    n.into()
                 }
-                //log!(format!("pos:{:?}", &p));
+            })
+            .collect();
 
-                //log!(format!("ind:{:?}", &i));
+        Ok(Part {
+            data: ModelData {
+                matrix,
+                positions,
+                ranges: vec![DrawRange {
+                    start: 0,
+                    count: indices.len(),
+                    texture: info.as_ref().map(|a| a.texture().index()),
+                    alpha,
+                }],
+                indices: Some(Indices::new(indices)),
+                normals,
+                tex_coords,
+            },
+            texture: info.map(|a| a.texture().index()),
+            base_colour: pbr.base_color_factor(),
+            alpha,
+        })
+    }
 
-                offset += p.len() as u16;
-                positions.extend(p);
+    ///Every primitive of every mesh in the scene with the transforms of all its parent nodes applied.
+    pub fn scene(&self, custom_alpha: Option<f64>) -> Result<Scene, LoadError> {
+        //Without a scene, draw every node that has no parent.
+        let roots: Vec<_> = match self
+            .document
+            .default_scene()
+            .or_else(|| self.document.scenes().next())
+        {
+            Some(scene) => scene.nodes().collect(),
+            None => {
+                let children: Vec<_> = self
+                    .document
+                    .nodes()
+                    .flat_map(|a| a.children())
+                    .map(|a| a.index())
+                    .collect();
+                self.document
+                    .nodes()
+                    .filter(|a| !children.contains(&a.index()))
+                    .collect()
+            }
+        };
 
-                indices.extend(i);
+        let mut parts = vec![];
+        let mut stack: Vec<_> = roots
+            .into_iter()
+            .map(|a| (a, cgmath::Matrix4::identity()))
+            .collect();
+        while let Some((node, parent)) = stack.pop() {
+            let local: cgmath::Matrix4<f32> = node.transform().matrix().into();
+            let matrix = parent * local;
+
+            if let Some(mesh) = node.mesh() {
+                for p in mesh.primitives() {
+                    parts.push(self.part(mesh.index(), p, matrix)?);
+                }
             }
+
+            //Reversed so the children come off the stack in order.
+            let children: Vec<_> = node.children().collect();
+            stack.extend(children.into_iter().rev().map(|a| (a, matrix)));
+        }
+
+        if parts.is_empty() {
+            return Err(LoadError::NoMeshes);
         }
 
-        let node = self.document.nodes().next().unwrap();
+        let mut textures: Vec<_> = self
+            .document
+            .textures()
+            .map(|a| self.texture(a, custom_alpha))
+            .collect();
 
-        let matrix: cgmath::Matrix4<f32> = node.transform().matrix().into();
+        //A texture is made to suit the first part that uses it.
+        for (k, t) in textures.iter_mut().enumerate() {
+            let alpha = parts
+                .iter()
+                .find(|a| a.texture == Some(k))
+                .map(|a| a.alpha)
+                .unwrap_or_default();
+            apply_alpha_mode(t, alpha);
+            premultiply(t);
+        }
 
-        // log!(format!("mat:    {:?}",node.transform().matrix()));
+        Ok(Scene { parts, textures })
+    }
 
-        // let (t,r,s)=node.transform().decomposed();
+    ///All the parts of the scene combined into one model with one texture.
+    ///Uses the texture of the first part that has one.
+    //TODO return a read only reference instead!
+    pub fn gen(&self, custom_alpha: Option<f64>) -> Result<(ModelData, Img), LoadError> {
+        // TODO use this: https://www.nayuki.io/page/png-file-chunk-inspector
+        let Scene {
+            parts,
+            mut textures,
+        } = self.scene(custom_alpha)?;
+
+        let texture = match parts.iter().find_map(|a| a.texture) {
+            Some(t) => textures.swap_remove(t),
+            None => {
+                let mut t = colour_tex(parts[0].base_colour);
+                apply_alpha_mode(&mut t, parts[0].alpha);
+                premultiply(&mut t);
+                t
+            }
+        };
 
-        // let rot={
-        //     let a:&cgmath::Quaternion<f32>=(&r).into();
-        //     log!(format!("quart:    {:?}",r));
-        //     let a=*a;
-        //     let rot:cgmath::Matrix4<f32>=a.into();
-        //     rot
-        // };
+        Ok((merge(&parts)?, texture))
+    }
+}
 
-        // let t={
-        //     let a:&cgmath::Vector3<f32>=(&t).into();
-        //     matrix::translation(a.x, a.y, a.z)
-        // };
+///Decode a png, jpeg or uncompressed ktx2 image into rgba pixels for webgl.
+///Only the largest level of a ktx2 file is used, mip levels are made with [`mipmap`].
+#[cfg(feature = "load")]
+fn decode_image(index: usize, data: &[u8]) -> Result<Img, LoadError> {
+    if ktx2::is_ktx2(data) {
+        let mut levels = ktx2::load(data).map_err(|error| LoadError::Ktx2 {
+            image: index,
+            error,
+        })?;
+        return Ok(levels.swap_remove(0));
+    }
 
-        // let s={
-        //     let a:&cgmath::Vector3<f32>=(&s).into();
-        //     matrix::scale(a.x, a.y, a.z)
-        // };
+    let image = image::load_from_memory(data).map_err(|error| LoadError::Image {
+        image: index,
+        error,
+    })?;
+    Ok(Img {
+        width: image.width(),
+        height: image.height(),
+        data: image.to_rgba8().into_raw(),
+    })
+}
 
-        // use matrix::*;
-        // let matrix=s.chain(t).chain(rot).generate();// rot.chain(t).chain(s).generate();
+///Load a texture on its own. The format is worked out from the bytes.
+#[cfg(feature = "load")]
+pub fn load_texture(data: &[u8]) -> Result<Img, LoadError> {
+    decode_image(0, data)
+}
 
-        let positions = positions
-            .into_iter()
-            .map(|p| matrix.transform_point(p.into()).into())
-            .collect();
+#[cfg(feature = "load")]
+pub fn load_texture_from_data(data: &[u8]) -> Img {
+    load_texture(data).unwrap()
+}
 
-        let normals = normals
-            .into_iter()
-            .map(|p| matrix.transform_point(p.into()).into())
-            .collect();
+#[cfg(all(test, feature = "load"))]
+mod tests {
+    use super::*;
 
+    const ASSETS: &[(&str, &[u8])] = &[
+        ("attack", include_bytes!("../../assets/attack.glb")),
+        ("cat2", include_bytes!("../../assets/cat2.glb")),
+        ("cat_culled", include_bytes!("../../assets/cat_culled.glb")),
+        ("cat_final", include_bytes!("../../assets/cat_final.glb")),
         (
-            ModelData {
-                normals,
-                matrix,
+            "cat_texture_bleed",
+            include_bytes!("../../assets/cat_texture_bleed.glb"),
+        ),
+        ("donut", include_bytes!("../../assets/donut.glb")),
+        (
+            "drop_shadow",
+            include_bytes!("../../assets/drop_shadow.glb"),
+        ),
+        ("grass", include_bytes!("../../assets/grass.glb")),
+        ("key", include_bytes!("../../assets/key.glb")),
+        ("kitty", include_bytes!("../../assets/kitty.glb")),
+        ("person-v1", include_bytes!("../../assets/person-v1.glb")),
+        ("road", include_bytes!("../../assets/road.glb")),
+        (
+            "select_model",
+            include_bytes!("../../assets/select_model.glb"),
+        ),
+        ("shaded", include_bytes!("../../assets/shaded.glb")),
+        ("tiger", include_bytes!("../../assets/tiger.glb")),
+        ("tiger2", include_bytes!("../../assets/tiger2.glb")),
+    ];
 
-                positions,
-                indices: Some(indices),
-                tex_coords,
+    fn part(vertices: usize, indices: Option<Vec<u32>>, texture: Option<usize>) -> Part {
+        Part {
+            data: ModelData {
+                matrix: cgmath::Matrix4::identity(),
+                positions: (0..vertices).map(|a| [a as f32, 0.0, 0.0]).collect(),
+                indices: indices.map(Indices::U32),
+                normals: vec![[0.0, 0.0, 1.0]; vertices],
+                tex_coords: vec![[0.0, 0.0]; vertices],
+                ranges: vec![],
             },
             texture,
-        )
+            alpha: AlphaMode::Opaque,
+            base_colour: [1.0; 4],
+        }
     }
-}
 
-pub fn load_texture_from_data(data: &[u8]) -> Img {
-    //use image::GenericImageView;
-    let image = image::load_from_memory_with_format(data, image::ImageFormat::Png).unwrap();
-    let width = image.width();
-    let height = image.height();
+    fn check_ranges(name: &str, data: &ModelData) {
+        let n = data.indices.as_ref().map(|a| a.len()).unwrap_or(0);
+        let mut next = 0;
+        for r in &data.ranges {
+            assert_eq!(r.start, next, "{}: ranges have a gap", name);
+            next += r.count;
+        }
+        assert_eq!(next, n, "{}: ranges don't cover every index", name);
+        for i in data.indices.as_ref().unwrap().to_u32() {
+            assert!((i as usize) < data.positions.len(), "{}: bad index", name);
+        }
+    }
 
-    let rgba_image = image.to_rgba8();
-    let data = rgba_image.into_raw();
+    #[test]
+    fn bundled_assets_load() {
+        for (name, bytes) in ASSETS {
+            let doop = load_glb(bytes).unwrap_or_else(|e| panic!("{}: {}", name, e));
+            let scene = doop.scene(None).unwrap();
+            let (data, _) = doop.gen(None).unwrap();
 
-    Img {
-        width,
-        height,
-        data,
+            assert_eq!(data.ranges.len(), scene.parts.len(), "{}", name);
+            assert_eq!(data.positions.len(), data.normals.len(), "{}", name);
+            assert_eq!(data.positions.len(), data.tex_coords.len(), "{}", name);
+            check_ranges(name, &data);
+        }
+    }
+
+    #[test]
+    fn picks_index_size() {
+        assert_eq!(
+            Indices::new(vec![0, 1, 65535]),
+            Indices::U16(vec![0, 1, 65535])
+        );
+        assert_eq!(
+            Indices::new(vec![0, 1, 65536]),
+            Indices::U32(vec![0, 1, 65536])
+        );
+        assert!(Indices::new(vec![]).is_empty());
+    }
+
+    #[test]
+    fn merges_large_meshes() {
+        let n = 40000;
+        let tri: Vec<u32> = vec![0, 1, (n - 1) as u32];
+        let parts = [
+            part(n, Some(tri.clone()), Some(0)),
+            part(n, Some(tri), Some(1)),
+        ];
+
+        let data = merge(&parts).unwrap();
+        assert_eq!(data.positions.len(), 2 * n);
+        assert_eq!(
+            data.indices,
+            Some(Indices::U32(vec![
+                0,
+                1,
+                n as u32 - 1,
+                n as u32,
+                n as u32 + 1,
+                2 * n as u32 - 1
+            ]))
+        );
+        assert_eq!(
+            data.ranges,
+            vec![
+                DrawRange {
+                    start: 0,
+                    count: 3,
+                    texture: Some(0),
+                    alpha: AlphaMode::Opaque,
+                },
+                DrawRange {
+                    start: 3,
+                    count: 3,
+                    texture: Some(1),
+                    alpha: AlphaMode::Opaque,
+                },
+            ]
+        );
+        check_ranges("large", &data);
+    }
+
+    #[test]
+    fn small_meshes_stay_16_bit() {
+        let parts = [part(3, None, None), part(4, Some(vec![0, 2, 3]), None)];
+        let data = merge(&parts).unwrap();
+        assert_eq!(data.indices, Some(Indices::U16(vec![0, 1, 2, 3, 5, 6])));
+        check_ranges("small", &data);
+    }
+
+    #[test]
+    fn bad_index_is_an_error() {
+        let parts = [part(3, None, None), part(3, Some(vec![0, 1, 3]), None)];
+        assert!(matches!(
+            merge(&parts),
+            Err(LoadError::PartIndexOutOfRange { part: 1, index: 3 })
+        ));
+    }
+
+    #[test]
+    fn unindexed_copies_vertices() {
+        let n = 70000;
+        let parts = [part(n, Some(vec![n as u32 - 1, 0, 1]), None)];
+        let data = merge(&parts).unwrap().unindexed();
+        assert_eq!(data.indices, None);
+        assert_eq!(
+            data.positions,
+            vec![[(n - 1) as f32, 0.0, 0.0], [0.0, 0.0, 0.0], [1.0, 0.0, 0.0]]
+        );
+        assert_eq!(data.ranges[0].count, 3);
+    }
+
+    #[test]
+    fn chunks_follow_ranges() {
+        let n = 70000;
+        let parts = [
+            part(n, Some(vec![n as u32 - 1, 0, 1]), None),
+            part(3, Some(vec![2, 1, 0]), Some(1)),
+        ];
+        let chunks = merge(&parts).unwrap().chunks();
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(
+            chunks[0].positions,
+            vec![[(n - 1) as f32, 0.0, 0.0], [0.0, 0.0, 0.0], [1.0, 0.0, 0.0]]
+        );
+        assert_eq!(chunks[0].indices, vec![0, 1, 2]);
+        assert_eq!(chunks[1].texture, Some(1));
+        assert_eq!(chunks[1].indices, vec![0, 1, 2]);
+        assert_eq!(chunks[1].positions[0], [2.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn big_ranges_are_split() {
+        let n = 70002;
+        let parts = [part(n, Some((0..n as u32).collect()), None)];
+        let chunks = merge(&parts).unwrap().chunks();
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(chunks[0].positions.len() % 3, 0);
+        assert!(chunks[0].positions.len() <= 65536);
+        let total: usize = chunks.iter().map(|a| a.indices.len()).sum();
+        assert_eq!(total, n);
+        assert_eq!(
+            chunks[1].positions[0],
+            [chunks[0].positions.len() as f32, 0.0, 0.0]
+        );
+    }
+
+    #[test]
+    fn loads_jpeg_textures() {
+        let img = image::RgbImage::from_pixel(8, 4, image::Rgb([200, 40, 40]));
+        let mut jpeg = vec![];
+        image::DynamicImage::ImageRgb8(img)
+            .write_to(
+                &mut std::io::Cursor::new(&mut jpeg),
+                image::ImageFormat::Jpeg,
+            )
+            .unwrap();
+
+        let tex = load_texture(&jpeg).unwrap();
+        assert_eq!((tex.width, tex.height), (8, 4));
+        for p in tex.data.chunks(4) {
+            assert!(p[0] > 190 && p[1] < 50 && p[3] == 255, "{:?}", p);
+        }
+        assert!(matches!(
+            load_texture(b"not an image"),
+            Err(LoadError::Image { .. })
+        ));
+    }
+
+    #[test]
+    fn premultiplies() {
+        let mut img = Img {
+            width: 2,
+            height: 1,
+            data: vec![255, 128, 10, 128, 200, 200, 200, 0],
+        };
+        premultiply(&mut img);
+        assert_eq!(img.data, [128, 64, 5, 128, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn masked_textures_are_cut_out() {
+        let doop = load_glb(include_bytes!("../../assets/cat_final.glb")).unwrap();
+        let (data, tex) = doop.gen(None).unwrap();
+        assert_eq!(data.ranges[0].alpha, AlphaMode::Mask { cutoff: 0.05 });
+        assert_eq!(data.pass(), Pass::Mask);
+        assert_eq!(data.cutoff(), 0.05);
+        for p in tex.data.chunks(4) {
+            assert!(p[3] == 255 || p == [0, 0, 0, 0], "{:?}", p);
+        }
+    }
+
+    #[test]
+    fn blended_textures_are_premultiplied() {
+        let mut doop = load_glb(include_bytes!("../../assets/drop_shadow.glb")).unwrap();
+        doop.alpha_mode = Some(AlphaMode::Blend);
+        let (data, plain) = doop.gen(None).unwrap();
+        let (_, half) = doop.gen(Some(0.5)).unwrap();
+        assert_eq!(data.pass(), Pass::Blend);
+        assert_eq!(data.cutoff(), 0.0);
+
+        assert!(plain.data.chunks(4).any(|p| p[3] > 0 && p[3] < 255));
+        for (p, h) in plain.data.chunks(4).zip(half.data.chunks(4)) {
+            assert!(p[..3].iter().all(|&c| c <= p[3]), "{:?}", p);
+            assert!(h[..3].iter().all(|&c| c <= h[3]), "{:?}", h);
+            //Halving used to turn 255 into 127.
+            let want = (p[3] as f64 * 0.5).round() as u8;
+            assert_eq!(h[3], want);
+        }
     }
 }
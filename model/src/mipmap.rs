@@ -0,0 +1,294 @@
+//! Mip chains and how a texture would like to be sampled.
+//!
+//! Textures are expected to be premultiplied, see [`premultiply`]. Filtering them then
+//! weights colours by alpha so see-through pixels don't darken the edges around them.
+use super::*;
+
+///How a texture is sampled when it is drawn bigger than it is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Filter {
+    ///Sharp pixels, for pixel art.
+    Nearest,
+    #[default]
+    Linear,
+}
+
+///How each mip level is made from the one before it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MipFilter {
+    ///Average of two by two pixels. Fast.
+    Box,
+    ///Sharper, at the cost of some ringing.
+    Lanczos,
+}
+
+///How an asset wants its texture sampled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Sampling {
+    pub filter: Filter,
+    ///Textures drawn smaller than they are use mip levels made with this filter.
+    pub mipmaps: Option<MipFilter>,
+}
+
+impl Sampling {
+    ///Packed into one word for baked files.
+    pub fn to_bits(self) -> u32 {
+        let filter = match self.filter {
+            Filter::Nearest => 0,
+            Filter::Linear => 1,
+        };
+        let mipmaps = match self.mipmaps {
+            None => 0,
+            Some(MipFilter::Box) => 1,
+            Some(MipFilter::Lanczos) => 2,
+        };
+        filter | mipmaps << 8
+    }
+
+    pub fn from_bits(a: u32) -> Option<Sampling> {
+        let filter = match a & 0xff {
+            0 => Filter::Nearest,
+            1 => Filter::Linear,
+            _ => return None,
+        };
+        let mipmaps = match a >> 8 {
+            0 => None,
+            1 => Some(MipFilter::Box),
+            2 => Some(MipFilter::Lanczos),
+            _ => return None,
+        };
+        Some(Sampling { filter, mipmaps })
+    }
+}
+
+///The levels below `base`, each half the size of the one before, down to one pixel
+///or until there are `max_levels` of them.
+pub fn mipmaps(base: &Img, filter: MipFilter, max_levels: usize) -> Vec<Img> {
+    let mut levels: Vec<Img> = vec![];
+    while levels.len() < max_levels {
+        let last = levels.last().unwrap_or(base);
+        if last.width <= 1 && last.height <= 1 {
+            break;
+        }
+        let next = match filter {
+            MipFilter::Box => box_half(last),
+            MipFilter::Lanczos => lanczos_half(last),
+        };
+        levels.push(next);
+    }
+    levels
+}
+
+fn half(a: u32) -> u32 {
+    (a / 2).max(1)
+}
+
+fn floats(img: &Img) -> Vec<[f32; 4]> {
+    img.data
+        .chunks_exact(4)
+        .map(|p| [p[0], p[1], p[2], p[3]].map(|c| c as f32))
+        .collect()
+}
+
+fn bytes(p: [f32; 4]) -> [u8; 4] {
+    p.map(|c| c.round().clamp(0.0, 255.0) as u8)
+}
+
+fn box_half(img: &Img) -> Img {
+    let (w, h) = (img.width, img.height);
+    let (nw, nh) = (half(w), half(h));
+    let src = floats(img);
+    let at = |x: u32, y: u32| src[(y.min(h - 1) * w + x.min(w - 1)) as usize];
+
+    let mut data = Vec::with_capacity((nw * nh * 4) as usize);
+    for y in 0..nh {
+        for x in 0..nw {
+            let mut sum = [0.0; 4];
+            for (dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+                let p = at(x * 2 + dx, y * 2 + dy);
+                for k in 0..4 {
+                    sum[k] += p[k] / 4.0;
+                }
+            }
+            data.extend(bytes(sum));
+        }
+    }
+    Img {
+        width: nw,
+        height: nh,
+        data,
+    }
+}
+
+fn lanczos3(x: f32) -> f32 {
+    use std::f32::consts::PI;
+    if x == 0.0 {
+        1.0
+    } else if x.abs() < 3.0 {
+        let px = PI * x;
+        3.0 * px.sin() * (px / 3.0).sin() / (px * px)
+    } else {
+        0.0
+    }
+}
+
+//The weights of the source pixels for each output pixel when halving a line of `len`.
+fn lanczos_taps(len: u32) -> Vec<Vec<(u32, f32)>> {
+    (0..half(len))
+        .map(|o| {
+            //Centre of the output pixel in source pixels.
+            let centre = if len > 1 { o as f32 * 2.0 + 1.0 } else { 0.5 };
+            let mut taps: Vec<(u32, f32)> = (-6..6)
+                .map(|k| {
+                    let s = (centre.floor() as i64 + k).clamp(0, len as i64 - 1) as u32;
+                    let x = centre.floor() + k as f32 + 0.5;
+                    (s, lanczos3((x - centre) / 2.0))
+                })
+                .collect();
+            let total: f32 = taps.iter().map(|a| a.1).sum();
+            for t in taps.iter_mut() {
+                t.1 /= total;
+            }
+            taps
+        })
+        .collect()
+}
+
+fn lanczos_half(img: &Img) -> Img {
+    let (w, h) = (img.width, img.height);
+    let (nw, nh) = (half(w), half(h));
+    let src = floats(img);
+
+    //Across first, then down.
+    let xtaps = lanczos_taps(w);
+    let mut across = vec![[0.0; 4]; (nw * h) as usize];
+    for y in 0..h {
+        for (x, taps) in xtaps.iter().enumerate() {
+            let out = &mut across[(y * nw) as usize + x];
+            for &(s, weight) in taps {
+                let p = src[(y * w + s) as usize];
+                for k in 0..4 {
+                    out[k] += p[k] * weight;
+                }
+            }
+        }
+    }
+
+    let ytaps = lanczos_taps(h);
+    let mut data = Vec::with_capacity((nw * nh * 4) as usize);
+    for taps in ytaps.iter() {
+        for x in 0..nw {
+            let mut sum = [0.0; 4];
+            for &(s, weight) in taps {
+                let p = across[(s * nw + x) as usize];
+                for k in 0..4 {
+                    sum[k] += p[k] * weight;
+                }
+            }
+            //Ringing can push colours past the alpha they are premultiplied by.
+            let a = sum[3].clamp(0.0, 255.0);
+            for c in sum[..3].iter_mut() {
+                *c = c.clamp(0.0, a);
+            }
+            sum[3] = a;
+            data.extend(bytes(sum));
+        }
+    }
+    Img {
+        width: nw,
+        height: nh,
+        data,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn solid(width: u32, height: u32, p: [u8; 4]) -> Img {
+        Img {
+            width,
+            height,
+            data: p.repeat((width * height) as usize),
+        }
+    }
+
+    #[test]
+    fn chain_sizes() {
+        let levels = mipmaps(&solid(16, 4, [1, 2, 3, 255]), MipFilter::Box, usize::MAX);
+        let sizes: Vec<_> = levels.iter().map(|a| (a.width, a.height)).collect();
+        assert_eq!(sizes, [(8, 2), (4, 1), (2, 1), (1, 1)]);
+
+        let levels = mipmaps(&solid(16, 16, [0; 4]), MipFilter::Lanczos, 2);
+        assert_eq!(levels.len(), 2);
+        assert!(mipmaps(&solid(1, 1, [0; 4]), MipFilter::Box, 5).is_empty());
+    }
+
+    #[test]
+    fn solid_stays_solid() {
+        let p = [200, 100, 50, 255];
+        for filter in [MipFilter::Box, MipFilter::Lanczos] {
+            for level in mipmaps(&solid(12, 7, p), filter, usize::MAX) {
+                assert!(level.data.chunks(4).all(|a| a == p), "{:?}", filter);
+            }
+        }
+    }
+
+    #[test]
+    fn box_averages() {
+        let img = Img {
+            width: 2,
+            height: 2,
+            data: vec![0, 0, 0, 255, 200, 0, 0, 255, 0, 100, 0, 255, 0, 0, 40, 255],
+        };
+        let levels = mipmaps(&img, MipFilter::Box, usize::MAX);
+        assert_eq!(levels[0].data, [50, 25, 10, 255]);
+    }
+
+    #[test]
+    fn clear_pixels_dont_darken() {
+        //Half red, half see through. The red shouldn't get darker.
+        let img = Img {
+            width: 2,
+            height: 1,
+            data: vec![255, 0, 0, 255, 0, 0, 0, 0],
+        };
+        for filter in [MipFilter::Box, MipFilter::Lanczos] {
+            let p = &mipmaps(&img, filter, 1)[0].data;
+            assert_eq!(p[0], p[3], "{:?}", filter);
+            assert_eq!(&p[1..3], [0, 0], "{:?}", filter);
+            assert!((120..=136).contains(&p[3]), "{:?} {:?}", filter, p);
+        }
+    }
+
+    #[test]
+    fn lanczos_isnt_shifted() {
+        //A ramp should come out as the average of each pair away from the edges.
+        let data = (0..16u8)
+            .flat_map(|x| [x * 16, x * 16, x * 16, 255])
+            .collect();
+        let img = Img {
+            width: 16,
+            height: 1,
+            data,
+        };
+        let level = &mipmaps(&img, MipFilter::Lanczos, 1)[0];
+        assert_eq!(level.width, 8);
+        for x in 3..5 {
+            let want = 32 * x as i32 + 8;
+            let got = level.data[x * 4] as i32;
+            assert!((got - want).abs() <= 2, "{} {} {}", x, got, want);
+        }
+    }
+
+    #[test]
+    fn sampling_bits_round_trip() {
+        for filter in [Filter::Nearest, Filter::Linear] {
+            for mipmaps in [None, Some(MipFilter::Box), Some(MipFilter::Lanczos)] {
+                let s = Sampling { filter, mipmaps };
+                assert_eq!(Sampling::from_bits(s.to_bits()), Some(s));
+            }
+        }
+        assert_eq!(Sampling::from_bits(7), None);
+    }
+}
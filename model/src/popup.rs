@@ -0,0 +1,182 @@
+//! Text that pops up somewhere in the world, floats up and fades away, like damage
+//! numbers. Time is counted in frames.
+
+///Frames a popup is shown for.
+pub const LIFETIME: u32 = 60;
+///Frames between popups that start at the same place so they don't cover each other.
+pub const STAGGER: u32 = 15;
+///How far a popup floats up over its life, in lines of text.
+pub const RISE: f32 = 1.5;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Popup {
+    pub text: String,
+    ///Where it starts, in world coordinates.
+    pub pos: [f32; 3],
+    age: u32,
+    //Frames to wait before showing up.
+    delay: u32,
+}
+
+impl Popup {
+    //How far through its life it is, from 0 to 1. None while it is waiting.
+    fn progress(&self) -> Option<f32> {
+        let t = self.age.checked_sub(self.delay)?;
+        Some(t as f32 / LIFETIME as f32)
+    }
+
+    pub fn is_visible(&self) -> bool {
+        self.progress().is_some()
+    }
+
+    ///How far it has floated up, in lines. Quick at first and slowing down.
+    pub fn rise(&self) -> f32 {
+        let t = self.progress().unwrap_or(0.0);
+        RISE * (1.0 - (1.0 - t) * (1.0 - t))
+    }
+
+    ///Solid for the first half of its life, then fading out.
+    pub fn alpha(&self) -> f32 {
+        match self.progress() {
+            Some(t) => ((1.0 - t) * 2.0).min(1.0),
+            None => 0.0,
+        }
+    }
+}
+
+///Every popup that is still showing. Tick it once a frame.
+#[derive(Debug, Clone, Default)]
+pub struct Popups {
+    popups: Vec<Popup>,
+}
+
+impl Popups {
+    pub fn new() -> Popups {
+        Popups::default()
+    }
+
+    ///Show `text` at `pos`. It waits its turn if another popup has just started there.
+    pub fn spawn(&mut self, text: impl Into<String>, pos: [f32; 3]) {
+        let delay = self
+            .popups
+            .iter()
+            .filter(|a| a.pos == pos)
+            .map(|a| (a.delay + STAGGER).saturating_sub(a.age))
+            .max()
+            .unwrap_or(0);
+        self.popups.push(Popup {
+            text: text.into(),
+            pos,
+            age: 0,
+            delay,
+        });
+    }
+
+    ///Move every popup on a frame and drop the ones that are done.
+    pub fn tick(&mut self) {
+        for a in self.popups.iter_mut() {
+            a.age += 1;
+        }
+        self.popups.retain(|a| a.age < a.delay + LIFETIME);
+    }
+
+    ///Oldest first, including the ones that are still waiting to show up.
+    pub fn iter(&self) -> impl Iterator<Item = &Popup> {
+        self.popups.iter()
+    }
+
+    pub fn clear(&mut self) {
+        self.popups.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.popups.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.popups.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn floats_up_and_fades() {
+        let mut a = Popups::new();
+        a.spawn("-5", [1.0, 2.0, 3.0]);
+        let p = a.iter().next().unwrap();
+        assert_eq!((p.text.as_str(), p.pos), ("-5", [1.0, 2.0, 3.0]));
+        assert!(p.is_visible());
+        assert_eq!((p.rise(), p.alpha()), (0.0, 1.0));
+
+        let mut last = 0.0;
+        for frame in 1..LIFETIME {
+            a.tick();
+            let p = a.iter().next().unwrap();
+            assert!(p.rise() > last, "{}", frame);
+            last = p.rise();
+            if frame <= LIFETIME / 2 {
+                assert_eq!(p.alpha(), 1.0);
+            } else {
+                assert!(p.alpha() < 1.0 && p.alpha() > 0.0);
+            }
+        }
+        assert!(last <= RISE);
+
+        a.tick();
+        assert!(a.is_empty());
+    }
+
+    #[test]
+    fn popups_in_one_place_take_turns() {
+        let mut a = Popups::new();
+        let pos = [0.0; 3];
+        a.spawn("-3", pos);
+        a.spawn("KO", pos);
+        a.spawn("+2", [5.0, 0.0, 0.0]);
+
+        let visible = |a: &Popups| -> Vec<String> {
+            a.iter()
+                .filter(|p| p.is_visible())
+                .map(|p| p.text.clone())
+                .collect()
+        };
+        assert_eq!(visible(&a), ["-3", "+2"]);
+        let ko = a.iter().nth(1).unwrap();
+        assert_eq!((ko.rise(), ko.alpha()), (0.0, 0.0));
+
+        for _ in 0..STAGGER {
+            a.tick();
+        }
+        assert_eq!(visible(&a), ["-3", "KO", "+2"]);
+
+        //The one that waited is also around for longer.
+        for _ in STAGGER..LIFETIME {
+            a.tick();
+        }
+        assert_eq!(visible(&a), ["KO"]);
+        for _ in 0..STAGGER {
+            a.tick();
+        }
+        assert!(a.is_empty());
+    }
+
+    #[test]
+    fn later_popups_wait_for_the_last() {
+        let mut a = Popups::new();
+        let pos = [0.0; 3];
+        for _ in 0..3 {
+            a.spawn("-1", pos);
+        }
+        a.tick();
+        a.spawn("-1", pos);
+        //The fourth waits for the third, which waits 2 * STAGGER.
+        let waits: Vec<_> = a.iter().map(|p| p.delay - p.age.min(p.delay)).collect();
+        assert_eq!(waits, [0, STAGGER - 1, 2 * STAGGER - 1, 3 * STAGGER - 1]);
+
+        a.clear();
+        assert_eq!(a.len(), 0);
+    }
+}
@@ -0,0 +1,750 @@
+//! Skins and animation clips from gltf files, played back on the cpu.
+//!
+//! A [`Skeleton`] holds the joints of one skin and every clip that moves them.
+//! Sampling a clip gives a [`Pose`], which turns into one matrix per joint that
+//! a [`Skin`] uses to move the vertices of its mesh.
+//!
+//! The game itself doesn't use skinning. Skins and clips are only read from gltf
+//! files, which needs the `load` feature, and the baked format has no room for them.
+use super::*;
+use cgmath::{Matrix4, Quaternion, Vector3, VectorSpace, Zero};
+
+///A translation, rotation and scale. How a node sits relative to its parent.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Trs {
+    pub translation: Vector3<f32>,
+    pub rotation: Quaternion<f32>,
+    pub scale: Vector3<f32>,
+}
+
+impl Trs {
+    pub fn identity() -> Trs {
+        Trs {
+            translation: Vector3::new(0.0, 0.0, 0.0),
+            rotation: Quaternion::new(1.0, 0.0, 0.0, 0.0),
+            scale: Vector3::new(1.0, 1.0, 1.0),
+        }
+    }
+
+    fn from_node(node: &gltf::Node) -> Trs {
+        let (t, [x, y, z, w], s) = node.transform().decomposed();
+        Trs {
+            translation: t.into(),
+            rotation: Quaternion::new(w, x, y, z),
+            scale: s.into(),
+        }
+    }
+
+    pub fn matrix(&self) -> Matrix4<f32> {
+        Matrix4::from_translation(self.translation)
+            * Matrix4::from(self.rotation)
+            * Matrix4::from_nonuniform_scale(self.scale.x, self.scale.y, self.scale.z)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interpolation {
+    Linear,
+    ///Hold each keyframe until the next one.
+    Step,
+}
+
+///The values of a channel, one per keyframe.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Keyframes {
+    Translation(Vec<Vector3<f32>>),
+    Rotation(Vec<Quaternion<f32>>),
+    Scale(Vec<Vector3<f32>>),
+}
+
+///Moves one part of one joint over time.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Channel {
+    ///Index into the joints of the skeleton.
+    pub joint: usize,
+    pub interpolation: Interpolation,
+    ///Keyframe times in seconds, in order.
+    pub times: Vec<f32>,
+    pub keyframes: Keyframes,
+}
+
+//The keyframes either side of `time` and how far along between them it is.
+//Times outside the clip hold the first or last keyframe.
+fn keyframe(times: &[f32], time: f32) -> (usize, usize, f32) {
+    let last = times.len() - 1;
+    if time <= times[0] {
+        return (0, 0, 0.0);
+    }
+    if time >= times[last] {
+        return (last, last, 0.0);
+    }
+    let a = times.partition_point(|&t| t <= time) - 1;
+    let b = a + 1;
+    let span = times[b] - times[a];
+    let f = if span > 0.0 {
+        (time - times[a]) / span
+    } else {
+        0.0
+    };
+    (a, b, f)
+}
+
+//Spherical interpolation the short way around.
+fn slerp(a: Quaternion<f32>, b: Quaternion<f32>, f: f32) -> Quaternion<f32> {
+    let b = if a.dot(b) < 0.0 { -b } else { b };
+    a.slerp(b, f).normalize()
+}
+
+impl Channel {
+    ///Set the part of `trs` this channel moves to its value at `time`.
+    pub fn sample(&self, time: f32, trs: &mut Trs) {
+        if self.times.is_empty() {
+            return;
+        }
+        let (a, b, f) = keyframe(&self.times, time);
+        let f = match self.interpolation {
+            Interpolation::Linear => f,
+            Interpolation::Step => 0.0,
+        };
+        match &self.keyframes {
+            Keyframes::Translation(v) => trs.translation = v[a].lerp(v[b], f),
+            Keyframes::Rotation(v) => trs.rotation = slerp(v[a], v[b], f),
+            Keyframes::Scale(v) => trs.scale = v[a].lerp(v[b], f),
+        }
+    }
+}
+
+///One animation, like walking or attacking.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Clip {
+    pub name: Option<String>,
+    ///The time of the last keyframe in seconds.
+    pub duration: f32,
+    pub channels: Vec<Channel>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Joint {
+    pub name: Option<String>,
+    ///Index of the node in the gltf file.
+    pub node: usize,
+    ///Index of the closest ancestor that is also a joint.
+    pub parent: Option<usize>,
+    ///Transforms of the nodes between the parent joint and this one that aren't joints.
+    pub offset: Matrix4<f32>,
+    ///Where the joint is when nothing is playing.
+    pub rest: Trs,
+    ///Takes vertices from model space into the space of the joint.
+    pub inverse_bind: Matrix4<f32>,
+}
+
+///The local transform of every joint at some moment.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pose {
+    pub locals: Vec<Trs>,
+}
+
+///The joints of one skin and all the clips in the file that move them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Skeleton {
+    pub joints: Vec<Joint>,
+    pub clips: Vec<Clip>,
+    //Joints sorted so parents come before their children.
+    order: Vec<usize>,
+}
+
+impl Skeleton {
+    pub fn clip(&self, name: &str) -> Option<usize> {
+        self.clips
+            .iter()
+            .position(|a| a.name.as_deref() == Some(name))
+    }
+
+    pub fn rest_pose(&self) -> Pose {
+        Pose {
+            locals: self.joints.iter().map(|a| a.rest).collect(),
+        }
+    }
+
+    ///The pose `time` seconds into a clip. Looping clips wrap around, others hold their last frame.
+    pub fn sample(&self, clip: usize, time: f32, looping: bool) -> Pose {
+        let clip = &self.clips[clip];
+        let time = if looping && clip.duration > 0.0 {
+            time.rem_euclid(clip.duration)
+        } else {
+            time
+        };
+
+        let mut pose = self.rest_pose();
+        for c in clip.channels.iter() {
+            c.sample(time, &mut pose.locals[c.joint]);
+        }
+        pose
+    }
+
+    ///Where each joint is in model space.
+    pub fn globals(&self, pose: &Pose) -> Vec<Matrix4<f32>> {
+        let mut globals = vec![Matrix4::identity(); self.joints.len()];
+        for &k in self.order.iter() {
+            let joint = &self.joints[k];
+            let parent = joint
+                .parent
+                .map(|p| globals[p])
+                .unwrap_or(Matrix4::identity());
+            globals[k] = parent * joint.offset * pose.locals[k].matrix();
+        }
+        globals
+    }
+
+    ///The matrices to upload for skinning. One per joint.
+    pub fn joint_matrices(&self, pose: &Pose) -> Vec<Matrix4<f32>> {
+        self.globals(pose)
+            .into_iter()
+            .zip(self.joints.iter())
+            .map(|(g, j)| g * j.inverse_bind)
+            .collect()
+    }
+}
+
+///A mesh with the joints and weights that move each vertex.
+#[derive(Debug)]
+pub struct Skin {
+    ///The mesh in its bind pose.
+    pub data: ModelData,
+    pub joints: Vec<[u16; 4]>,
+    pub weights: Vec<[f32; 4]>,
+}
+
+impl Skin {
+    ///Move the vertices by the joint matrices of a pose, one for every joint of the skeleton.
+    pub fn apply(&self, matrices: &[Matrix4<f32>]) -> ModelData {
+        let mut positions = Vec::with_capacity(self.data.positions.len());
+        let mut normals = Vec::with_capacity(self.data.normals.len());
+
+        for (k, (p, n)) in self
+            .data
+            .positions
+            .iter()
+            .zip(self.data.normals.iter())
+            .enumerate()
+        {
+            let weights = self.weights[k];
+            let total: f32 = weights.iter().sum();
+
+            let m = if total > 0.0 {
+                let mut m = Matrix4::zero();
+                for (&j, &w) in self.joints[k].iter().zip(weights.iter()) {
+                    m += matrices[j as usize] * (w / total);
+                }
+                m
+            } else {
+                Matrix4::identity()
+            };
+
+            positions.push(m.transform_point((*p).into()).into());
+            let n = m.transform_vector((*n).into());
+            normals.push(if n.magnitude2() > 0.0 {
+                n.normalize().into()
+            } else {
+                n.into()
+            });
+        }
+
+        ModelData {
+            matrix: self.data.matrix,
+            positions,
+            indices: self.data.indices.clone(),
+            normals,
+            tex_coords: self.data.tex_coords.clone(),
+            ranges: self.data.ranges.clone(),
+        }
+    }
+}
+
+impl Doop {
+    ///The joints of a skin and every clip that moves them.
+    ///Channels that move nodes that aren't joints of this skin are left out.
+    ///Cubic spline channels are played back linearly between their keyframes.
+    pub fn skeleton(&self, skin: usize) -> Result<Skeleton, LoadError> {
+        let s = self
+            .document
+            .skins()
+            .nth(skin)
+            .ok_or(LoadError::NoSkin(skin))?;
+        let get = |buffer: gltf::Buffer| Some(&*self.buffers[buffer.index()]);
+
+        let nodes: Vec<_> = self.document.nodes().collect();
+        let mut parent_of = vec![None; nodes.len()];
+        for n in nodes.iter() {
+            for c in n.children() {
+                parent_of[c.index()] = Some(n.index());
+            }
+        }
+
+        let joint_nodes: Vec<_> = s.joints().map(|a| a.index()).collect();
+        let inverse_binds: Vec<[[f32; 4]; 4]> = match s.reader(get).read_inverse_bind_matrices() {
+            Some(a) => a.collect(),
+            None => vec![Matrix4::identity().into(); joint_nodes.len()],
+        };
+        if inverse_binds.len() < joint_nodes.len() {
+            return Err(LoadError::BadSkin(skin));
+        }
+
+        let mut joints = Vec::with_capacity(joint_nodes.len());
+        for (k, &node) in joint_nodes.iter().enumerate() {
+            let mut parent = None;
+            let mut offset = Matrix4::identity();
+            let mut cur = parent_of[node];
+            let mut steps = 0;
+            while let Some(p) = cur {
+                if let Some(j) = joint_nodes.iter().position(|&a| a == p) {
+                    parent = Some(j);
+                    break;
+                }
+                offset = Trs::from_node(&nodes[p]).matrix() * offset;
+                cur = parent_of[p];
+                steps += 1;
+                if steps > nodes.len() {
+                    return Err(LoadError::BadSkin(skin));
+                }
+            }
+
+            joints.push(Joint {
+                name: nodes[node].name().map(|a| a.to_string()),
+                node,
+                parent,
+                offset,
+                rest: Trs::from_node(&nodes[node]),
+                inverse_bind: inverse_binds[k].into(),
+            });
+        }
+
+        //Count the joint ancestors of each joint so parents can go first.
+        let mut depth = vec![0; joints.len()];
+        for (k, d) in depth.iter_mut().enumerate() {
+            let mut cur = joints[k].parent;
+            while let Some(p) = cur {
+                *d += 1;
+                if *d > joints.len() {
+                    return Err(LoadError::BadSkin(skin));
+                }
+                cur = joints[p].parent;
+            }
+        }
+        let mut order: Vec<_> = (0..joints.len()).collect();
+        order.sort_by_key(|&k| depth[k]);
+
+        let clips = self
+            .document
+            .animations()
+            .map(|a| self.clip(a, &joint_nodes))
+            .collect::<Result<_, _>>()?;
+
+        Ok(Skeleton {
+            joints,
+            clips,
+            order,
+        })
+    }
+
+    fn clip(&self, anim: gltf::Animation, joint_nodes: &[usize]) -> Result<Clip, LoadError> {
+        use gltf::animation::util::ReadOutputs;
+        let animation = anim.index();
+        let get = |buffer: gltf::Buffer| Some(&*self.buffers[buffer.index()]);
+
+        let mut channels = vec![];
+        let mut duration: f32 = 0.0;
+        for c in anim.channels() {
+            let channel = c.index();
+            let bad = LoadError::BadAnimation { animation, channel };
+
+            let Some(joint) = joint_nodes
+                .iter()
+                .position(|&a| a == c.target().node().index())
+            else {
+                continue;
+            };
+
+            let reader = c.reader(get);
+            let times: Vec<f32> = match reader.read_inputs() {
+                Some(a) => a.collect(),
+                None => return Err(bad),
+            };
+
+            let (interpolation, stride) = match c.sampler().interpolation() {
+                gltf::animation::Interpolation::Linear => (Interpolation::Linear, 1),
+                gltf::animation::Interpolation::Step => (Interpolation::Step, 1),
+                //Keep the values and leave out the tangents either side of them.
+                gltf::animation::Interpolation::CubicSpline => (Interpolation::Linear, 3),
+            };
+            let pick = |a: Vec<[f32; 3]>| -> Vec<Vector3<f32>> {
+                a.into_iter()
+                    .skip(stride / 2)
+                    .step_by(stride)
+                    .map(|a| a.into())
+                    .collect()
+            };
+
+            let keyframes = match reader.read_outputs() {
+                Some(ReadOutputs::Translations(a)) => Keyframes::Translation(pick(a.collect())),
+                Some(ReadOutputs::Scales(a)) => Keyframes::Scale(pick(a.collect())),
+                Some(ReadOutputs::Rotations(a)) => Keyframes::Rotation(
+                    a.into_f32()
+                        .skip(stride / 2)
+                        .step_by(stride)
+                        .map(|[x, y, z, w]| Quaternion::new(w, x, y, z))
+                        .collect(),
+                ),
+                //Morph targets aren't supported.
+                Some(ReadOutputs::MorphTargetWeights(_)) => continue,
+                None => return Err(bad),
+            };
+
+            let len = match &keyframes {
+                Keyframes::Translation(a) | Keyframes::Scale(a) => a.len(),
+                Keyframes::Rotation(a) => a.len(),
+            };
+            if times.is_empty() || len != times.len() || times.windows(2).any(|a| a[0] > a[1]) {
+                return Err(bad);
+            }
+
+            duration = duration.max(*times.last().unwrap());
+            channels.push(Channel {
+                joint,
+                interpolation,
+                times,
+                keyframes,
+            });
+        }
+
+        Ok(Clip {
+            name: anim.name().map(|a| a.to_string()),
+            duration,
+            channels,
+        })
+    }
+
+    ///The meshes that use a skin, merged together in their bind pose.
+    pub fn skin(&self, skin: usize) -> Result<Skin, LoadError> {
+        let get = |buffer: gltf::Buffer| Some(&*self.buffers[buffer.index()]);
+        let joint_count = self
+            .document
+            .skins()
+            .nth(skin)
+            .ok_or(LoadError::NoSkin(skin))?
+            .joints()
+            .count();
+
+        let mut parts = vec![];
+        let mut joints = vec![];
+        let mut weights = vec![];
+        for node in self.document.nodes() {
+            if node.skin().map(|a| a.index()) != Some(skin) {
+                continue;
+            }
+            let Some(mesh) = node.mesh() else {
+                continue;
+            };
+            for p in mesh.primitives() {
+                let primitive = p.index();
+                let reader = p.reader(get);
+                let no_weights = || LoadError::NoWeights {
+                    mesh: mesh.index(),
+                    primitive,
+                };
+
+                let j: Vec<_> = reader
+                    .read_joints(0)
+                    .ok_or_else(no_weights)?
+                    .into_u16()
+                    .collect();
+                let w: Vec<_> = reader
+                    .read_weights(0)
+                    .ok_or_else(no_weights)?
+                    .into_f32()
+                    .collect();
+
+                //Skinned meshes ignore the transform of their node.
+                let part = self.part(mesh.index(), p, Matrix4::identity())?;
+                if j.len() != part.data.positions.len() || w.len() != j.len() {
+                    return Err(no_weights());
+                }
+                if let Some(&joint) = j.iter().flatten().find(|&&a| a as usize >= joint_count) {
+                    return Err(LoadError::JointOutOfRange {
+                        mesh: mesh.index(),
+                        primitive,
+                        joint,
+                    });
+                }
+                joints.extend(j);
+                weights.extend(w);
+                parts.push(part);
+            }
+        }
+
+        if parts.is_empty() {
+            return Err(LoadError::NoMeshes);
+        }
+        Ok(Skin {
+            data: merge(&parts)?,
+            joints,
+            weights,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::{Deg, Rotation3};
+
+    fn close(a: impl Into<[f32; 3]>, b: [f32; 3]) -> bool {
+        (Vector3::from(a.into()) - Vector3::from(b)).magnitude() < 1e-4
+    }
+
+    fn channel(interpolation: Interpolation, times: &[f32], keyframes: Keyframes) -> Channel {
+        Channel {
+            joint: 0,
+            interpolation,
+            times: times.to_vec(),
+            keyframes,
+        }
+    }
+
+    fn translations(a: &[[f32; 3]]) -> Keyframes {
+        Keyframes::Translation(a.iter().map(|&a| a.into()).collect())
+    }
+
+    fn sample(c: &Channel, time: f32) -> Trs {
+        let mut trs = Trs::identity();
+        c.sample(time, &mut trs);
+        trs
+    }
+
+    #[test]
+    fn linear_sampling() {
+        let c = channel(
+            Interpolation::Linear,
+            &[1.0, 2.0, 4.0],
+            translations(&[[0.0, 0.0, 0.0], [2.0, 0.0, 0.0], [2.0, 4.0, 0.0]]),
+        );
+        assert!(close(sample(&c, 0.0).translation, [0.0, 0.0, 0.0]));
+        assert!(close(sample(&c, 1.5).translation, [1.0, 0.0, 0.0]));
+        assert!(close(sample(&c, 2.0).translation, [2.0, 0.0, 0.0]));
+        assert!(close(sample(&c, 3.0).translation, [2.0, 2.0, 0.0]));
+        assert!(close(sample(&c, 9.0).translation, [2.0, 4.0, 0.0]));
+    }
+
+    #[test]
+    fn step_sampling() {
+        let c = channel(
+            Interpolation::Step,
+            &[0.0, 1.0],
+            translations(&[[0.0, 0.0, 0.0], [5.0, 0.0, 0.0]]),
+        );
+        assert!(close(sample(&c, 0.99).translation, [0.0, 0.0, 0.0]));
+        assert!(close(sample(&c, 1.0).translation, [5.0, 0.0, 0.0]));
+        assert!(close(sample(&c, 1.5).translation, [5.0, 0.0, 0.0]));
+    }
+
+    #[test]
+    fn rotation_takes_the_short_way() {
+        let a = Quaternion::from_angle_z(Deg(0.0));
+        let b = Quaternion::from_angle_z(Deg(90.0));
+        for b in [b, -b] {
+            let c = channel(
+                Interpolation::Linear,
+                &[0.0, 1.0],
+                Keyframes::Rotation(vec![a, b]),
+            );
+            let r = sample(&c, 0.5).rotation;
+            let p = r * Vector3::new(1.0, 0.0, 0.0);
+            let h = 0.5f32.sqrt();
+            assert!(close(p, [h, h, 0.0]), "{:?}", p);
+        }
+    }
+
+    #[test]
+    fn empty_channel_changes_nothing() {
+        let c = channel(Interpolation::Linear, &[], translations(&[]));
+        assert_eq!(sample(&c, 1.0), Trs::identity());
+    }
+
+    //Pack a json document and a binary chunk into a glb file.
+    fn glb(json: &str, bin: &[u8]) -> Vec<u8> {
+        let mut json = json.as_bytes().to_vec();
+        json.resize(json.len().next_multiple_of(4), b' ');
+        let mut bin = bin.to_vec();
+        bin.resize(bin.len().next_multiple_of(4), 0);
+        let total = 12 + 8 + json.len() + 8 + bin.len();
+
+        let mut out = vec![];
+        out.extend(b"glTF");
+        out.extend(2u32.to_le_bytes());
+        out.extend((total as u32).to_le_bytes());
+        out.extend((json.len() as u32).to_le_bytes());
+        out.extend(b"JSON");
+        out.extend(json);
+        out.extend((bin.len() as u32).to_le_bytes());
+        out.extend(b"BIN\0");
+        out.extend(bin);
+        out
+    }
+
+    fn floats(a: &[f32]) -> Vec<u8> {
+        a.iter().flat_map(|a| a.to_le_bytes()).collect()
+    }
+
+    //An arm of two joints. The lower joint sits one up from the root and bends
+    //90 degrees over the "wave" clip. The root sits under a node that moves it
+    //two along x. One triangle is stuck to the lower joint.
+    fn arm() -> Doop {
+        let mut bin = vec![];
+        //0: positions
+        bin.extend(floats(&[2.0, 1.0, 0.0, 3.0, 1.0, 0.0, 2.0, 2.0, 0.0]));
+        //36: joints
+        bin.extend([1u8, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0]);
+        //48: weights
+        bin.extend(floats(&[1.0, 0.0, 0.0, 0.0].repeat(3)));
+        //96: inverse bind matrices
+        let ib0: Matrix4<f32> = Matrix4::from_translation(Vector3::new(-2.0, 0.0, 0.0));
+        let ib1: Matrix4<f32> = Matrix4::from_translation(Vector3::new(-2.0, -1.0, 0.0));
+        for m in [ib0, ib1] {
+            let m: &[f32; 16] = m.as_ref();
+            bin.extend(floats(m));
+        }
+        //224: times
+        bin.extend(floats(&[0.0, 2.0]));
+        //232: rotations
+        let q = Quaternion::from_angle_z(Deg(90.0));
+        bin.extend(floats(&[0.0, 0.0, 0.0, 1.0, q.v.x, q.v.y, q.v.z, q.s]));
+        //264: times for the step channel
+        bin.extend(floats(&[0.0, 1.0]));
+        //272: translations for the step channel
+        bin.extend(floats(&[2.0, 0.0, 0.0, 2.0, 0.0, 0.0]));
+
+        let json = format!(
+            r#"{{
+            "asset": {{"version": "2.0"}},
+            "buffers": [{{"byteLength": {len}}}],
+            "bufferViews": [
+                {{"buffer": 0, "byteOffset": 0, "byteLength": 36}},
+                {{"buffer": 0, "byteOffset": 36, "byteLength": 12}},
+                {{"buffer": 0, "byteOffset": 48, "byteLength": 48}},
+                {{"buffer": 0, "byteOffset": 96, "byteLength": 128}},
+                {{"buffer": 0, "byteOffset": 224, "byteLength": 8}},
+                {{"buffer": 0, "byteOffset": 232, "byteLength": 32}},
+                {{"buffer": 0, "byteOffset": 264, "byteLength": 8}},
+                {{"buffer": 0, "byteOffset": 272, "byteLength": 24}}
+            ],
+            "accessors": [
+                {{"bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3", "min": [2, 1, 0], "max": [3, 2, 0]}},
+                {{"bufferView": 1, "componentType": 5121, "count": 3, "type": "VEC4"}},
+                {{"bufferView": 2, "componentType": 5126, "count": 3, "type": "VEC4"}},
+                {{"bufferView": 3, "componentType": 5126, "count": 2, "type": "MAT4"}},
+                {{"bufferView": 4, "componentType": 5126, "count": 2, "type": "SCALAR", "min": [0], "max": [2]}},
+                {{"bufferView": 5, "componentType": 5126, "count": 2, "type": "VEC4"}},
+                {{"bufferView": 6, "componentType": 5126, "count": 2, "type": "SCALAR", "min": [0], "max": [1]}},
+                {{"bufferView": 7, "componentType": 5126, "count": 2, "type": "VEC3"}}
+            ],
+            "meshes": [{{"primitives": [{{"attributes": {{"POSITION": 0, "JOINTS_0": 1, "WEIGHTS_0": 2}}}}]}}],
+            "skins": [{{"joints": [1, 2], "inverseBindMatrices": 3}}],
+            "nodes": [
+                {{"name": "armature", "translation": [2, 0, 0], "children": [1]}},
+                {{"name": "shoulder", "children": [2]}},
+                {{"name": "elbow", "translation": [0, 1, 0]}},
+                {{"mesh": 0, "skin": 0}}
+            ],
+            "scenes": [{{"nodes": [0, 3]}}],
+            "animations": [{{
+                "name": "wave",
+                "samplers": [
+                    {{"input": 4, "output": 5}},
+                    {{"input": 6, "output": 7, "interpolation": "STEP"}}
+                ],
+                "channels": [
+                    {{"sampler": 0, "target": {{"node": 2, "path": "rotation"}}}},
+                    {{"sampler": 1, "target": {{"node": 0, "path": "translation"}}}}
+                ]
+            }}]
+        }}"#,
+            len = bin.len()
+        );
+        load_glb(&glb(&json, &bin)).unwrap()
+    }
+
+    #[test]
+    fn loads_skeleton() {
+        let s = arm().skeleton(0).unwrap();
+        assert_eq!(s.joints.len(), 2);
+        assert_eq!(s.joints[0].name.as_deref(), Some("shoulder"));
+        assert_eq!(s.joints[0].parent, None);
+        assert_eq!(s.joints[1].parent, Some(0));
+        assert_eq!(s.clip("wave"), Some(0));
+        assert_eq!(s.clip("walk"), None);
+
+        //The channel on the armature isn't a joint so it is left out.
+        let clip = &s.clips[0];
+        assert_eq!(clip.channels.len(), 1);
+        assert_eq!(clip.duration, 2.0);
+
+        let g = s.globals(&s.rest_pose());
+        assert!(close(
+            g[1].transform_point([0.0; 3].into()),
+            [2.0, 1.0, 0.0]
+        ));
+
+        assert!(matches!(arm().skeleton(1), Err(LoadError::NoSkin(1))));
+    }
+
+    #[test]
+    fn bind_pose_leaves_mesh_alone() {
+        let doop = arm();
+        let s = doop.skeleton(0).unwrap();
+        let skin = doop.skin(0).unwrap();
+        let m = s.joint_matrices(&s.rest_pose());
+        assert_eq!(skin.apply(&m).positions, skin.data.positions);
+    }
+
+    #[test]
+    fn rejects_missing_joints() {
+        assert!(matches!(arm().skin(1), Err(LoadError::NoSkin(1))));
+
+        //The second vertex is moved by a third joint the skin doesn't have.
+        let mut doop = arm();
+        doop.buffers[0].0[36 + 4] = 2;
+        assert!(matches!(
+            doop.skin(0),
+            Err(LoadError::JointOutOfRange {
+                mesh: 0,
+                primitive: 0,
+                joint: 2
+            })
+        ));
+    }
+
+    #[test]
+    fn plays_clip() {
+        let doop = arm();
+        let s = doop.skeleton(0).unwrap();
+        let skin = doop.skin(0).unwrap();
+
+        //Halfway through the lower joint has turned 45 degrees.
+        let pose = s.sample(0, 1.0, false);
+        let moved = skin.apply(&s.joint_matrices(&pose));
+        let h = 0.5f32.sqrt();
+        assert!(close(moved.positions[0], [2.0, 1.0, 0.0]));
+        assert!(close(moved.positions[1], [2.0 + h, 1.0 + h, 0.0]));
+
+        //At the end the point above the joint points along -x.
+        let pose = s.sample(0, 2.0, false);
+        let moved = skin.apply(&s.joint_matrices(&pose));
+        assert!(close(moved.positions[2], [1.0, 1.0, 0.0]));
+
+        //Past the end it holds or wraps around.
+        assert_eq!(s.sample(0, 5.0, false), s.sample(0, 2.0, false));
+        let a = s.sample(0, 2.5, true).locals[1].rotation;
+        let b = s.sample(0, 0.5, false).locals[1].rotation;
+        assert!((a - b).magnitude() < 1e-5);
+    }
+}
@@ -0,0 +1,493 @@
+//! Lays out text with a bitmap font as one quad per glyph.
+//!
+//! Sizes are in lines, so a line of text is one unit high whatever the font. y goes up.
+//! The first line sits between y 0 and 1 and the lines after it go down from there.
+use super::*;
+use std::collections::HashMap;
+
+///Where a glyph is in the font texture and where it goes relative to the pen.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Glyph {
+    ///Top left and bottom right in texture coordinates.
+    pub uv: [[f32; 2]; 2],
+    ///Bottom left and top right of the quad, from the bottom of the line at the pen.
+    pub min: [f32; 2],
+    pub max: [f32; 2],
+    ///How far the pen moves after the glyph.
+    pub advance: f32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FontError {
+    ///A line of a font file that couldn't be read, counting from 1.
+    Syntax(usize),
+    ///There was no `common` line with the line height and texture size.
+    MissingCommon,
+    ///Glyphs spread over more than one texture.
+    Pages(u32),
+}
+
+impl std::fmt::Display for FontError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FontError::Syntax(a) => write!(f, "can't read line {} of the font", a),
+            FontError::MissingCommon => write!(f, "the font has no common line"),
+            FontError::Pages(a) => write!(f, "the font uses {} textures, only one is supported", a),
+        }
+    }
+}
+
+impl std::error::Error for FontError {}
+
+#[derive(Debug, Clone)]
+pub struct Font {
+    glyphs: HashMap<char, Glyph>,
+    kerning: HashMap<(char, char), f32>,
+    ///Drawn for characters the font doesn't have. They are left out if this is missing too.
+    pub fallback: Option<char>,
+    ///From the top of one line to the top of the next.
+    pub line_spacing: f32,
+}
+
+impl Default for Font {
+    fn default() -> Font {
+        Font {
+            glyphs: HashMap::new(),
+            kerning: HashMap::new(),
+            fallback: None,
+            line_spacing: 1.0,
+        }
+    }
+}
+
+impl Font {
+    pub fn new() -> Font {
+        Font::default()
+    }
+
+    pub fn insert(&mut self, c: char, glyph: Glyph) {
+        self.glyphs.insert(c, glyph);
+    }
+
+    ///Move `right` by `amount` when it comes straight after `left`.
+    pub fn set_kerning(&mut self, left: char, right: char, amount: f32) {
+        self.kerning.insert((left, right), amount);
+    }
+
+    ///The glyph drawn for `c`, which is the fallback one if the font doesn't have it.
+    pub fn glyph(&self, c: char) -> Option<&Glyph> {
+        self.glyphs
+            .get(&c)
+            .or_else(|| self.fallback.and_then(|a| self.glyphs.get(&a)))
+    }
+
+    pub fn kerning(&self, left: char, right: char) -> f32 {
+        self.kerning.get(&(left, right)).copied().unwrap_or(0.0)
+    }
+
+    ///A font drawn in a `columns` by `rows` grid of equal cells, like a code page.
+    ///`cells` says which character is in which cell, counting along the rows from the top left.
+    ///
+    ///With `proportional` each glyph is cut down to its visible pixels and gets a little
+    ///space after it, otherwise every glyph takes up its whole cell. Empty cells are
+    ///spaces.
+    pub fn grid(
+        texture: &Img,
+        columns: u32,
+        rows: u32,
+        cells: impl IntoIterator<Item = (u32, char)>,
+        proportional: bool,
+    ) -> Font {
+        let cw = texture.width / columns;
+        let ch = texture.height / rows;
+        let (tw, th) = (texture.width as f32, texture.height as f32);
+        //Everything is measured in lines, which are as high as a cell.
+        let unit = |px: u32| px as f32 / ch as f32;
+
+        let mut font = Font::new();
+        for (k, c) in cells {
+            let (cx, cy) = (k % columns * cw, k / columns * ch);
+
+            let (x0, x1) = if proportional {
+                let visible = |x: u32| {
+                    (0..ch).any(|y| {
+                        texture.data[(((cy + y) * texture.width + cx + x) * 4 + 3) as usize] > 0
+                    })
+                };
+                match (
+                    (0..cw).find(|&x| visible(x)),
+                    (0..cw).rfind(|&x| visible(x)),
+                ) {
+                    (Some(a), Some(b)) => (a, b + 1),
+                    //A third of a cell is about as wide as a space in most fonts.
+                    _ => (0, cw / 3),
+                }
+            } else {
+                (0, cw)
+            };
+            let gap = if proportional { unit(cw / 12) } else { 0.0 };
+
+            font.insert(
+                c,
+                Glyph {
+                    uv: [
+                        [(cx + x0) as f32 / tw, cy as f32 / th],
+                        [(cx + x1) as f32 / tw, (cy + ch) as f32 / th],
+                    ],
+                    min: [0.0, 0.0],
+                    max: [unit(x1 - x0), 1.0],
+                    advance: unit(x1 - x0) + gap,
+                },
+            );
+        }
+        font
+    }
+
+    ///Reads the text format of the AngelCode bitmap font generator. Fonts have to fit
+    ///in one texture.
+    pub fn bmfont(file: &str) -> Result<Font, FontError> {
+        let mut font = Font::new();
+        //Line height and texture size.
+        let mut common = None;
+        let mut glyphs = vec![];
+        let mut kerning = vec![];
+
+        for (k, line) in file.lines().enumerate() {
+            let mut words = line.split_whitespace();
+            let Some(tag) = words.next() else {
+                continue;
+            };
+            let fields: HashMap<&str, &str> = words.filter_map(|a| a.split_once('=')).collect();
+            let num = |name: &str| -> Result<f32, FontError> {
+                fields
+                    .get(name)
+                    .and_then(|a| a.parse().ok())
+                    .ok_or(FontError::Syntax(k + 1))
+            };
+
+            match tag {
+                "common" => {
+                    let pages = num("pages").unwrap_or(1.0) as u32;
+                    if pages > 1 {
+                        return Err(FontError::Pages(pages));
+                    }
+                    common = Some((num("lineHeight")?, num("scaleW")?, num("scaleH")?));
+                }
+                "char" => {
+                    let id = num("id")? as u32;
+                    let Some(c) = char::from_u32(id) else {
+                        return Err(FontError::Syntax(k + 1));
+                    };
+                    let names = [
+                        "x", "y", "width", "height", "xoffset", "yoffset", "xadvance",
+                    ];
+                    let mut out = [0.0; 7];
+                    for (o, name) in out.iter_mut().zip(names) {
+                        *o = num(name)?;
+                    }
+                    glyphs.push((c, out));
+                }
+                "kerning" => {
+                    let pair = [num("first")?, num("second")?].map(|a| char::from_u32(a as u32));
+                    let [Some(a), Some(b)] = pair else {
+                        return Err(FontError::Syntax(k + 1));
+                    };
+                    kerning.push((a, b, num("amount")?));
+                }
+                _ => {}
+            }
+        }
+
+        let (lh, tw, th) = common.ok_or(FontError::MissingCommon)?;
+        for (c, [x, y, w, h, xo, yo, xa]) in glyphs {
+            //Offsets are down from the top of the line.
+            let top = 1.0 - yo / lh;
+            font.insert(
+                c,
+                Glyph {
+                    uv: [[x / tw, y / th], [(x + w) / tw, (y + h) / th]],
+                    min: [xo / lh, top - h / lh],
+                    max: [(xo + w) / lh, top],
+                    advance: xa / lh,
+                },
+            );
+        }
+        for (a, b, amount) in kerning {
+            font.set_kerning(a, b, amount / lh);
+        }
+        if font.glyphs.contains_key(&'?') {
+            font.fallback = Some('?');
+        }
+        Ok(font)
+    }
+
+    fn line_width(&self, line: &str) -> f32 {
+        let mut pen = 0.0;
+        let mut last = None;
+        for c in line.chars().filter(|&c| c != '\r') {
+            if let Some(g) = self.glyph(c) {
+                if let Some(l) = last {
+                    pen += self.kerning(l, c);
+                }
+                pen += g.advance;
+                last = Some(c);
+            }
+        }
+        pen
+    }
+
+    ///Width of the widest line and height of all of them.
+    pub fn measure(&self, text: &str) -> [f32; 2] {
+        let lines = text.split('\n');
+        let count = lines.clone().count();
+        let width = lines.map(|a| self.line_width(a)).fold(0.0, f32::max);
+        [width, (count - 1) as f32 * self.line_spacing + 1.0]
+    }
+}
+
+///Where each line goes relative to x 0.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Align {
+    ///Starts at 0.
+    #[default]
+    Left,
+    Centre,
+    ///Ends at 0.
+    Right,
+}
+
+///A model of `text` with one quad per glyph, drawn in the blend pass. Lines are split at
+///`\n`. Texture coordinates are into the font texture.
+pub fn layout(font: &Font, text: &str, align: Align) -> ModelData {
+    let mut positions = vec![];
+    let mut tex_coords = vec![];
+    let mut indices = vec![];
+
+    for (k, line) in text.split('\n').enumerate() {
+        let width = font.line_width(line);
+        let mut pen = match align {
+            Align::Left => 0.0,
+            Align::Centre => -width / 2.0,
+            Align::Right => -width,
+        };
+        let bottom = -(k as f32) * font.line_spacing;
+        let mut last = None;
+
+        for c in line.chars().filter(|&c| c != '\r') {
+            let Some(g) = font.glyph(c) else {
+                continue;
+            };
+            if let Some(l) = last {
+                pen += font.kerning(l, c);
+            }
+            last = Some(c);
+
+            let [x0, y0] = [pen + g.min[0], bottom + g.min[1]];
+            let [x1, y1] = [pen + g.max[0], bottom + g.max[1]];
+            let [[u0, v0], [u1, v1]] = g.uv;
+
+            let start = positions.len() as u32;
+            positions.extend([[x0, y1, 0.0], [x1, y1, 0.0], [x0, y0, 0.0], [x1, y0, 0.0]]);
+            tex_coords.extend([[u0, v0], [u1, v0], [u0, v1], [u1, v1]]);
+            indices.extend([0, 1, 2, 2, 1, 3].map(|a| start + a));
+
+            pen += g.advance;
+        }
+    }
+
+    ModelData {
+        matrix: cgmath::Matrix4::from_scale(1.0),
+        normals: positions.iter().map(|_| [0.0, 0.0, 1.0]).collect(),
+        positions,
+        tex_coords,
+        ranges: vec![DrawRange {
+            start: 0,
+            count: indices.len(),
+            texture: None,
+            alpha: AlphaMode::Blend,
+        }],
+        indices: Some(Indices::new(indices)),
+    }
+}
+
+///Keeps what was made from each piece of text so it is only laid out once. Text that
+///isn't used for a whole frame is dropped, so numbers that keep changing don't pile up.
+#[derive(Debug)]
+pub struct TextCache<T> {
+    entries: HashMap<(String, Align), (T, bool)>,
+}
+
+impl<T> Default for TextCache<T> {
+    fn default() -> TextCache<T> {
+        TextCache {
+            entries: HashMap::new(),
+        }
+    }
+}
+
+impl<T> TextCache<T> {
+    pub fn new() -> TextCache<T> {
+        TextCache::default()
+    }
+
+    ///What was made for `text` before, or what `make` makes for it now.
+    pub fn get_or_insert_with(&mut self, text: &str, align: Align, make: impl FnOnce() -> T) -> &T {
+        let entry = self
+            .entries
+            .entry((text.to_string(), align))
+            .or_insert_with(|| (make(), false));
+        entry.1 = true;
+        &entry.0
+    }
+
+    ///Drops everything that wasn't used since the last call. Call once a frame.
+    pub fn end_frame(&mut self) {
+        self.entries
+            .retain(|_, a| std::mem::replace(&mut a.1, false));
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //A 4 by 1 grid of 4 pixel cells. Cell 0 is full, cell 1 has one column at x 1, the
+    //rest are empty.
+    fn grid_texture() -> Img {
+        let mut data = vec![0; 16 * 4 * 4];
+        for y in 0..4 {
+            for x in 0..4 {
+                data[(y * 16 + x) * 4 + 3] = 255;
+            }
+            data[(y * 16 + 5) * 4 + 3] = 255;
+        }
+        Img {
+            width: 16,
+            height: 4,
+            data,
+        }
+    }
+
+    fn mono() -> Font {
+        let tex = grid_texture();
+        Font::grid(&tex, 4, 1, [(0, 'a'), (1, 'b'), (2, ' ')], false)
+    }
+
+    #[test]
+    fn grid_fonts() {
+        let font = mono();
+        let a = font.glyph('a').unwrap();
+        assert_eq!(a.uv, [[0.0, 0.0], [0.25, 1.0]]);
+        assert_eq!((a.max, a.advance), ([1.0, 1.0], 1.0));
+        assert!(font.glyph('z').is_none());
+
+        let tex = grid_texture();
+        let font = Font::grid(&tex, 4, 1, [(0, 'a'), (1, 'b'), (2, ' ')], true);
+        let b = font.glyph('b').unwrap();
+        assert_eq!(b.uv, [[5.0 / 16.0, 0.0], [6.0 / 16.0, 1.0]]);
+        assert_eq!(b.max, [0.25, 1.0]);
+        assert_eq!(font.glyph(' ').unwrap().max, [0.25, 1.0]);
+    }
+
+    #[test]
+    fn lays_out_lines() {
+        let font = mono();
+        let model = layout(&font, "ab\na", Align::Left);
+        assert_eq!(model.positions.len(), 12);
+        assert_eq!(model.indices.as_ref().unwrap().len(), 18);
+        assert_eq!(model.ranges[0].count, 18);
+        assert_eq!(model.pass(), Pass::Blend);
+
+        //Top left of each glyph.
+        assert_eq!(model.positions[0], [0.0, 1.0, 0.0]);
+        assert_eq!(model.positions[4], [1.0, 1.0, 0.0]);
+        assert_eq!(model.positions[8], [0.0, 0.0, 0.0]);
+        assert_eq!(model.positions[11], [1.0, -1.0, 0.0]);
+        assert_eq!(font.measure("ab\na"), [2.0, 2.0]);
+    }
+
+    #[test]
+    fn aligns_lines() {
+        let font = mono();
+        let centre = layout(&font, "ab\na", Align::Centre);
+        assert_eq!(centre.positions[0][0], -1.0);
+        assert_eq!(centre.positions[8][0], -0.5);
+
+        let right = layout(&font, "ab", Align::Right);
+        assert_eq!(right.positions[7][0], 0.0);
+    }
+
+    #[test]
+    fn kerning_and_fallback() {
+        let mut font = mono();
+        font.set_kerning('a', 'b', -0.25);
+        let model = layout(&font, "ab", Align::Left);
+        assert_eq!(model.positions[4][0], 0.75);
+        assert_eq!(font.measure("ab")[0], 1.75);
+
+        //Unknown characters are left out until there is a fallback.
+        assert!(layout(&font, "zé", Align::Left).positions.is_empty());
+        font.fallback = Some('b');
+        let model = layout(&font, "zé", Align::Left);
+        assert_eq!(model.positions.len(), 8);
+        assert_eq!(model.tex_coords[0], font.glyph('b').unwrap().uv[0]);
+    }
+
+    #[test]
+    fn reads_bmfont() {
+        let file = "info face=\"Test\" size=32\n\
+            common lineHeight=32 base=26 scaleW=256 scaleH=128 pages=1 packed=0\n\
+            page id=0 file=\"test.png\"\n\
+            chars count=2\n\
+            char id=65 x=0 y=0 width=16 height=16 xoffset=0 yoffset=8 xadvance=16 page=0\n\
+            char id=63 x=16 y=0 width=8 height=32 xoffset=4 yoffset=0 xadvance=8 page=0\n\
+            kernings count=1\n\
+            kerning first=65 second=65 amount=-4\n";
+        let font = Font::bmfont(file).unwrap();
+        let a = font.glyph('A').unwrap();
+        assert_eq!(a.uv, [[0.0, 0.0], [16.0 / 256.0, 16.0 / 128.0]]);
+        assert_eq!((a.min, a.max), ([0.0, 0.25], [0.5, 0.75]));
+        assert_eq!(a.advance, 0.5);
+        assert_eq!(font.kerning('A', 'A'), -0.125);
+        assert_eq!(font.fallback, Some('?'));
+        assert_eq!(font.glyph('x').unwrap().min, [0.125, 0.0]);
+
+        assert_eq!(
+            Font::bmfont("char id=65").unwrap_err(),
+            FontError::Syntax(1)
+        );
+        assert_eq!(Font::bmfont("").unwrap_err(), FontError::MissingCommon);
+        let two = "common lineHeight=32 scaleW=256 scaleH=128 pages=2";
+        assert_eq!(Font::bmfont(two).unwrap_err(), FontError::Pages(2));
+    }
+
+    #[test]
+    fn cache_drops_unused_text() {
+        let mut cache = TextCache::new();
+        let mut made = 0;
+        for _ in 0..2 {
+            cache.get_or_insert_with("5", Align::Left, || {
+                made += 1;
+                made
+            });
+        }
+        assert_eq!(made, 1);
+        cache.get_or_insert_with("5", Align::Right, || 0);
+        assert_eq!(cache.len(), 2);
+
+        cache.end_frame();
+        cache.get_or_insert_with("5", Align::Left, || 0);
+        cache.end_frame();
+        assert_eq!(cache.len(), 1);
+        cache.end_frame();
+        assert!(cache.is_empty());
+    }
+}
@@ -0,0 +1,395 @@
+use super::*;
+use crate::state::{GameHandle, Stuff};
+
+///Special actions a unit can take instead of moving or attacking.
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Ability {
+    ///Give health back to an adjacent ally.
+    Heal,
+    ///Shove an adjacent enemy back one cell. Damages it if it has nowhere to go.
+    Push,
+    ///Dash to a free cell and rally for the next attack.
+    Charge,
+    ///Poison an adjacent enemy so it loses health at the start of its turns.
+    Poison,
+    ///Pave an adjacent cell. Costs resources as well as stamina.
+    BuildRoad,
+}
+
+const HEAL_AMOUNT: i8 = 3;
+const PUSH_DAMAGE: i8 = 2;
+
+//Effects count down at the end of their owner's turns. One turn on an enemy lasts
+//through its next turn, an ally needs two to still have it on the enemy's turn.
+const CHARGE_RALLY_TURNS: u8 = 2;
+const HEAL_SHIELD_TURNS: u8 = 2;
+const PUSH_SLOW_TURNS: u8 = 1;
+const PUSH_STUN_TURNS: u8 = 1;
+const POISON_TURNS: u8 = 3;
+
+impl Ability {
+    pub const ALL: [Ability; 5] = [
+        Ability::Heal,
+        Ability::Push,
+        Ability::Charge,
+        Ability::Poison,
+        Ability::BuildRoad,
+    ];
+
+    pub fn cost(&self) -> MoveUnit {
+        match self {
+            Ability::Heal => MoveUnit(2),
+            Ability::Push => MoveUnit(2),
+            Ability::Charge => MoveUnit(4),
+            Ability::Poison => MoveUnit(2),
+            Ability::BuildRoad => terrain::ROAD_COST,
+        }
+    }
+
+    ///Resources taken from the team on use.
+    pub fn resource_cost(&self, rules: &economy::EconomyRules) -> i16 {
+        match self {
+            Ability::BuildRoad => rules.road_cost,
+            _ => 0,
+        }
+    }
+
+    //Range in the same units as stamina. 2 is just the adjacent cells.
+    fn range(&self) -> MoveUnit {
+        match self {
+            Ability::Heal => MoveUnit(2),
+            Ability::Push => MoveUnit(2),
+            Ability::Charge => MoveUnit(6),
+            Ability::Poison => MoveUnit(2),
+            Ability::BuildRoad => MoveUnit(2),
+        }
+    }
+
+    ///Whether using this counts as the units attack for the turn.
+    pub fn uses_attack(&self) -> bool {
+        match self {
+            Ability::Heal | Ability::Push | Ability::Poison => true,
+            Ability::Charge | Ability::BuildRoad => false,
+        }
+    }
+
+    pub fn button_id(&self) -> &'static str {
+        match self {
+            Ability::Heal => "ability_heal",
+            Ability::Push => "ability_push",
+            Ability::Charge => "ability_charge",
+            Ability::Poison => "ability_poison",
+            Ability::BuildRoad => "ability_road",
+        }
+    }
+
+    pub fn can_use(&self, unit: &Warrior, team: &Tribe, rules: &economy::EconomyRules) -> bool {
+        unit.stamina.0 >= self.cost().0
+            && team.resources >= self.resource_cost(rules)
+            && !(self.uses_attack() && unit.attacked)
+    }
+
+    ///All the cells this ability can be used on.
+    pub fn targets(&self, unit: &WarriorPointer<&Warrior>, game: &Stuff) -> movement::PossibleMoves {
+        let gg = game.grid_matrix;
+        let mut targets = match self {
+            Ability::Charge => movement::PossibleMoves::new(
+                &movement::WarriorMovement,
+                &gg.filter()
+                    .chain(game.this_team.filter())
+                    .chain(game.that_team.filter()),
+                &terrain::Grass,
+                unit.position,
+                self.range(),
+            ),
+            Ability::Heal | Ability::Push | Ability::Poison | Ability::BuildRoad => {
+                movement::PossibleMoves::new(
+                    &movement::WarriorMovement,
+                    &gg.filter().chain(SingleFilter { a: unit.get_pos() }),
+                    &terrain::Grass,
+                    unit.position,
+                    self.range(),
+                )
+            }
+        };
+
+        targets.retain(&TargetFilter {
+            ability: *self,
+            this_team: game.this_team,
+            that_team: game.that_team,
+            roads: game.roads,
+        });
+        targets
+    }
+}
+
+///Allows only the cells an ability can be used on.
+pub struct TargetFilter<'a> {
+    ability: Ability,
+    this_team: &'a Tribe,
+    that_team: &'a Tribe,
+    roads: &'a terrain::Roads,
+}
+impl<'a> movement::Filter for TargetFilter<'a> {
+    fn filter(&self, a: &GridCoord) -> bool {
+        match self.ability {
+            Ability::Heal => self.this_team.find_slow(a).is_some(),
+            Ability::Push | Ability::Poison => self.that_team.find_slow(a).is_some(),
+            Ability::Charge => self.this_team.filter().chain(self.that_team.filter()).filter(a),
+            Ability::BuildRoad => self.roads.filter().filter(a),
+        }
+    }
+}
+
+///Use an ability. Returns the new position of the unit.
+pub fn execute(
+    ability: Ability,
+    targets: &movement::PossibleMoves,
+    unit: WarriorPointer<GridCoord>,
+    target: GridCoord,
+    g1: &mut Stuff,
+) -> impl GameStepper<GameHandle, Result = Option<WarriorPointer<GridCoord>>> {
+    {
+        let mut current = g1.this_team.lookup_mut(&unit);
+        current.stamina.0 -= ability.cost().0;
+        if ability.uses_attack() {
+            current.attacked = true;
+        }
+    }
+
+    match ability {
+        Ability::Charge => {
+            let warrior = g1.this_team.lookup_take(unit);
+            let (path, _) = targets.get_path_data(&target).unwrap();
+
+            let aa = animation::Animation::new(warrior.position, path, g1.grid_matrix, warrior);
+            state::AnimationTicker::new(aa)
+                .map(move |res, g1| {
+                    let mut warrior = res.into_data();
+                    warrior.position = target;
+                    warrior
+                        .effects
+                        .add(status::EffectKind::Rally, CHARGE_RALLY_TURNS);
+                    let pos = warrior.slim();
+                    g1.this_team.add(warrior);
+                    Some(pos)
+                })
+                .either_a()
+        }
+        Ability::Heal | Ability::Push | Ability::Poison | Ability::BuildRoad => {
+            gameplay::next::<GameHandle>()
+                .map(move |_, g1: &mut Stuff| {
+                    apply_instant(ability, &unit, target, g1);
+                    Some(unit)
+                })
+                .either_b()
+        }
+    }
+    .map(|a, _| match a {
+        gameplay::Either::A(a) => a,
+        gameplay::Either::B(a) => a,
+    })
+}
+
+fn apply_instant(ability: Ability, unit: &GridCoord, target: GridCoord, g1: &mut Stuff) {
+    match ability {
+        Ability::Heal => {
+            let ally = g1.this_team.find_slow(&target).unwrap().slim();
+            let mut ally = g1.this_team.lookup_mut(&ally);
+            let before = ally.health;
+            ally.health = (ally.health + HEAL_AMOUNT).min(Warrior::MAX_HEALTH);
+            if ally.health > before {
+                combat_text::heal(g1.popups, g1.grid_matrix, target, ally.health - before);
+            }
+            ally.effects
+                .add(status::EffectKind::Shield, HEAL_SHIELD_TURNS);
+        }
+        Ability::Push => {
+            let enemy = g1.that_team.find_slow(&target).unwrap().slim();
+
+            //Push directly away from the unit.
+            let dir = [
+                (target.0[0] - unit.0[0]).signum(),
+                (target.0[1] - unit.0[1]).signum(),
+            ];
+            let dest = GridCoord([target.0[0] + dir[0], target.0[1] + dir[1]]);
+
+            let free = g1
+                .grid_matrix
+                .filter()
+                .chain(g1.this_team.filter())
+                .chain(g1.that_team.filter())
+                .filter(&dest);
+
+            let mut e = g1.that_team.lookup_mut(&enemy);
+            if free {
+                e.position = dest;
+                e.effects.add(status::EffectKind::Slow, PUSH_SLOW_TURNS);
+            } else {
+                //Slammed into something.
+                e.health -= PUSH_DAMAGE;
+                e.effects.add(status::EffectKind::Stun, PUSH_STUN_TURNS);
+                combat_text::damage(g1.popups, g1.grid_matrix, target, PUSH_DAMAGE);
+                if e.health <= 0 {
+                    combat_text::ko(g1.popups, g1.grid_matrix, target);
+                    g1.that_team.kill(enemy);
+                }
+            }
+        }
+        Ability::Poison => {
+            let enemy = g1.that_team.find_slow(&target).unwrap().slim();
+            let mut e = g1.that_team.lookup_mut(&enemy);
+            e.effects.add(status::EffectKind::Poison, POISON_TURNS);
+            combat_text::poisoned(g1.popups, g1.grid_matrix, target);
+        }
+        Ability::BuildRoad => {
+            g1.this_team.resources -= ability.resource_cost(&g1.economy.rules);
+            g1.roads.pos.push(target);
+        }
+        Ability::Charge => unreachable!(),
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    ///A game with fresh units of the first kind for both teams, for testing.
+    pub(crate) fn game(cats: &[[i16; 2]], dogs: &[[i16; 2]]) -> Game {
+        let tribe = |units: &[[i16; 2]]| {
+            let units = units.iter().map(|&a| Warrior::new(GridCoord(a))).collect();
+            Tribe::new(vec![UnitCollection::new(units)], GridCoord([0, 0]), 0)
+        };
+        Game {
+            team: 0,
+            cats: tribe(cats),
+            dogs: tribe(dogs),
+            grid_matrix: grids::GridMatrix::new(),
+            roads: terrain::roads(vec![]),
+            economy: economy::Economy::new(economy::EconomyRules::default(), vec![]),
+            survival: None,
+            ai_team: None,
+            confirm_attack: false,
+            popups: model::popup::Popups::new(),
+        }
+    }
+
+    fn use_on(game: &mut Game, ability: Ability, unit: [i16; 2], target: [i16; 2]) {
+        realtime::with_stuff(game, 0, |stuff| {
+            apply_instant(ability, &GridCoord(unit), GridCoord(target), stuff)
+        });
+    }
+
+    fn effects(tribe: &Tribe, a: [i16; 2]) -> Vec<status::EffectKind> {
+        let w = tribe.find_slow(&GridCoord(a)).unwrap();
+        w.effects.iter().map(|a| a.kind).collect()
+    }
+
+    #[test]
+    fn heal_shields_the_ally() {
+        let mut g = game(&[[2, 2], [3, 2]], &[]);
+        use_on(&mut g, Ability::Heal, [2, 2], [3, 2]);
+        assert_eq!(effects(&g.cats, [3, 2]), [status::EffectKind::Shield]);
+
+        //Still there once the healer's turn is over.
+        g.cats.tick_effects();
+        assert_eq!(effects(&g.cats, [3, 2]), [status::EffectKind::Shield]);
+    }
+
+    #[test]
+    fn push_slows_or_stuns() {
+        let mut g = game(&[[2, 2]], &[[3, 2]]);
+        use_on(&mut g, Ability::Push, [2, 2], [3, 2]);
+        assert_eq!(effects(&g.dogs, [4, 2]), [status::EffectKind::Slow]);
+
+        //Pushed into another unit.
+        let mut g = game(&[[2, 2]], &[[3, 2], [4, 2]]);
+        use_on(&mut g, Ability::Push, [2, 2], [3, 2]);
+        assert_eq!(effects(&g.dogs, [3, 2]), [status::EffectKind::Stun]);
+        g.dogs.replenish_stamina();
+        g.dogs.apply_effects();
+        let w = g.dogs.find_slow(&GridCoord([3, 2])).unwrap();
+        assert_eq!(
+            (w.health, w.stamina.0, w.attacked),
+            (10 - PUSH_DAMAGE, 0, true)
+        );
+    }
+
+    #[test]
+    fn poison_hurts_every_turn() {
+        let mut g = game(&[[2, 2]], &[[3, 2]]);
+        use_on(&mut g, Ability::Poison, [2, 2], [3, 2]);
+        for _ in 0..POISON_TURNS {
+            g.dogs.apply_effects();
+            g.dogs.tick_effects();
+        }
+        let w = g.dogs.find_slow(&GridCoord([3, 2])).unwrap();
+        assert_eq!(w.health, 10 - POISON_TURNS as i8);
+        assert!(w.effects.iter().next().is_none());
+    }
+
+    #[test]
+    fn heal_stops_at_max_health() {
+        let mut g = game(&[[2, 2], [3, 2]], &[]);
+        g.cats.find_slow_mut(&GridCoord([3, 2])).unwrap().health = Warrior::MAX_HEALTH - 1;
+        use_on(&mut g, Ability::Heal, [2, 2], [3, 2]);
+        let w = g.cats.find_slow(&GridCoord([3, 2])).unwrap();
+        assert_eq!(w.health, Warrior::MAX_HEALTH);
+    }
+
+    #[test]
+    fn can_use_needs_stamina_attack_and_resources() {
+        let mut g = game(&[[2, 2]], &[]);
+        let rules = economy::EconomyRules::default();
+        let mut w = Warrior::new(GridCoord([2, 2]));
+        assert!(!Ability::Push.can_use(&w, &g.cats, &rules));
+
+        w.stamina = MoveUnit(4);
+        assert!(Ability::Push.can_use(&w, &g.cats, &rules));
+        assert!(!Ability::BuildRoad.can_use(&w, &g.cats, &rules));
+        g.cats.resources = rules.road_cost;
+        assert!(Ability::BuildRoad.can_use(&w, &g.cats, &rules));
+
+        //Charging doesn't take the attack for the turn.
+        w.attacked = true;
+        assert!(!Ability::Push.can_use(&w, &g.cats, &rules));
+        assert!(Ability::Charge.can_use(&w, &g.cats, &rules));
+    }
+
+    #[test]
+    fn targets_only_fit_the_ability() {
+        let mut g = game(&[[2, 2], [2, 3]], &[[3, 2]]);
+        g.roads.pos.push(GridCoord([1, 2]));
+        let targets = |g: &mut Game, ability: Ability| {
+            realtime::with_stuff(g, 0, |stuff| {
+                let unit = stuff.this_team.find_slow(&GridCoord([2, 2])).unwrap();
+                let mut cells: Vec<_> = ability
+                    .targets(&unit, stuff)
+                    .iter_coords()
+                    .map(|a| a.0)
+                    .collect();
+                cells.sort();
+                cells
+            })
+        };
+        assert_eq!(targets(&mut g, Ability::Heal), [[2, 3]]);
+        assert_eq!(targets(&mut g, Ability::Push), [[3, 2]]);
+        //Any adjacent cell, diagonals too, that isn't a road yet.
+        assert_eq!(
+            targets(&mut g, Ability::BuildRoad),
+            [[1, 1], [1, 3], [2, 1], [2, 3], [3, 1], [3, 2], [3, 3]]
+        );
+    }
+
+    #[test]
+    fn build_road_paves_and_pays() {
+        let mut g = game(&[[2, 2]], &[]);
+        g.cats.resources = 3;
+        use_on(&mut g, Ability::BuildRoad, [2, 2], [2, 3]);
+        assert_eq!(g.roads.pos, [GridCoord([2, 3])]);
+        assert_eq!(
+            g.cats.resources,
+            3 - economy::EconomyRules::default().road_cost
+        );
+    }
+}
@@ -0,0 +1,102 @@
+use super::*;
+use crate::state::{GameHandle, PlayerCellAskRes, Stuff};
+
+//Number of moves between two cells when diagonal moves are allowed.
+pub fn distance(a: &GridCoord, b: &GridCoord) -> i16 {
+    (a.0[0] - b.0[0]).abs().max((a.0[1] - b.0[1]).abs())
+}
+
+pub fn closest<'a>(
+    from: &GridCoord,
+    it: impl IntoIterator<Item = &'a GridCoord>,
+) -> Option<GridCoord> {
+    it.into_iter().min_by_key(|a| distance(from, a)).copied()
+}
+
+///Attack an enemy in range if possible, otherwise walk towards the goal.
+pub fn decide(
+    unit: &WarriorPointer<&Warrior>,
+    goal: Option<GridCoord>,
+    game: &Stuff,
+) -> Option<(CellSelection, PlayerCellAskRes)> {
+    let cc = state::generate_unit_possible_moves(unit, game);
+    let CellSelection::MoveSelection(ss, att) = &cc else {
+        unreachable!()
+    };
+
+    if !unit.attacked {
+        let target = att
+            .iter_coords()
+            .find_map(|a| game.that_team.find_slow(a))
+            .map(|a| a.slim());
+
+        if let Some(target) = target {
+            return Some((cc, PlayerCellAskRes::Attack(target)));
+        }
+    }
+
+    let goal = goal?;
+    let best = closest(&goal, ss.iter_coords())?;
+
+    //Only move if it gets us closer.
+    if distance(&best, &goal) < distance(&unit.position, &goal) {
+        Some((cc, PlayerCellAskRes::MoveTo(best)))
+    } else {
+        None
+    }
+}
+
+///Let every unit of the current team act one after the other.
+///The goal function picks where a unit should head to.
+pub fn handle_ai_move(
+    goal: fn(&WarriorPointer<&Warrior>, &Stuff) -> Option<GridCoord>,
+) -> impl GameStepper<GameHandle, Result = ()> {
+    gameplay::next::<GameHandle>()
+        .map(move |_, stuff: &mut Stuff| {
+            let units: Vec<_> = stuff.this_team.iter().map(|a| a.slim()).collect();
+
+            gameplay::looper(units, move |mut units, stuff| {
+                let next = units.pop().and_then(|unit| {
+                    let w = stuff.this_team.find_slow(&unit)?;
+                    let target = goal(&w, stuff);
+                    let (cc, res) = decide(&w, target, stuff)?;
+                    Some(state::handle_one_execution(unit, cc, res, stuff))
+                });
+
+                GameStepper::map(next, move |res, _| {
+                    //If the unit is still around, let it act again. It will stop once
+                    //it can't get any closer or attack anymore.
+                    if let Some(Some(pos)) = res {
+                        units.push(pos);
+                    }
+
+                    if units.is_empty() {
+                        gameplay::LooperRes::Finish(())
+                    } else {
+                        gameplay::LooperRes::Loop(units)
+                    }
+                })
+            })
+        })
+        .flatten()
+}
+
+//Head for the closest enemy.
+fn enemy_goal(unit: &WarriorPointer<&Warrior>, stuff: &Stuff) -> Option<GridCoord> {
+    let enemies: Vec<_> = stuff.that_team.iter().map(|a| a.position).collect();
+    closest(&unit.position, enemies.iter())
+}
+
+///A regular turn for a team controlled by the computer.
+pub fn handle_ai_turn() -> impl GameStepper<GameHandle, Result = ()> {
+    gameplay::next::<GameHandle>()
+        .map(|_, stuff: &mut Stuff| {
+            state::start_turn(stuff);
+            handle_ai_move(enemy_goal)
+        })
+        .flatten()
+        .map(|_, stuff| {
+            stuff.this_team.reset_attacked();
+            stuff.this_team.tick_effects();
+        })
+}
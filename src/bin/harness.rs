@@ -0,0 +1,207 @@
+//! Plays full matches against the match server to check that it keeps the game consistent.
+//!
+//! Starts the server binary next to this one, plays both teams with a simple greedy client
+//! and tries some illegal commands along the way that should all be rejected.
+//!
+//! ```text
+//! harness [--matches N] [--server PATH]
+//! ```
+use gridlock::movement::GridCoord;
+use gridlock::server::{Command, Request, Response, View};
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, ChildStdout, Stdio};
+
+//Call it a draw after this many turns.
+const MAX_TURNS: u32 = 200;
+
+//Nobody should need this many commands in one turn.
+const MAX_COMMANDS: usize = 100;
+
+struct Client {
+    child: Child,
+    input: ChildStdin,
+    output: BufReader<ChildStdout>,
+}
+
+impl Client {
+    fn start(server: &str, seed: u64) -> Client {
+        let mut child = std::process::Command::new(server)
+            .arg("--seed")
+            .arg(seed.to_string())
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("could not start the server");
+        let input = child.stdin.take().unwrap();
+        let output = BufReader::new(child.stdout.take().unwrap());
+        Client {
+            child,
+            input,
+            output,
+        }
+    }
+
+    fn send(&mut self, team: usize, command: Command) -> Response {
+        let req = serde_json::to_string(&Request { team, command }).unwrap();
+        writeln!(self.input, "{}", req).unwrap();
+        self.input.flush().unwrap();
+
+        let mut line = String::new();
+        self.output.read_line(&mut line).unwrap();
+        serde_json::from_str(&line).expect("server sent something that isn't a response")
+    }
+
+    fn view(&mut self) -> View {
+        match self.send(0, Command::State) {
+            Response::State(v) => v,
+            a => panic!("expected the state:{:?}", a),
+        }
+    }
+}
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+fn distance(a: &GridCoord, b: &GridCoord) -> i16 {
+    (a.0[0] - b.0[0]).abs().max((a.0[1] - b.0[1]).abs())
+}
+
+fn closest(a: &GridCoord, others: &[GridCoord]) -> Option<i16> {
+    others.iter().map(|b| distance(a, b)).min()
+}
+
+#[derive(Default, Debug)]
+struct Stats {
+    commands: usize,
+    rejected_on_purpose: usize,
+}
+
+//Attack if possible, otherwise walk toward the closest enemy.
+fn pick_command(client: &mut Client, team: usize, view: &View) -> Option<Command> {
+    let enemies: Vec<_> = view.units[1 - team].iter().map(|a| a.position).collect();
+
+    for unit in view.units[team].iter().map(|a| a.position) {
+        let Response::Moves { moves, attacks } = client.send(team, Command::Moves { unit }) else {
+            continue;
+        };
+
+        if let Some(&target) = attacks.first() {
+            return Some(Command::Attack { unit, target });
+        }
+
+        let now = closest(&unit, &enemies)?;
+        let best = moves.iter().min_by_key(|a| closest(a, &enemies))?;
+        if closest(best, &enemies)? < now {
+            return Some(Command::Move { unit, to: *best });
+        }
+    }
+    None
+}
+
+//Commands that break the rules. None of them should go through.
+fn try_cheating(client: &mut Client, team: usize, view: &View, stats: &mut Stats) {
+    let Some(unit) = view.units[team].first().map(|a| a.position) else {
+        return;
+    };
+    let far = GridCoord([unit.0[0] + 50, unit.0[1] + 50]);
+
+    let cheats = [
+        (team, Command::Move { unit, to: far }),
+        (1 - team, Command::EndTurn),
+        (team, Command::Attack { unit, target: unit }),
+        (
+            team,
+            Command::Move {
+                unit: far,
+                to: unit,
+            },
+        ),
+        (7, Command::EndTurn),
+    ];
+
+    for (team, command) in cheats {
+        match client.send(team, command.clone()) {
+            Response::Rejected(_) => stats.rejected_on_purpose += 1,
+            a => panic!("illegal command {:?} was accepted:{:?}", command, a),
+        }
+    }
+
+    let after = client.view();
+    assert_eq!(&after, view, "a rejected command changed the game");
+}
+
+//Play one match to the end. Returns the winner if there is one.
+fn play(server: &str, seed: u64, stats: &mut Stats) -> Option<usize> {
+    let mut client = Client::start(server, seed);
+
+    loop {
+        let view = client.view();
+        if let Some(w) = view.winner {
+            return Some(w);
+        }
+        if view.turn >= MAX_TURNS {
+            return None;
+        }
+
+        let team = view.team;
+        try_cheating(&mut client, team, &view, stats);
+
+        for _ in 0..MAX_COMMANDS {
+            let view = client.view();
+            if view.winner.is_some() {
+                break;
+            }
+            let Some(command) = pick_command(&mut client, team, &view) else {
+                break;
+            };
+            stats.commands += 1;
+            if let Response::Rejected(e) = client.send(team, command.clone()) {
+                panic!("legal command {:?} was rejected:{}", command, e);
+            }
+        }
+
+        if client.view().winner.is_none() {
+            match client.send(team, Command::EndTurn) {
+                Response::Ok { team: next, .. } => assert_eq!(next, 1 - team),
+                a => panic!("could not end the turn:{:?}", a),
+            }
+        }
+    }
+}
+
+fn main() {
+    let mut matches = 5;
+    let mut server = std::env::current_exe()
+        .unwrap()
+        .with_file_name(format!("server{}", std::env::consts::EXE_SUFFIX))
+        .to_string_lossy()
+        .into_owned();
+
+    let mut args = std::env::args().skip(1);
+    while let Some(a) = args.next() {
+        match a.as_str() {
+            "--matches" => {
+                matches = args
+                    .next()
+                    .and_then(|a| a.parse().ok())
+                    .expect("--matches needs a number")
+            }
+            "--server" => server = args.next().expect("--server needs a path"),
+            _ => panic!("unknown argument:{}", a),
+        }
+    }
+
+    let mut stats = Stats::default();
+    for seed in 0..matches {
+        match play(&server, seed, &mut stats) {
+            Some(w) => println!("seed {}: team {} won", seed, w),
+            None => println!("seed {}: draw after {} turns", seed, MAX_TURNS),
+        }
+    }
+    println!("{:?}", stats);
+}
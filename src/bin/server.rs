@@ -0,0 +1,103 @@
+//! Headless match server. Owns the game and only carries out commands that follow the rules.
+//!
+//! Every request and response is one line of JSON. See `gridlock::server` for the messages.
+//!
+//! ```text
+//! server [--seed N]             talk over stdin/stdout
+//! server [--seed N] --tcp ADDR  accept clients on a local socket
+//! ```
+//!
+//! Over tcp the first client to connect plays team 0 and the next one team 1. Each is
+//! told its team with a `Joined` line and can't send commands for the other team.
+use gridlock::server::{Match, Response};
+use std::io::{BufRead, BufReader, Write};
+use std::sync::{Arc, Mutex};
+
+//Serve one client. `team` is the only team it may play, or None for both.
+fn serve(
+    m: &Mutex<Match>,
+    team: Option<usize>,
+    input: impl BufRead,
+    mut output: impl Write,
+) -> std::io::Result<()> {
+    for line in input.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let res = match team {
+            Some(team) => m.lock().unwrap().handle_line_for(team, &line),
+            None => m.lock().unwrap().handle_line(&line),
+        };
+        writeln!(output, "{}", res)?;
+        output.flush()?;
+    }
+    Ok(())
+}
+
+fn send(mut output: impl Write, res: &Response) -> std::io::Result<()> {
+    writeln!(output, "{}", serde_json::to_string(res).unwrap())?;
+    output.flush()
+}
+
+fn main() -> std::io::Result<()> {
+    let mut seed = 0;
+    let mut tcp = None;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(a) = args.next() {
+        match a.as_str() {
+            "--seed" => {
+                seed = args
+                    .next()
+                    .and_then(|a| a.parse().ok())
+                    .expect("--seed needs a number")
+            }
+            "--tcp" => tcp = Some(args.next().expect("--tcp needs an address")),
+            _ => panic!("unknown argument:{}", a),
+        }
+    }
+
+    let m = Arc::new(Mutex::new(Match::new(seed)));
+
+    let Some(addr) = tcp else {
+        let stdin = std::io::stdin();
+        return serve(&m, None, stdin.lock(), std::io::stdout());
+    };
+
+    //Both players share the same match. A team is free again once its client leaves.
+    let taken = Arc::new(Mutex::new([false; 2]));
+    let listener = std::net::TcpListener::bind(&addr)?;
+    eprintln!("listening on {}", listener.local_addr()?);
+    for stream in listener.incoming() {
+        let stream = stream?;
+        let team = {
+            let mut taken = taken.lock().unwrap();
+            let team = taken.iter().position(|a| !a);
+            if let Some(team) = team {
+                taken[team] = true;
+            }
+            team
+        };
+        let Some(team) = team else {
+            send(
+                &stream,
+                &Response::Rejected("both teams are taken".to_string()),
+            )?;
+            continue;
+        };
+
+        let m = m.clone();
+        let taken = taken.clone();
+        std::thread::spawn(move || {
+            let res = (|| {
+                send(&stream, &Response::Joined { team })?;
+                let input = BufReader::new(stream.try_clone()?);
+                serve(&m, Some(team), input, &stream)
+            })();
+            taken.lock().unwrap()[team] = false;
+            res
+        });
+    }
+    Ok(())
+}
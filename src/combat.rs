@@ -0,0 +1,49 @@
+use super::*;
+
+///The predicted outcome of one unit attacking another.
+#[derive(Debug, Copy, Clone)]
+pub struct Forecast {
+    pub attacker: GridCoord,
+    pub defender: GridCoord,
+    pub damage: i8,
+    pub counter_damage: i8,
+    pub kills_target: bool,
+    pub kills_self: bool,
+}
+
+//TODO only counter if the defender has stamina left?
+const DAMAGE: i8 = 5;
+const COUNTER_DAMAGE: i8 = 5;
+
+///Compute what would happen if the attacker attacked the defender.
+///Does not modify anything, so it can be used to preview an attack.
+pub fn forecast(
+    attacker: &WarriorPointer<&Warrior>,
+    defender: &WarriorPointer<&Warrior>,
+) -> Forecast {
+    let damage = defender
+        .effects
+        .damage_taken(attacker.effects.damage_dealt(DAMAGE));
+
+    let kills_target = defender.health <= damage;
+
+    //A dead or stunned unit can't counter attack.
+    let counter_damage = if kills_target || defender.effects.has(status::EffectKind::Stun) {
+        0
+    } else {
+        attacker
+            .effects
+            .damage_taken(defender.effects.damage_dealt(COUNTER_DAMAGE))
+    };
+
+    let kills_self = !kills_target && attacker.health <= counter_damage;
+
+    Forecast {
+        attacker: attacker.position,
+        defender: defender.position,
+        damage,
+        counter_damage,
+        kills_target,
+        kills_self,
+    }
+}
@@ -0,0 +1,89 @@
+//! Popups over units for what happens to them, like damage and stamina coming back.
+use super::*;
+use crate::state::Stuff;
+use model::popup::Popups;
+
+//Over the middle of the unit, in front of the numbers that are always shown.
+fn over(gg: &grids::GridMatrix, GridCoord(a): GridCoord) -> [f32; 3] {
+    let pos: [f32; 2] = gg.to_world_center(a.into()).into();
+    [pos[0], pos[1], 40.0]
+}
+
+pub fn damage(popups: &mut Popups, gg: &grids::GridMatrix, unit: GridCoord, amount: i8) {
+    popups.spawn(format!("-{}", amount), over(gg, unit));
+}
+
+pub fn heal(popups: &mut Popups, gg: &grids::GridMatrix, unit: GridCoord, amount: i8) {
+    popups.spawn(format!("+{}", amount), over(gg, unit));
+}
+
+pub fn ko(popups: &mut Popups, gg: &grids::GridMatrix, unit: GridCoord) {
+    popups.spawn("KO", over(gg, unit));
+}
+
+pub fn poisoned(popups: &mut Popups, gg: &grids::GridMatrix, unit: GridCoord) {
+    popups.spawn("poisoned", over(gg, unit));
+}
+
+///Health and stamina of every unit of a team, to compare against later.
+pub struct Snapshot(Vec<(GridCoord, i8, i8)>);
+
+impl Snapshot {
+    pub fn new(team: &Tribe) -> Self {
+        Snapshot(
+            team.iter()
+                .map(|a| (a.position, a.health, a.stamina.0))
+                .collect(),
+        )
+    }
+}
+
+///Popups for how the units of this team changed at the start of its turn.
+pub fn turn_start(stuff: &mut Stuff, before: Snapshot) {
+    let gg = stuff.grid_matrix;
+    for (pos, health, stamina) in before.0 {
+        let Some(a) = stuff.this_team.find_slow(&pos) else {
+            ko(stuff.popups, gg, pos);
+            continue;
+        };
+        if a.health < health {
+            damage(stuff.popups, gg, pos, health - a.health);
+        }
+        if a.stamina.0 > stamina {
+            let text = format!("+{} stamina", a.stamina.0 - stamina);
+            stuff.popups.spawn(text, over(gg, pos));
+        } else if a.stamina.0 < stamina {
+            //Only a stun takes stamina away at the start of a turn.
+            stuff.popups.spawn("stunned", over(gg, pos));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn popups_for_what_changed() {
+        let mut g = ability::tests::game(&[[1, 1], [2, 2], [3, 3]], &[]);
+        let texts = realtime::with_stuff(&mut g, 0, |stuff| {
+            let before = Snapshot::new(stuff.this_team);
+            let mut a = stuff.this_team.find_slow_mut(&GridCoord([1, 1])).unwrap();
+            a.health -= 3;
+            a.stamina.0 += 2;
+            let b = stuff
+                .this_team
+                .find_slow(&GridCoord([2, 2]))
+                .unwrap()
+                .slim();
+            stuff.this_team.kill(b);
+            turn_start(stuff, before);
+            stuff
+                .popups
+                .iter()
+                .map(|a| a.text.clone())
+                .collect::<Vec<_>>()
+        });
+        assert_eq!(texts, ["-3", "+2 stamina", "KO"]);
+    }
+}
@@ -23,7 +23,27 @@ pub enum MEvent {
     EndTurn,
     CanvasMouseUp,
     CanvasMouseLeave,
-    ButtonClick,
+    AbilityClick(ability::Ability),
+    RecruitClick,
+    UpgradeClick,
+    OrderClick(orders::OrderKind),
+    DragMode(scroll::DragMode),
+    CancelRoute,
+    StartHotSeat {
+        profiles: [hotseat::Profile; 2],
+    },
+    HandOffReady,
+    StartMail {
+        seed: u64,
+        team: usize,
+    },
+    ImportTurn(String),
+    StartSurvival,
+    ToggleRealtime,
+    StartRun {
+        seed: u64,
+    },
+    ChooseReward(run::Reward),
     ShutdownClick,
     Resize {
         canvasx: u32,
@@ -126,13 +146,36 @@ pub async fn main_entry() {
 
     log!("demo start");
 
-    let (canvas, button, endturn) = (
+    let (canvas, endturn) = (
         utils::get_by_id_canvas("mycanvas"),
-        utils::get_by_id_elem("mybutton"),
         utils::get_by_id_elem("endturn"),
     );
-
-    button.set_hidden(true);
+    let (recruit, upgrade, resources) = (
+        utils::get_by_id_elem("recruit"),
+        utils::get_by_id_elem("upgrade"),
+        utils::get_by_id_elem("resources"),
+    );
+    let (survival, score, start_run) = (
+        utils::get_by_id_elem("survival"),
+        utils::get_by_id_elem("score"),
+        utils::get_by_id_elem("start_run"),
+    );
+    let toggle_realtime = utils::get_by_id_elem("realtime");
+    let cancel_route = utils::get_by_id_elem("cancel_route");
+    let (start_hotseat, handoff, handoff_text, handoff_ready) = (
+        utils::get_by_id_elem("start_hotseat"),
+        utils::get_by_id_elem("handoff"),
+        utils::get_by_id_elem("handoff_text"),
+        utils::get_by_id_elem("handoff_ready"),
+    );
+    let (mail_host, mail_join, mail_import, mail_status) = (
+        utils::get_by_id_elem("mail_host"),
+        utils::get_by_id_elem("mail_join"),
+        utils::get_by_id_elem("mail_import"),
+        utils::get_by_id_elem("mail_status"),
+    );
+    handoff.set_hidden(true);
+    cancel_route.set_hidden(true);
 
     canvas.set_width(gloo::utils::body().client_width() as u32);
     canvas.set_height(gloo::utils::body().client_height() as u32);
@@ -178,18 +221,121 @@ pub async fn main_entry() {
         MEvent::TouchEnd { touches }.some()
     });
 
-    let bb = button.clone();
-    let _handler = worker.register_event(&button, "click", move |_| {
-        log!("clicked the button!!!!!");
-        bb.set_hidden(true);
-        MEvent::ButtonClick.some()
-    });
-
     let _handler = worker.register_event(&endturn, "click", move |_| {
         log!("clicked the button!!!!!");
         MEvent::EndTurn.some()
     });
 
+    let _handler = worker.register_event(&recruit, "click", |_| MEvent::RecruitClick.some());
+
+    let _handler = worker.register_event(&upgrade, "click", |_| MEvent::UpgradeClick.some());
+
+    let ss = survival.clone();
+    let _handler = worker.register_event(&survival, "click", move |_| {
+        ss.set_hidden(true);
+        MEvent::StartSurvival.some()
+    });
+
+    let _handler =
+        worker.register_event(&toggle_realtime, "click", |_| MEvent::ToggleRealtime.some());
+
+    let _handler = worker.register_event(&cancel_route, "click", |_| MEvent::CancelRoute.some());
+
+    let _handler = worker.register_event(&start_hotseat, "click", |_| {
+        let profile = |team: usize| hotseat::Profile {
+            name: input_value(&format!("player{}_name", team)),
+            colour: input_value(&format!("player{}_colour", team)),
+        };
+        MEvent::StartHotSeat {
+            profiles: [profile(0), profile(1)],
+        }
+        .some()
+    });
+
+    let hh = handoff.clone();
+    let _handler = worker.register_event(&handoff_ready, "click", move |_| {
+        hh.set_hidden(true);
+        MEvent::HandOffReady.some()
+    });
+
+    let _handler = worker.register_event(&mail_host, "click", |_| {
+        let seed = js_sys::Date::now() as u64;
+        MEvent::StartMail { seed, team: 0 }.some()
+    });
+
+    let _handler = worker.register_event(&mail_join, "click", |_| {
+        let Ok(seed) = input_value("mail_seed").trim().parse() else {
+            log!("not a valid seed");
+            return None;
+        };
+        MEvent::StartMail { seed, team: 1 }.some()
+    });
+
+    let _handler = worker.register_event(&mail_import, "click", |_| {
+        MEvent::ImportTurn(input_value("mail_text")).some()
+    });
+
+    let _handler = worker.register_event(&start_run, "click", |_| {
+        let seed = js_sys::Date::now() as u64;
+        MEvent::StartRun { seed }.some()
+    });
+
+    let reward_buttons: Vec<_> = run::Reward::ALL
+        .iter()
+        .map(|&a| (a, utils::get_by_id_elem(a.button_id())))
+        .collect();
+
+    let _reward_handlers: Vec<_> = reward_buttons
+        .iter()
+        .map(|(a, b)| {
+            let a = *a;
+            b.set_hidden(true);
+            worker.register_event(b, "click", move |_| MEvent::ChooseReward(a).some())
+        })
+        .collect();
+
+    let drag_buttons = [
+        (scroll::DragMode::Scroll, utils::get_by_id_elem("drag_scroll")),
+        (scroll::DragMode::Box, utils::get_by_id_elem("drag_box")),
+        (scroll::DragMode::Lasso, utils::get_by_id_elem("drag_lasso")),
+    ];
+
+    let _drag_handlers: Vec<_> = drag_buttons
+        .iter()
+        .map(|(a, b)| {
+            let a = *a;
+            worker.register_event(b, "click", move |_| MEvent::DragMode(a).some())
+        })
+        .collect();
+
+    let order_buttons: Vec<_> = orders::OrderKind::ALL
+        .iter()
+        .map(|&a| (a, utils::get_by_id_elem(a.button_id())))
+        .collect();
+
+    let _order_handlers: Vec<_> = order_buttons
+        .iter()
+        .map(|(a, b)| {
+            let a = *a;
+            b.set_hidden(true);
+            worker.register_event(b, "click", move |_| MEvent::OrderClick(a).some())
+        })
+        .collect();
+
+    let ability_buttons: Vec<_> = ability::Ability::ALL
+        .iter()
+        .map(|&a| (a, utils::get_by_id_elem(a.button_id())))
+        .collect();
+
+    let _ability_handlers: Vec<_> = ability_buttons
+        .iter()
+        .map(|(a, b)| {
+            let a = *a;
+            b.set_hidden(true);
+            worker.register_event(b, "click", move |_| MEvent::AbilityClick(a).some())
+        })
+        .collect();
+
     let w = gloo::utils::window();
 
     let _handler = worker.register_event(&w, "resize", |_| resize().some());
@@ -201,18 +347,89 @@ pub async fn main_entry() {
         let hay: UiButton = response.next().await.unwrap_throw();
 
         match hay {
-            UiButton::ShowRoadUi => {
-                button.set_hidden(false);
-                button.set_text_content(Some("make a road?"));
+            UiButton::Resources(a) => {
+                resources.set_text_content(Some(&format!("resources: {}", a)));
+            }
+            UiButton::Score {
+                survived,
+                game_over,
+            } => {
+                let text = if game_over {
+                    format!("game over! waves survived: {}", survived)
+                } else {
+                    format!("waves survived: {}", survived)
+                };
+                score.set_text_content(Some(&text));
+            }
+            UiButton::ChooseReward(ref choices) => {
+                for (a, b) in reward_buttons.iter() {
+                    b.set_hidden(!choices.contains(a));
+                }
+            }
+            UiButton::Realtime(on) => {
+                let text = if on { "Turn-based" } else { "Real-time" };
+                toggle_realtime.set_text_content(Some(text));
+                endturn.set_hidden(on);
+            }
+            UiButton::RunSummary(ref s) => {
+                let result = if s.victory { "victory" } else { "defeat" };
+                score.set_text_content(Some(&format!(
+                    "{}! battles won: {}/{} kills: {} fallen: {} survivors: {} seed: {}",
+                    result, s.battles_won, s.num_battles, s.kills, s.fallen, s.survivors, s.seed
+                )));
+            }
+            UiButton::ShowAbilities(ref abilities) => {
+                for (a, b) in ability_buttons.iter() {
+                    b.set_hidden(!abilities.contains(a));
+                }
+            }
+            UiButton::HandOff(ref p) => {
+                handoff_text.set_text_content(Some(&format!("Pass the device to {}", p.name)));
+                handoff
+                    .set_attribute("style", &format!("background-color:{}", p.colour))
+                    .unwrap_throw();
+                handoff.set_hidden(false);
+            }
+            UiButton::TurnExport(ref text) => {
+                set_input_value("mail_text", text);
+                mail_status.set_text_content(Some("turn ended, send the text to the other player"));
+            }
+            UiButton::MailStatus(ref text) => {
+                mail_status.set_text_content(Some(text));
+            }
+            UiButton::ShowCancelRoute(show) => {
+                cancel_route.set_hidden(!show);
+            }
+            UiButton::ShowOrders(show) => {
+                for (_, b) in order_buttons.iter() {
+                    b.set_hidden(!show);
+                }
             }
             UiButton::NoUi => {
-                button.set_text_content(Some(""));
+                let order_buttons = order_buttons.iter().map(|(_, b)| b);
+                for b in ability_buttons.iter().map(|(_, b)| b).chain(order_buttons) {
+                    b.set_hidden(true);
+                }
             }
         }
         log!(format!("main thread received={:?}", hay));
     }
     log!("main thread is closing");
 }
+//Read the value of an input element.
+fn input_value(id: &str) -> String {
+    let elem = utils::get_by_id_elem(id);
+    js_sys::Reflect::get(&elem, &"value".into())
+        .ok()
+        .and_then(|a| a.as_string())
+        .unwrap_or_default()
+}
+
+fn set_input_value(id: &str, value: &str) {
+    let elem = utils::get_by_id_elem(id);
+    js_sys::Reflect::set(&elem, &"value".into(), &value.into()).unwrap_throw();
+}
+
 fn resize() -> MEvent {
     let canvas = utils::get_by_id_canvas("mycanvas");
     //canvas.set_width(gloo::utils::body().client_width() as u32);
@@ -0,0 +1,91 @@
+use super::*;
+use crate::state::Stuff;
+
+///The numbers that control the economy. Tweak these to balance the game.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct EconomyRules {
+    pub starting_resources: i16,
+    ///Resources every tribe gets at the start of its turn.
+    pub base_income: i16,
+    ///Extra resources for every resource cell one of the tribes units stands on.
+    pub cell_income: i16,
+    pub road_cost: i16,
+    pub recruit_cost: i16,
+    ///Cost of the first upgrade. Each upgrade after that costs this much more.
+    pub upgrade_cost: i16,
+    pub max_upgrades: u8,
+}
+impl Default for EconomyRules {
+    fn default() -> Self {
+        EconomyRules {
+            starting_resources: 5,
+            base_income: 2,
+            cell_income: 3,
+            road_cost: 1,
+            recruit_cost: 8,
+            upgrade_cost: 10,
+            max_upgrades: 3,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Economy {
+    pub rules: EconomyRules,
+    ///Cells that give extra income to whoever stands on them.
+    pub resource_cells: Vec<GridCoord>,
+}
+impl Economy {
+    pub fn new(rules: EconomyRules, resource_cells: Vec<GridCoord>) -> Self {
+        Economy {
+            rules,
+            resource_cells,
+        }
+    }
+    pub fn income(&self, tribe: &Tribe) -> i16 {
+        let controlled = self
+            .resource_cells
+            .iter()
+            .filter(|a| tribe.find_slow(a).is_some())
+            .count() as i16;
+
+        self.rules.base_income + controlled * self.rules.cell_income
+    }
+
+    ///Returns none if the tribe is already fully upgraded.
+    pub fn upgrade_cost(&self, tribe: &Tribe) -> Option<i16> {
+        if tribe.upgrades >= self.rules.max_upgrades {
+            None
+        } else {
+            Some(self.rules.upgrade_cost * (tribe.upgrades as i16 + 1))
+        }
+    }
+}
+
+///Place a new warrior on the spawn point. Returns false if it is blocked or too expensive.
+pub fn recruit(stuff: &mut Stuff) -> bool {
+    let spawn = stuff.this_team.spawn;
+
+    let blocked =
+        stuff.this_team.find_slow(&spawn).is_some() || stuff.that_team.find_slow(&spawn).is_some();
+
+    if blocked || !stuff.this_team.spend(stuff.economy.rules.recruit_cost) {
+        return false;
+    }
+
+    stuff.this_team.add_recruit(Warrior::new(spawn));
+    true
+}
+
+pub fn upgrade(stuff: &mut Stuff) -> bool {
+    let Some(cost) = stuff.economy.upgrade_cost(stuff.this_team) else {
+        return false;
+    };
+
+    if !stuff.this_team.spend(cost) {
+        return false;
+    }
+
+    stuff.this_team.upgrades += 1;
+    true
+}
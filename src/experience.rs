@@ -0,0 +1,131 @@
+use super::*;
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+pub enum StatUpgrade {
+    ///Health numbers only go up to 10 so this restores the unit to full health.
+    Health,
+    ///Get more stamina back every turn.
+    Stamina,
+    ///Attack from further away.
+    AttackRange,
+}
+
+const XP_PER_DAMAGE: u16 = 1;
+const KILL_XP: u16 = 5;
+
+//Total experience needed to reach level 2, 3, 4 and 5.
+const LEVEL_THRESHOLDS: [u16; 4] = [5, 12, 22, 35];
+
+//Range is in the same units as stamina, so one more cell across costs 2.
+const RANGE_UPGRADE: i8 = 2;
+
+//The upgrades are handed out in this order, one per level.
+const UPGRADE_ORDER: [StatUpgrade; 3] = [
+    StatUpgrade::Health,
+    StatUpgrade::Stamina,
+    StatUpgrade::AttackRange,
+];
+
+pub fn damage_xp(damage: i8) -> u16 {
+    damage.max(0) as u16 * XP_PER_DAMAGE
+}
+
+pub fn kill_xp(damage: i8) -> u16 {
+    damage_xp(damage) + KILL_XP
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Experience {
+    points: u16,
+    //Number of level ups. A fresh unit is level 1.
+    level_ups: u8,
+    stamina_bonus: i8,
+    range_bonus: i8,
+}
+impl Experience {
+    pub fn level(&self) -> u8 {
+        1 + self.level_ups
+    }
+    pub fn points(&self) -> u16 {
+        self.points
+    }
+    pub fn stamina_bonus(&self) -> i8 {
+        self.stamina_bonus
+    }
+    pub fn range_bonus(&self) -> i8 {
+        self.range_bonus
+    }
+
+    fn next_threshold(&self) -> Option<u16> {
+        LEVEL_THRESHOLDS.get(self.level_ups as usize).copied()
+    }
+}
+
+impl Warrior {
+    ///Add experience points and apply the upgrade of every level gained.
+    pub fn gain_experience(&mut self, points: u16) {
+        let xp = &mut self.experience;
+        xp.points = xp.points.saturating_add(points);
+
+        while let Some(threshold) = xp.next_threshold() {
+            if xp.points < threshold {
+                break;
+            }
+
+            let upgrade = UPGRADE_ORDER[xp.level_ups as usize % UPGRADE_ORDER.len()];
+            xp.level_ups += 1;
+            log!(format!("level up:{:?} {:?}", xp.level(), upgrade));
+
+            match upgrade {
+                StatUpgrade::Health => self.health = Warrior::MAX_HEALTH,
+                StatUpgrade::Stamina => xp.stamina_bonus += 1,
+                StatUpgrade::AttackRange => xp.range_bonus += RANGE_UPGRADE,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use movement::tests::Anywhere;
+
+    //The cells a unit at the origin can attack with its range.
+    fn attack_cells(w: &Warrior) -> usize {
+        movement::PossibleMoves::new(
+            &movement::WarriorMovement,
+            &Anywhere,
+            &terrain::Grass,
+            GridCoord([0, 0]),
+            MoveUnit(2 + w.experience.range_bonus()),
+        )
+        .iter_coords()
+        .count()
+    }
+
+    #[test]
+    fn range_upgrade_reaches_further() {
+        let mut w = Warrior::new(GridCoord([0, 0]));
+        let before = attack_cells(&w);
+        assert_eq!(before, 8);
+
+        //Health, stamina, then range.
+        w.gain_experience(LEVEL_THRESHOLDS[2]);
+        assert_eq!(w.experience.level(), 4);
+        assert_eq!(w.experience.range_bonus(), RANGE_UPGRADE);
+        assert!(attack_cells(&w) > before);
+    }
+
+    #[test]
+    fn levels_follow_thresholds() {
+        let mut w = Warrior::new(GridCoord([0, 0]));
+        w.health = 3;
+        w.gain_experience(LEVEL_THRESHOLDS[0] - 1);
+        assert_eq!(w.experience.level(), 1);
+        w.gain_experience(1);
+        assert_eq!((w.experience.level(), w.health), (2, Warrior::MAX_HEALTH));
+        w.gain_experience(u16::MAX);
+        assert_eq!(w.experience.level(), 1 + LEVEL_THRESHOLDS.len() as u8);
+        assert_eq!(w.experience.stamina_bonus(), 1);
+    }
+}
@@ -24,6 +24,9 @@ impl<Z: Zoo, A: GameStepper<Z>, F: FnOnce(A::Result, &mut Z::G<'_>) -> X, X> Gam
     fn get_animation(&self) -> Option<&crate::animation::Animation<WarriorPointer<Warrior>>> {
         self.elem.get_animation()
     }
+    fn get_forecast(&self) -> Option<&crate::combat::Forecast> {
+        self.elem.get_forecast()
+    }
 }
 
 pub enum Stage<T> {
@@ -138,6 +141,9 @@ impl<Z: Zoo, A: GameStepper<Z>, B: GameStepper<Z, Result = A::Result>> GameStepp
     fn get_animation(&self) -> Option<&crate::animation::Animation<WarriorPointer<Warrior>>> {
         self.a.get_animation()
     }
+    fn get_forecast(&self) -> Option<&crate::combat::Forecast> {
+        self.a.get_forecast()
+    }
 }
 
 pub enum Either<A, B> {
@@ -182,6 +188,13 @@ impl<Z: Zoo, A: GameStepper<Z>, B: GameStepper<Z>> GameStepper<Z> for Either<A,
             Either::B(a) => a.get_animation(),
         }
     }
+
+    fn get_forecast(&self) -> Option<&crate::combat::Forecast> {
+        match self {
+            Either::A(a) => a.get_forecast(),
+            Either::B(a) => a.get_forecast(),
+        }
+    }
 }
 
 // pub struct Optional<A> {
@@ -234,6 +247,9 @@ impl<Z: Zoo, A: GameStepper<Z>> GameStepper<Z> for Option<A> {
             None
         }
     }
+    fn get_forecast(&self) -> Option<&crate::combat::Forecast> {
+        self.as_ref().and_then(|a| a.get_forecast())
+    }
 }
 
 enum EitherOr<A, B> {
@@ -300,6 +316,13 @@ impl<Z: Zoo, A: GameStepper<Z, Result = B>, B: GameStepper<Z>> GameStepper<Z> fo
             EitherOr::None => unreachable!(),
         }
     }
+    fn get_forecast(&self) -> Option<&crate::combat::Forecast> {
+        match &self.inner {
+            EitherOr::A(a) => a.get_forecast(),
+            EitherOr::B(a) => a.get_forecast(),
+            EitherOr::None => unreachable!(),
+        }
+    }
 }
 
 pub trait GameStepper<Z: Zoo> {
@@ -318,6 +341,9 @@ pub trait GameStepper<Z: Zoo> {
     fn get_animation(&self) -> Option<&crate::animation::Animation<WarriorPointer<Warrior>>> {
         None
     }
+    fn get_forecast(&self) -> Option<&crate::combat::Forecast> {
+        None
+    }
 
     fn or<O: GameStepper<Z, Result = Self::Result>>(self, other: O) -> Or<Self, O>
     where
@@ -407,6 +433,9 @@ impl<
     fn get_animation(&self) -> Option<&crate::animation::Animation<WarriorPointer<Warrior>>> {
         self.a.as_ref().map(|a| a.get_animation()).flatten()
     }
+    fn get_forecast(&self) -> Option<&crate::combat::Forecast> {
+        self.a.as_ref().map(|a| a.get_forecast()).flatten()
+    }
     fn consume(self, g: &mut Z::G<'_>, a: Self::Int) -> Self::Result {
         a
     }
@@ -0,0 +1,326 @@
+use super::*;
+use crate::state::{GameHandle, PlayerCellAskRes, Stuff};
+
+//How far from its spot in the formation a unit will look for a free cell.
+const MAX_SPREAD: i16 = 4;
+
+///Check if a point is inside a polygon using the even-odd rule.
+pub fn contains_point(poly: &[[f32; 2]], p: [f32; 2]) -> bool {
+    let mut inside = false;
+    let mut j = poly.len().wrapping_sub(1);
+    for i in 0..poly.len() {
+        let [xi, yi] = poly[i];
+        let [xj, yj] = poly[j];
+        if (yi > p[1]) != (yj > p[1]) && p[0] < (xj - xi) * (p[1] - yi) / (yj - yi) + xi {
+            inside = !inside;
+        }
+        j = i;
+    }
+    inside
+}
+
+///The units of a tribe whose center is inside a polygon in world coordinates.
+pub fn units_in_area(tribe: &Tribe, gg: &grids::GridMatrix, poly: &[[f32; 2]]) -> Vec<GridCoord> {
+    tribe
+        .iter()
+        .filter(|a| a.selectable())
+        .map(|a| a.position)
+        .filter(|a| contains_point(poly, gg.to_world_center(a.0.into()).into()))
+        .collect()
+}
+
+///Give every unit its own destination around the target, keeping the shape of the group.
+///Units closest to the target get their spot first. No two units get the same cell.
+pub fn formation(
+    units: &[GridCoord],
+    target: GridCoord,
+    free: impl Fn(&GridCoord) -> bool,
+) -> Vec<(GridCoord, GridCoord)> {
+    if units.is_empty() {
+        return vec![];
+    }
+
+    let n = units.len() as i16;
+    let sum = units
+        .iter()
+        .fold([0, 0], |acc, a| [acc[0] + a.0[0], acc[1] + a.0[1]]);
+    let center = [sum[0] / n, sum[1] / n];
+
+    let mut order = units.to_vec();
+    order.sort_by_key(|a| ai::distance(a, &target));
+
+    let mut taken: Vec<GridCoord> = vec![];
+    let mut res = vec![];
+    for unit in order {
+        let want = GridCoord([
+            target.0[0] + unit.0[0] - center[0],
+            target.0[1] + unit.0[1] - center[1],
+        ]);
+
+        //Closest free cell to the wanted spot.
+        let mut found = None;
+        'search: for r in 0..=MAX_SPREAD {
+            let mut ring: Vec<_> = (-r..=r)
+                .flat_map(|x| (-r..=r).map(move |y| [x, y]))
+                .filter(|a| a[0].abs() == r || a[1].abs() == r)
+                .map(|a| GridCoord([want.0[0] + a[0], want.0[1] + a[1]]))
+                .collect();
+            ring.sort_by_key(|a| ai::distance(a, &target));
+
+            for c in ring {
+                if free(&c) && !taken.contains(&c) {
+                    found = Some(c);
+                    break 'search;
+                }
+            }
+        }
+
+        if let Some(dest) = found {
+            taken.push(dest);
+            res.push((unit, dest));
+        }
+    }
+    res
+}
+
+//Work out how a unit can get to its destination this turn without taking someone else's.
+fn step_toward(
+    unit: WarriorPointer<GridCoord>,
+    dest: GridCoord,
+    reserved: &[GridCoord],
+    stuff: &Stuff,
+) -> Option<(CellSelection, PlayerCellAskRes)> {
+    let w = stuff.this_team.lookup(unit);
+    let cc = state::generate_unit_possible_moves(&w, stuff);
+    let CellSelection::MoveSelection(ss, _) = &cc else {
+        unreachable!()
+    };
+
+    if movement::contains_coord(ss.iter_coords(), &dest) {
+        return Some((cc, PlayerCellAskRes::MoveTo(dest)));
+    }
+
+    let cells: Vec<_> = ss
+        .iter_coords()
+        .filter(|a| !reserved.contains(a))
+        .copied()
+        .collect();
+    let best = ai::closest(&dest, cells.iter())?;
+    if ai::distance(&best, &dest) < ai::distance(&unit.inner, &dest) {
+        Some((cc, PlayerCellAskRes::MoveTo(best)))
+    } else {
+        None
+    }
+}
+
+///Move a group of units towards a cell in formation, one after the other.
+pub fn group_move(
+    units: Vec<GridCoord>,
+    target: GridCoord,
+    stuff: &Stuff,
+) -> impl GameStepper<GameHandle, Result = ()> {
+    let free = |a: &GridCoord| {
+        stuff.grid_matrix.filter().filter(a)
+            && (units.contains(a) || stuff.this_team.find_slow(a).is_none())
+            && stuff.that_team.find_slow(a).is_none()
+    };
+    let mut plan = formation(&units, target, free);
+    log!(format!("group move:{:?}", plan));
+
+    //Pop from the back so the units in front go first.
+    plan.reverse();
+    let reserved: Vec<_> = plan.iter().map(|a| a.1).collect();
+
+    gameplay::looper(plan, move |mut plan, stuff| {
+        let next = plan.pop().and_then(|(unit, dest)| {
+            let unit = stuff.this_team.find_slow(&unit)?.slim();
+            let others: Vec<_> = reserved.iter().filter(|a| **a != dest).copied().collect();
+            let (cc, res) = step_toward(unit, dest, &others, stuff)?;
+            Some(state::handle_one_execution(unit, cc, res, stuff))
+        });
+
+        GameStepper::map(next, move |_, _| {
+            if plan.is_empty() {
+                gameplay::LooperRes::Finish(())
+            } else {
+                gameplay::LooperRes::Loop(plan)
+            }
+        })
+    })
+}
+
+///Wait for the player to drag out an area.
+pub struct WaitArea;
+impl GameStepper<GameHandle> for WaitArea {
+    type Result = Vec<[f32; 2]>;
+    type Int = ();
+    fn step(&mut self, game: &mut Stuff<'_>) -> gameplay::Stage<()> {
+        if game.area.is_some() {
+            gameplay::Stage::NextStage(())
+        } else {
+            gameplay::Stage::Stay
+        }
+    }
+    fn consume(self, game: &mut Stuff<'_>, _: ()) -> Self::Result {
+        game.area.clone().unwrap()
+    }
+}
+
+//Ask the player where the selected group should go.
+struct GroupAsk {
+    a: CellSelection,
+}
+impl GameStepper<GameHandle> for GroupAsk {
+    type Result = (Vec<GridCoord>, Option<GridCoord>);
+    type Int = Option<GridCoord>;
+    fn get_selection(&self) -> Option<&CellSelection> {
+        Some(&self.a)
+    }
+    fn step(&mut self, game: &mut Stuff<'_>) -> gameplay::Stage<Self::Int> {
+        if let Some(mouse_world) = game.mouse {
+            let cell = GridCoord(game.grid_matrix.to_grid(mouse_world.into()).into());
+
+            //Tapping one of our own units cancels.
+            if game.this_team.find_slow(&cell).is_some() {
+                gameplay::Stage::NextStage(None)
+            } else {
+                gameplay::Stage::NextStage(Some(cell))
+            }
+        } else {
+            gameplay::Stage::Stay
+        }
+    }
+    fn consume(self, _: &mut Stuff<'_>, a: Self::Int) -> Self::Result {
+        let CellSelection::GroupSelection(units) = self.a else {
+            unreachable!()
+        };
+        (units, a)
+    }
+}
+
+///Select the units inside an area and move them all with one tap.
+pub fn handle_group_select(
+    poly: &[[f32; 2]],
+    stuff: &Stuff,
+) -> impl GameStepper<GameHandle, Result = ()> {
+    let units = units_in_area(stuff.this_team, stuff.grid_matrix, poly);
+    let ask = if units.is_empty() {
+        None
+    } else {
+        Some(GroupAsk {
+            a: CellSelection::GroupSelection(units),
+        })
+    };
+
+    GameStepper::map(ask, |res, stuff| {
+        let Some((units, Some(target))) = res else {
+            return None;
+        };
+        Some(group_move(units, target, stuff))
+    })
+    .flatten()
+    .map(|_, _| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SQUARE: [[f32; 2]; 4] = [[0.0, 0.0], [2.0, 0.0], [2.0, 2.0], [0.0, 2.0]];
+
+    #[test]
+    fn points_in_square() {
+        assert!(contains_point(&SQUARE, [1.0, 1.0]));
+        assert!(!contains_point(&SQUARE, [3.0, 1.0]));
+        assert!(!contains_point(&SQUARE, [-1.0, 1.0]));
+        assert!(!contains_point(&SQUARE, [1.0, 3.0]));
+
+        //Going around the other way doesn't matter.
+        let mut reversed = SQUARE;
+        reversed.reverse();
+        assert!(contains_point(&reversed, [1.0, 1.0]));
+    }
+
+    #[test]
+    fn points_on_edges() {
+        //Edges are half open, so neighbouring areas never both get a point.
+        assert!(contains_point(&SQUARE, [0.0, 1.0]));
+        assert!(!contains_point(&SQUARE, [2.0, 1.0]));
+        assert!(contains_point(&SQUARE, [1.0, 0.0]));
+        assert!(!contains_point(&SQUARE, [1.0, 2.0]));
+    }
+
+    #[test]
+    fn points_level_with_corners() {
+        let diamond = [[1.0, 0.0], [2.0, 1.0], [1.0, 2.0], [0.0, 1.0]];
+        assert!(contains_point(&diamond, [1.0, 1.0]));
+        assert!(contains_point(&diamond, [1.5, 1.0]));
+        assert!(!contains_point(&diamond, [-0.5, 1.0]));
+        assert!(!contains_point(&diamond, [2.5, 1.0]));
+    }
+
+    #[test]
+    fn points_in_concave_polygon() {
+        //A U with the gap at the top.
+        let u = [
+            [0.0, 0.0],
+            [3.0, 0.0],
+            [3.0, 3.0],
+            [2.0, 3.0],
+            [2.0, 1.0],
+            [1.0, 1.0],
+            [1.0, 3.0],
+            [0.0, 3.0],
+        ];
+        assert!(contains_point(&u, [0.5, 2.0]));
+        assert!(contains_point(&u, [2.5, 2.0]));
+        assert!(contains_point(&u, [1.5, 0.5]));
+        assert!(!contains_point(&u, [1.5, 2.0]));
+    }
+
+    #[test]
+    fn degenerate_polygons_are_empty() {
+        assert!(!contains_point(&[], [0.0, 0.0]));
+        assert!(!contains_point(&[[0.0, 0.0]], [0.0, 0.0]));
+        assert!(!contains_point(&[[0.0, 0.0], [2.0, 2.0]], [1.0, 1.0]));
+    }
+
+    #[test]
+    fn formation_keeps_shape() {
+        let units = [GridCoord([1, 5]), GridCoord([2, 5]), GridCoord([1, 6])];
+        let plan = formation(&units, GridCoord([9, 5]), |_| true);
+        assert_eq!(
+            plan,
+            vec![
+                (GridCoord([2, 5]), GridCoord([10, 5])),
+                (GridCoord([1, 5]), GridCoord([9, 5])),
+                (GridCoord([1, 6]), GridCoord([9, 6])),
+            ]
+        );
+    }
+
+    #[test]
+    fn formation_avoids_blocked_cells() {
+        let units = [GridCoord([1, 5]), GridCoord([2, 5])];
+        let target = GridCoord([9, 5]);
+        let plan = formation(&units, target, |a| *a != target);
+        assert_eq!(plan.len(), 2);
+        assert_eq!(plan[0], (GridCoord([2, 5]), GridCoord([10, 5])));
+
+        let (unit, dest) = plan[1];
+        assert_eq!(unit, GridCoord([1, 5]));
+        assert_ne!(dest, target);
+        assert_ne!(dest, plan[0].1);
+        assert_eq!(ai::distance(&dest, &target), 1);
+    }
+
+    #[test]
+    fn closest_unit_gets_the_only_cell() {
+        let units = [GridCoord([0, 5]), GridCoord([1, 5])];
+        let target = GridCoord([9, 5]);
+        let plan = formation(&units, target, |a| *a == target);
+        assert_eq!(plan, vec![(GridCoord([1, 5]), target)]);
+
+        assert!(formation(&[], target, |_| true).is_empty());
+    }
+}
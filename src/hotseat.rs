@@ -0,0 +1,80 @@
+use super::*;
+
+///A human player sitting at the shared device.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Profile {
+    pub name: String,
+    ///Css hex colour like "#ff8800".
+    pub colour: String,
+}
+impl Profile {
+    ///Background colour used while it is this player's turn.
+    pub fn clear_colour(&self) -> [f32; 4] {
+        let hex = self.colour.trim_start_matches('#');
+        let channel = |i: usize| {
+            hex.get(i..i + 2)
+                .and_then(|a| u8::from_str_radix(a, 16).ok())
+                .unwrap_or(0) as f32
+                / 255.0
+        };
+        //Keep it dark so the board is still easy to see.
+        let dim = 0.25;
+        [channel(0) * dim, channel(2) * dim, channel(4) * dim, 1.0]
+    }
+}
+
+///Several players sharing one device. In between turns the board is hidden until
+///the next player says they are ready, and each player gets their own camera back.
+pub struct HotSeat {
+    profiles: [Profile; 2],
+    cameras: [Option<scroll::CameraState>; 2],
+    //The team whose player last had the device.
+    current: Option<usize>,
+    waiting: bool,
+}
+
+impl HotSeat {
+    pub fn new(profiles: [Profile; 2]) -> Self {
+        HotSeat {
+            profiles,
+            cameras: [None, None],
+            current: None,
+            waiting: false,
+        }
+    }
+
+    pub fn profile(&self, team: usize) -> &Profile {
+        &self.profiles[team]
+    }
+
+    ///While waiting the board must not be shown or interacted with.
+    pub fn is_waiting(&self) -> bool {
+        self.waiting
+    }
+
+    ///Call every frame with the team whose turn it is.
+    ///Returns the profile to hand the device to if the turn just changed.
+    pub fn update(&mut self, team: usize, scroll: &scroll::TouchController) -> Option<&Profile> {
+        if self.current == Some(team) {
+            return None;
+        }
+
+        if let Some(prev) = self.current {
+            self.cameras[prev] = Some(scroll.camera_state());
+        }
+        self.current = Some(team);
+        self.waiting = true;
+        Some(&self.profiles[team])
+    }
+
+    ///The next player is ready. Put their camera back where they left it.
+    pub fn ready(&mut self, scroll: &mut scroll::TouchController) {
+        if !self.waiting {
+            return;
+        }
+        self.waiting = false;
+        if let Some(c) = self.current.and_then(|a| self.cameras[a]) {
+            scroll.set_camera_state(c);
+        }
+    }
+}
@@ -1,83 +1,122 @@
 use axgeom::vec2same;
 use cgmath::{InnerSpace, Matrix4, Transform, Vector2};
 
+#[cfg(target_arch = "wasm32")]
 use gloo::console::log;
+
+//There is no console outside of the browser so the native server logs to stderr.
+#[cfg(not(target_arch = "wasm32"))]
+macro_rules! log {
+    ($($a:expr),*) => {
+        $(eprintln!("{}", $a);)*
+    };
+}
 use model::matrix::{self, MyMatrix};
+use model::text::Align;
 use movement::GridCoord;
 use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
 use shogo::simple2d::{self, ShaderSystem};
 use shogo::utils;
 use wasm_bindgen::prelude::*;
+pub mod ability;
+pub mod ai;
 pub mod animation;
+pub mod combat;
+pub mod combat_text;
 pub mod dom;
+pub mod economy;
+pub mod experience;
 pub mod gameplay;
+pub mod group;
 pub mod grids;
+pub mod hotseat;
+pub mod mail;
 pub mod model_parse;
 pub mod movement;
+pub mod orders;
 pub mod projection;
+pub mod realtime;
+pub mod route;
+pub mod run;
+pub mod save;
 pub mod scroll;
+pub mod server;
+pub mod status;
+pub mod survival;
 pub mod terrain;
 pub mod util;
 use dom::MEvent;
 use projection::*;
 pub mod state;
 //pub mod logic;
-pub const RESIZE: usize = 10;
+//Pixels around each texture in the atlas so filtering doesn't pick up its neighbours.
+//A power of two so the atlas gets mip levels, three of them with 8.
+const ATLAS_PADDING: u32 = 8;
 
-#[derive(Serialize, Deserialize, Debug, Copy, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 enum UiButton {
-    ShowRoadUi,
+    ShowAbilities(Vec<ability::Ability>),
+    ShowOrders(bool),
+    ShowCancelRoute(bool),
+    HandOff(hotseat::Profile),
+    TurnExport(String),
+    MailStatus(String),
+    Resources(i16),
+    Score { survived: u16, game_over: bool },
+    ChooseReward(Vec<run::Reward>),
+    RunSummary(run::RunSummary),
+    Realtime(bool),
     NoUi,
 }
 
+//Instances that are shaded but not grayed out.
+const LIT: model::instance::Flags = model::instance::Flags {
+    grayscale: false,
+    lighting: true,
+};
+
 pub struct WarriorDraw<'a> {
-    model: &'a MyModel,
-    drop_shadow: &'a MyModel,
+    model: &'a model_parse::InstancedModel,
     col: &'a UnitCollection<Warrior>,
 }
 impl<'a> WarriorDraw<'a> {
-    fn new(col: &'a UnitCollection<Warrior>, model: &'a MyModel, drop_shadow: &'a MyModel) -> Self {
-        Self {
-            model,
-            drop_shadow,
-            col,
-        }
-    }
-    fn draw(&self, gg: &grids::GridMatrix, draw_sys: &mut ShaderSystem, matrix: &Matrix4<f32>) {
-        for cc in self.col.elem.iter() {
-            let pos: [f32; 2] = gg.to_world_topleft(cc.position.0.into()).into();
-
-            let t = matrix::translation(pos[0], pos[1], 0.0);
-            let s = matrix::scale(1.0, 1.0, 1.0);
-            let m = matrix.chain(t).chain(s).generate();
-            let mut v = draw_sys.view(m.as_ref());
-
-            self.model
-                .draw_ext(&mut v, !cc.selectable(), false, false, true);
-        }
+    fn new(col: &'a UnitCollection<Warrior>, model: &'a model_parse::InstancedModel) -> Self {
+        Self { model, col }
     }
 
-    fn draw_shadow(
+    //Every unit of the collection in one draw.
+    fn draw(
         &self,
         gg: &grids::GridMatrix,
-        draw_sys: &mut ShaderSystem,
+        shader: &model_parse::InstanceShader,
+        ctx: &WebGl2RenderingContext,
         matrix: &Matrix4<f32>,
+        instances: &mut model::instance::Instances,
     ) {
+        instances.clear();
+        for cc in self.col.elem.iter() {
+            let pos: [f32; 2] = gg.to_world_topleft(cc.position.0.into()).into();
+            let flags = model::instance::Flags {
+                grayscale: !cc.selectable(),
+                lighting: true,
+            };
+            instances.push_at([pos[0], pos[1], 0.0], 1.0, flags);
+        }
+        shader.draw(ctx, matrix, self.model, instances);
+    }
+
+    fn push_shadows(&self, gg: &grids::GridMatrix, instances: &mut model::instance::Instances) {
         for &GridCoord(a) in self.col.elem.iter().map(|a| &a.position) {
             let pos: [f32; 2] = gg.to_world_topleft(a.into()).into();
-            let t = matrix::translation(pos[0], pos[1], 1.0);
-
-            let m = matrix.chain(t).generate();
-
-            let mut v = draw_sys.view(m.as_ref());
-            self.drop_shadow.draw(&mut v);
+            instances.push_at([pos[0], pos[1], 1.0], 1.0, LIT);
         }
     }
 
     fn draw_health_text(
         &self,
         gg: &grids::GridMatrix,
-        health_numbers: &NumberTextManager,
+        text: &TextManager,
         view_proj: &Matrix4<f32>,
         proj: &Matrix4<f32>,
         draw_sys: &mut ShaderSystem,
@@ -96,9 +135,8 @@ impl<'a> WarriorDraw<'a> {
             let s = matrix::scale(5.0, 5.0, 5.0);
             let m = new_proj.chain(s).generate();
 
-            let nn = health_numbers.get_number(ccat.health);
             let mut v = draw_sys.view(m.as_ref());
-            nn.draw_ext(&mut v, false, false, true, false);
+            text.draw(&mut v, &ccat.health.to_string(), Align::Left, false);
 
             //nn.draw(ccat.health,&ctx,&text_texture,&mut draw_sys,&m);
         }
@@ -116,17 +154,62 @@ impl<'a> WarriorDraw<'a> {
             let s = matrix::scale(5.0, 5.0, 5.0);
             let m = new_proj.chain(s).generate();
 
-            let nn = health_numbers.get_number(ccat.stamina.0);
             let mut v = draw_sys.view(m.as_ref());
-            nn.draw_ext(&mut v, false, false, true, false);
+            text.draw(&mut v, &ccat.stamina.0.to_string(), Align::Left, false);
 
             //nn.draw(ccat.health,&ctx,&text_texture,&mut draw_sys,&m);
         }
+
+        //level next to the health
+        for ccat in self.col.elem.iter() {
+            let pos: [f32; 2] = gg.to_world_topleft(ccat.position.0.into()).into();
+
+            let t = matrix::translation(pos[0] + 20.0, pos[1] + 20.0, 20.0);
+
+            let jj = view_proj.chain(t).generate();
+            let jj: &[f32; 16] = jj.as_ref();
+            let tt = matrix::translation(jj[12], jj[13], jj[14]);
+            let new_proj = proj.clone().chain(tt);
+
+            let s = matrix::scale(3.0, 3.0, 3.0);
+            let m = new_proj.chain(s).generate();
+
+            let level = ccat.experience.level().to_string();
+            let mut v = draw_sys.view(m.as_ref());
+            text.draw(&mut v, &level, Align::Left, true);
+        }
+    }
+}
+
+//Show how much health each side of an attack would lose.
+fn draw_forecast_text(
+    fc: &combat::Forecast,
+    gg: &grids::GridMatrix,
+    text: &TextManager,
+    view_proj: &Matrix4<f32>,
+    proj: &Matrix4<f32>,
+    draw_sys: &mut ShaderSystem,
+) {
+    for (GridCoord(a), damage) in [(fc.defender, fc.damage), (fc.attacker, fc.counter_damage)] {
+        let pos: [f32; 2] = gg.to_world_topleft(a.into()).into();
+
+        let t = matrix::translation(pos[0], pos[1] + 20.0, 40.0);
+
+        let jj = view_proj.chain(t).generate();
+        let jj: &[f32; 16] = jj.as_ref();
+        let tt = matrix::translation(jj[12], jj[13], jj[14]);
+        let new_proj = proj.clone().chain(tt);
+
+        let s = matrix::scale(5.0, 5.0, 5.0);
+        let m = new_proj.chain(s).generate();
+
+        let mut v = draw_sys.view(m.as_ref());
+        text.draw(&mut v, &(-damage).to_string(), Align::Left, false);
     }
 }
 
 //TODO sort this by x and then y axis!!!!!!!
-#[derive(Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct UnitCollection<T: HasPos> {
     elem: Vec<T>,
 }
@@ -189,17 +272,35 @@ impl HasPos for Warrior {
     }
 }
 
-type MyModel = model_parse::Foo<model_parse::TextureGpu, model_parse::ModelGpu>;
+type MyModel = model_parse::Foo<std::rc::Rc<model_parse::TextureGpu>, model_parse::ModelGpu>;
 
-#[derive(Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Warrior {
     position: GridCoord,
     stamina: MoveUnit,
     attacked: bool,
     health: i8,
+    effects: status::StatusEffects,
+    #[serde(default)]
+    experience: experience::Experience,
+    #[serde(default)]
+    standing: Option<orders::StandingOrder>,
+    //Cells left to walk on a route that takes more than one turn.
+    #[serde(default)]
+    route: Vec<GridCoord>,
+    //The survival wave this unit came with.
+    #[serde(default)]
+    wave: Option<u16>,
 }
 
 impl Warrior {
+    //Health numbers can only be displayed up to 10.
+    pub const MAX_HEALTH: i8 = 10;
+
+    //The last step of a move can cost more than what is left, so stop at zero.
+    fn spend_stamina(&mut self, cost: MoveUnit) {
+        self.stamina.0 = (self.stamina.0 - cost.0).max(0);
+    }
     //TODO replace with has possible moves
     fn selectable(&self) -> bool {
         !self.attacked || self.stamina.0 > 0
@@ -240,7 +341,12 @@ impl Warrior {
             position,
             stamina: MoveUnit(0),
             attacked: false,
-            health: 10,
+            health: Self::MAX_HEALTH,
+            effects: status::StatusEffects::default(),
+            experience: experience::Experience::default(),
+            standing: None,
+            route: vec![],
+            wave: None,
         }
     }
 }
@@ -248,7 +354,9 @@ impl Warrior {
 #[derive(Debug, Clone)]
 pub enum CellSelection {
     MoveSelection(movement::PossibleMoves, movement::PossibleMoves),
-    BuildSelection(GridCoord),
+    AbilitySelection(ability::Ability, movement::PossibleMoves),
+    OrderSelection(orders::OrderKind, movement::PossibleMoves),
+    GroupSelection(Vec<GridCoord>),
 }
 
 pub struct TribeFilter<'a> {
@@ -313,11 +421,40 @@ impl<T> std::ops::DerefMut for WarriorPointer<T> {
     }
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Tribe {
     warriors: Vec<UnitCollection<Warrior>>,
+    //Where recruited units show up.
+    spawn: GridCoord,
+    //Which of the unit collections recruited units join.
+    #[serde(default)]
+    recruit_kind: usize,
+    resources: i16,
+    //Each upgrade gives units more stamina every turn.
+    upgrades: u8,
+    //Number of units that died.
+    lost: u16,
 }
 impl Tribe {
-    fn get_movement_data<X>(&self, a: &WarriorPointer<X>) -> (i8, i8) {
+    fn new(warriors: Vec<UnitCollection<Warrior>>, spawn: GridCoord, resources: i16) -> Self {
+        Tribe {
+            warriors,
+            spawn,
+            recruit_kind: 0,
+            resources,
+            upgrades: 0,
+            lost: 0,
+        }
+    }
+    //Returns false if there aren't enough resources.
+    fn spend(&mut self, amount: i16) -> bool {
+        if self.resources < amount {
+            return false;
+        }
+        self.resources -= amount;
+        true
+    }
+    fn get_movement_data(&self, a: &WarriorPointer<&Warrior>) -> (i8, i8) {
         let (movement, attack) = {
             match a.val {
                 0 => (0, 2),
@@ -326,7 +463,16 @@ impl Tribe {
                 _ => unreachable!(),
             }
         };
-        (movement, attack)
+        (movement, attack + a.experience.range_bonus())
+    }
+    fn get_abilities<X>(&self, a: &WarriorPointer<X>) -> &'static [ability::Ability] {
+        use ability::Ability::*;
+        match a.val {
+            0 => &[Heal, Push, Charge, BuildRoad],
+            1 => &[Push, Charge, Poison, BuildRoad],
+            2 => &[Heal, BuildRoad],
+            _ => unreachable!(),
+        }
     }
     fn lookup(&self, a: WarriorPointer<GridCoord>) -> WarriorPointer<&Warrior> {
         self.warriors[a.val]
@@ -355,10 +501,30 @@ impl Tribe {
             .unwrap()
     }
 
+    //Remove a unit that died.
+    fn kill(&mut self, a: WarriorPointer<GridCoord>) -> WarriorPointer<Warrior> {
+        self.lost += 1;
+        self.lookup_take(a)
+    }
+
     fn add(&mut self, a: WarriorPointer<Warrior>) {
         self.warriors[a.val].elem.push(a.inner);
     }
 
+    //Add a unit that this tribe recruited or that spawned for it.
+    fn add_recruit(&mut self, inner: Warrior) {
+        let val = self.recruit_kind;
+        self.add(WarriorPointer { inner, val });
+    }
+
+    fn iter(&self) -> impl Iterator<Item = WarriorPointer<&Warrior>> {
+        self.warriors.iter().enumerate().flat_map(|(val, a)| {
+            a.elem
+                .iter()
+                .map(move |inner| WarriorPointer { inner, val })
+        })
+    }
+
     fn find_slow(&self, a: &GridCoord) -> Option<WarriorPointer<&Warrior>> {
         for (c, o) in self.warriors.iter().enumerate() {
             if let Some(k) = o.find(a) {
@@ -368,6 +534,15 @@ impl Tribe {
 
         None
     }
+    fn find_slow_mut(&mut self, a: &GridCoord) -> Option<WarriorPointer<&mut Warrior>> {
+        for (c, o) in self.warriors.iter_mut().enumerate() {
+            if let Some(k) = o.find_mut(a) {
+                return Some(WarriorPointer { inner: k, val: c });
+            }
+        }
+
+        None
+    }
     fn filter(&self) -> TribeFilter {
         TribeFilter { tribe: self }
     }
@@ -379,13 +554,35 @@ impl Tribe {
             }
         }
     }
-    fn replenish_stamina(&mut self) {
+    //Apply the status effects that happen at the start of a turn.
+    fn apply_effects(&mut self) {
         for a in self.warriors.iter_mut() {
             for b in a.elem.iter_mut() {
-                if b.stamina.0 <= 10 - 2 {
-                    b.stamina.0 += 2;
+                b.health -= b.effects.turn_damage();
+
+                if b.effects.has(status::EffectKind::Stun) {
+                    b.stamina = MoveUnit(0);
+                    b.attacked = true;
                 }
             }
+            let before = a.elem.len();
+            a.elem.retain(|b| b.health > 0);
+            self.lost += (before - a.elem.len()) as u16;
+        }
+    }
+    fn tick_effects(&mut self) {
+        for a in self.warriors.iter_mut() {
+            for b in a.elem.iter_mut() {
+                b.effects.tick();
+            }
+        }
+    }
+    fn replenish_stamina(&mut self) {
+        for a in self.warriors.iter_mut() {
+            for b in a.elem.iter_mut() {
+                let amount = 2 + self.upgrades as i8 + b.experience.stamina_bonus();
+                b.stamina.0 = (b.stamina.0 + amount).min(10);
+            }
         }
     }
 }
@@ -397,6 +594,16 @@ pub struct Game {
     grid_matrix: grids::GridMatrix,
     dogs: Tribe,
     cats: Tribe,
+    roads: terrain::Roads,
+    economy: economy::Economy,
+    //Only set when playing the survival mode.
+    survival: Option<survival::Survival>,
+    //The team that is controlled by the computer, if any.
+    ai_team: Option<usize>,
+    //Require a second tap on an enemy to confirm an attack.
+    confirm_attack: bool,
+    //Damage and such floating over the units. Not saved.
+    popups: model::popup::Popups,
 }
 
 #[wasm_bindgen]
@@ -410,6 +617,8 @@ pub async fn worker_entry() {
     let ctx = simple2d::ctx_wrap(&utils::get_context_webgl2_offscreen(&canvas));
 
     let mut draw_sys = ctx.shader_system();
+    let instance_shader = model_parse::InstanceShader::new(&ctx).unwrap_throw();
+    let mut instances = model::instance::Instances::new();
 
     //TODO get rid of this somehow.
     //these values are incorrect.
@@ -420,6 +629,11 @@ pub async fn worker_entry() {
     let mut viewport = [canvas.width() as f32, canvas.height() as f32];
 
     ctx.setup_alpha();
+    //Textures are premultiplied by the model crate.
+    ctx.blend_func(
+        WebGl2RenderingContext::ONE,
+        WebGl2RenderingContext::ONE_MINUS_SRC_ALPHA,
+    );
 
     //TODO delete
     //let gg = grids::GridMatrix::new();
@@ -440,20 +654,20 @@ pub async fn worker_entry() {
         Warrior::new(GridCoord([6, 6])),
     ]);
 
+    let rules = economy::EconomyRules::default();
+    let starting_resources = rules.starting_resources;
+
     let mut ggame = Game {
         team: 0,
-        dogs: Tribe {
-            warriors: vec![dogs],
-        },
-        cats: Tribe {
-            warriors: vec![cats],
-        },
+        dogs: Tribe::new(vec![dogs], GridCoord([4, 1]), starting_resources),
+        cats: Tribe::new(vec![cats], GridCoord([4, 8]), starting_resources),
         grid_matrix: grids::GridMatrix::new(),
-    };
-
-    let roads = terrain::TerrainCollection {
-        pos: vec![],
-        func: |a: MoveUnit| MoveUnit(a.0 / 2),
+        roads: terrain::roads(vec![]),
+        economy: economy::Economy::new(rules, vec![GridCoord([1, 4]), GridCoord([8, 5])]),
+        survival: None,
+        ai_team: None,
+        confirm_attack: true,
+        popups: model::popup::Popups::new(),
     };
 
     use cgmath::SquareMatrix;
@@ -462,44 +676,120 @@ pub async fn worker_entry() {
     let mut testo = state::create_state_machine();
     //log!(format!("size={:?}",std::mem::size_of_val(&testo)));
 
-    let quick_load = |name, res, alpha| {
-        let (data, t) = model::load_glb(name).gen_ext(ggame.grid_matrix.spacing(), res, alpha);
+    let baked = [
+        DROP_SHADOW_BAKED,
+        DOG_BAKED,
+        CAT_BAKED,
+        ROAD_BAKED,
+        GRASS_BAKED,
+        SELECT_BAKED,
+        ATTACK_BAKED,
+    ]
+    .map(|bytes| {
+        let baked = model::baked::Baked::from_bytes(bytes).unwrap_throw();
+        baked
+            .check_spacing(ggame.grid_matrix.spacing())
+            .unwrap_throw();
+        baked
+    });
+
+    //Unit and tile models that are filtered the same way share one texture so drawing
+    //them doesn't switch textures.
+    let mut groups: Vec<(model::mipmap::Sampling, Vec<usize>)> = vec![];
+    for (k, b) in baked.iter().enumerate() {
+        match groups.iter_mut().find(|g| g.0 == b.sampling) {
+            Some(g) => g.1.push(k),
+            None => groups.push((b.sampling, vec![k])),
+        }
+    }
 
-        log!(format!("texture:{:?}", (t.width, t.height)));
-        model_parse::Foo {
-            texture: model_parse::TextureGpu::new(&ctx, &t),
-            model: model_parse::ModelGpu::new(&ctx, &data),
+    //Which of them are drawn many at a time. Each model is only uploaded for the shader
+    //that draws it, so the units look the same whether they stand still or move.
+    let drawn_instanced = [true, true, true, false, true, false, false];
+
+    let mut models: Vec<Option<MyModel>> = baked.iter().map(|_| None).collect();
+    let mut instanced: Vec<Option<model_parse::InstancedModel>> =
+        baked.iter().map(|_| None).collect();
+    for (sampling, members) in groups {
+        let textures: Vec<_> = members.iter().map(|&k| baked[k].texture()).collect();
+        let tex_coords: Vec<_> = members.iter().map(|&k| baked[k].tex_coords).collect();
+        let atlas =
+            model::atlas::Atlas::pack_models(&textures, &tex_coords, ATLAS_PADDING).unwrap_throw();
+        log!(format!("atlas:{:?}", (atlas.image.width, atlas.image.height)));
+        let atlas_texture = std::rc::Rc::new(model_parse::TextureGpu::from_atlas(
+            &ctx, &atlas, &textures, sampling,
+        ));
+
+        for (texture, &k) in members.iter().enumerate() {
+            if drawn_instanced[k] {
+                instanced[k] = Some(
+                    model_parse::InstancedModel::from_baked_atlas(
+                        &ctx,
+                        &baked[k],
+                        &atlas,
+                        texture,
+                        atlas_texture.clone(),
+                    )
+                    .unwrap_throw(),
+                );
+            } else {
+                models[k] = Some(model_parse::Foo {
+                    texture: atlas_texture.clone(),
+                    model: model_parse::ModelGpu::from_baked_atlas(
+                        &ctx, &baked[k], &atlas, texture,
+                    ),
+                });
+            }
         }
-    };
+    }
 
-    let drop_shadow = quick_load(DROP_SHADOW_GLB, 1, Some(0.5));
+    let [road, select_model, attack_model] = [3, 5, 6].map(|k| models[k].take().unwrap_throw());
 
-    let dog = quick_load(DOG_GLB, RESIZE, None);
+    let [drop_shadow, dog, cat, grass] = [0, 1, 2, 4].map(|k| instanced[k].take().unwrap_throw());
 
-    let cat = quick_load(CAT_GLB, RESIZE, None);
+    let (font, text_texture) = {
+        let ascii = model::baked::Baked::from_bytes(ASCII_BAKED).unwrap_throw();
+        let img = ascii.texture();
 
-    let road = quick_load(ROAD_GLB, 1, None);
+        //The printable ascii characters sit in the cells of their codes.
+        let cells = (32..127).map(|k| (k, char::from(k as u8)));
+        let mut font = model::text::Font::grid(&img, 16, 16, cells, true);
+        font.fallback = Some('?');
 
-    let grass = quick_load(GRASS_GLB, RESIZE, None);
+        let texture = model_parse::TextureGpu::new(&ctx, &img, ascii.sampling);
+        (font, std::rc::Rc::new(texture))
+    };
 
-    let select_model = quick_load(SELECT_GLB, 1, None);
+    let text = TextManager::new(&ctx, text_texture, font);
 
-    let attack_model = quick_load(ATTACK_GLB, 1, None);
+    let mut last_abilities = vec![];
+    let mut last_orders = false;
+    let mut last_route = false;
+    let mut last_resources = -1;
+    let mut last_score = None;
 
-    let text_texture = {
-        let ascii_tex = model::load_texture_from_data(include_bytes!("../assets/ascii5.png"));
+    let mut run: Option<run::Run> = None;
 
-        model_parse::TextureGpu::new(&ctx, &ascii_tex)
-    };
+    //Set while playing in real-time instead of taking turns.
+    let mut realtime: Option<realtime::Realtime> = None;
+
+    let mut hotseat: Option<hotseat::HotSeat> = None;
 
-    let health_numbers = NumberTextManager::new(&ctx, &text_texture);
+    //Set while playing by passing turn files back and forth.
+    let mut mail: Option<mail::Mail> = None;
 
     'outer: loop {
         let mut on_select = false;
+        let mut on_area = None;
 
         let res = frame_timer.next().await;
 
         let mut end_turn = false;
+        let mut ability = None;
+        let mut recruit = false;
+        let mut upgrade = false;
+        let mut order = None;
+        let mut cancel_route = false;
         for e in res {
             match e {
                 MEvent::Resize {
@@ -526,8 +816,10 @@ pub async fn worker_entry() {
                 }
                 MEvent::TouchEnd { touches } => {
                     //log!(format!("touch end:{:?}",touches));
-                    if let scroll::MouseUp::Select = scroll_manager.on_touch_up(&touches) {
-                        on_select = true;
+                    match scroll_manager.on_touch_up(&touches) {
+                        scroll::MouseUp::Select => on_select = true,
+                        scroll::MouseUp::Area(a) => on_area = Some(a),
+                        scroll::MouseUp::NoSelect => {}
                     }
                 }
                 MEvent::CanvasMouseLeave => {
@@ -535,8 +827,10 @@ pub async fn worker_entry() {
                     let _ = scroll_manager.on_mouse_up();
                 }
                 MEvent::CanvasMouseUp => {
-                    if let scroll::MouseUp::Select = scroll_manager.on_mouse_up() {
-                        on_select = true;
+                    match scroll_manager.on_mouse_up() {
+                        scroll::MouseUp::Select => on_select = true,
+                        scroll::MouseUp::Area(a) => on_area = Some(a),
+                        scroll::MouseUp::NoSelect => {}
                     }
                 }
                 MEvent::CanvasMouseMove { x, y } => {
@@ -548,7 +842,121 @@ pub async fn worker_entry() {
                 MEvent::CanvasMouseDown { x, y } => {
                     scroll_manager.on_mouse_down([*x, *y]);
                 }
-                MEvent::ButtonClick => {}
+                MEvent::AbilityClick(a) => {
+                    ability = Some(*a);
+                }
+                MEvent::StartHotSeat { profiles } => {
+                    log!(format!("hot-seat with:{:?}", profiles));
+                    hotseat = Some(hotseat::HotSeat::new(profiles.clone()));
+                }
+                MEvent::StartMail { seed, team } => {
+                    let (m, g) = mail::Mail::new(*seed, *team);
+                    ggame = g;
+                    testo = state::create_state_machine();
+                    mail = Some(m);
+                    run = None;
+                    if realtime.take().is_some() {
+                        w.post_message(UiButton::Realtime(false));
+                    }
+                    w.post_message(UiButton::MailStatus(format!(
+                        "game seed: {} playing team: {}",
+                        seed, team
+                    )));
+                }
+                MEvent::ImportTurn(text) => {
+                    if let Some(m) = &mut mail {
+                        match m.import(text) {
+                            Ok(g) => {
+                                ggame = g;
+                                testo = state::create_state_machine();
+                                w.post_message(UiButton::MailStatus(format!(
+                                    "turn {}: your move",
+                                    m.turn()
+                                )));
+                            }
+                            Err(e) => {
+                                w.post_message(UiButton::MailStatus(format!("rejected: {}", e)));
+                            }
+                        }
+                    }
+                }
+                MEvent::HandOffReady => {
+                    if let Some(hs) = &mut hotseat {
+                        hs.ready(&mut scroll_manager);
+                    }
+                }
+                MEvent::CancelRoute => {
+                    cancel_route = true;
+                }
+                MEvent::DragMode(a) => {
+                    scroll_manager.set_mode(*a);
+                }
+                MEvent::OrderClick(a) => {
+                    order = Some(*a);
+                }
+                MEvent::RecruitClick => {
+                    recruit = true;
+                }
+                MEvent::UpgradeClick => {
+                    upgrade = true;
+                }
+                MEvent::StartSurvival => match survival::new_game() {
+                    Ok(g) => {
+                        mail = None;
+                        ggame = g;
+                        testo = state::create_state_machine();
+                        if realtime.take().is_some() {
+                            w.post_message(UiButton::Realtime(false));
+                        }
+                    }
+                    Err(e) => {
+                        log!(format!("could not start survival:{}", e));
+                    }
+                },
+                MEvent::ToggleRealtime => {
+                    if let Some(mut rt) = realtime.take() {
+                        rt.finish_all(&mut ggame);
+                        //Continue turn-based with the player to move.
+                        ggame.team = rt.player();
+                        testo = state::create_state_machine();
+                        w.post_message(UiButton::Realtime(false));
+                    } else if ggame.survival.is_some() {
+                        log!("survival waves are turn-based");
+                    } else if mail.is_some() {
+                        log!("play-by-mail is turn-based");
+                    } else if testo.get_animation().is_some() {
+                        log!("can't switch in the middle of an animation");
+                    } else {
+                        realtime = Some(realtime::Realtime::new(&ggame));
+                        w.post_message(UiButton::Realtime(true));
+                    }
+                }
+                MEvent::StartRun { seed } => {
+                    log!(format!("starting run with seed:{:?}", seed));
+                    mail = None;
+                    let mut r = run::Run::new(*seed);
+                    ggame = r.start_battle();
+                    testo = state::create_state_machine();
+                    run = Some(r);
+                    if realtime.take().is_some() {
+                        w.post_message(UiButton::Realtime(false));
+                    }
+                }
+                MEvent::ChooseReward(a) => {
+                    if let Some(r) = &mut run {
+                        if let run::RunStage::ChooseReward(choices) = &r.stage {
+                            if choices.contains(a) {
+                                r.choose_reward(*a);
+                                ggame = r.start_battle();
+                                testo = state::create_state_machine();
+                                if let Some(rt) = &mut realtime {
+                                    *rt = realtime::Realtime::new(&ggame);
+                                }
+                                w.post_message(UiButton::ChooseReward(vec![]));
+                            }
+                        }
+                    }
+                }
                 MEvent::ShutdownClick => break 'outer,
             }
         }
@@ -567,9 +975,65 @@ pub async fn worker_entry() {
         //TODO don't compute every frame?.
         let mouse_world = scroll::mouse_to_world(scroll_manager.cursor_canvas(), &matrix, viewport);
 
-        {
+        let to_world = |poly: Vec<[f32; 2]>| -> Vec<[f32; 2]> {
+            poly.into_iter()
+                .map(|a| scroll::mouse_to_world(a, &matrix, viewport))
+                .collect()
+        };
+        let area = on_area.map(to_world);
+        //Units inside the area that is still being dragged out.
+        let dragging = scroll_manager.area().map(to_world);
+
+        if let Some(hs) = &mut hotseat {
+            //Only hand off in between turns of human players.
+            let human = realtime.is_none() && ggame.ai_team != Some(ggame.team);
+            if human && testo.get_animation().is_none() {
+                if let Some(p) = hs.update(ggame.team, &scroll_manager) {
+                    w.post_message(UiButton::HandOff(p.clone()));
+                }
+            }
+        }
+        let hidden = hotseat.as_ref().map(|a| a.is_waiting()).unwrap_or(false);
+
+        let remote = mail
+            .as_ref()
+            .map(|a| !a.is_local_turn(&ggame))
+            .unwrap_or(false);
+
+        if hidden {
+            //Nobody should be able to play while the device is being handed over.
+        } else if remote {
+            //The other player's turn is replayed once their turn file is imported.
+        } else if let Some(rt) = &mut realtime {
+            if let Some(a) = order {
+                rt.give_order(&mut ggame, a);
+            }
+            if let Some(a) = &area {
+                rt.select_area(&ggame, a);
+            }
+            if cancel_route {
+                rt.cancel_orders();
+            }
+            rt.tick(&mut ggame, on_select.then_some(mouse_world));
+        } else {
             //Advance state machine.
             let mouse = on_select.then_some(mouse_world);
+            let team = ggame.team;
+
+            if let Some(m) = &mut mail {
+                m.record(mail::Input {
+                    wait: 0,
+                    tap: mouse.map(|a| GridCoord(ggame.grid_matrix.to_grid(a.into()).into())),
+                    area: area.clone(),
+                    end_turn,
+                    ability,
+                    recruit,
+                    upgrade,
+                    order,
+                    cancel_route,
+                });
+            }
+
             let [this_team, that_team] =
                 state::team_view([&mut ggame.cats, &mut ggame.dogs], ggame.team);
 
@@ -578,24 +1042,143 @@ pub async fn worker_entry() {
                 this_team,
                 that_team,
                 grid_matrix: &ggame.grid_matrix,
+                roads: &mut ggame.roads,
+                economy: &ggame.economy,
+                survival: ggame.survival.as_mut(),
+                ai_team: ggame.ai_team,
                 mouse,
                 end_turn,
+                ability,
+                recruit,
+                upgrade,
+                order,
+                area,
+                cancel_route,
+                confirm_attack: ggame.confirm_attack,
+                popups: &mut ggame.popups,
             };
             testo.step(&mut jj);
+
+            if let Some(m) = &mut mail {
+                if ggame.team != team {
+                    w.post_message(UiButton::TurnExport(m.end_turn(&ggame)));
+                }
+            }
+        }
+
+        if let Some(r) = &mut run {
+            //Don't check in the middle of an animation since the unit is taken out of its tribe.
+            let idle = testo.get_animation().is_none()
+                && realtime.as_ref().map(|a| a.is_idle()).unwrap_or(true);
+            let battle_over = idle && run::battle_winner(&ggame).is_some();
+
+            if matches!(r.stage, run::RunStage::Battle) && battle_over {
+                r.finish_battle(&ggame);
+
+                match &r.stage {
+                    run::RunStage::ChooseReward(choices) => {
+                        w.post_message(UiButton::ChooseReward(choices.clone()));
+                    }
+                    run::RunStage::Finished => {
+                        w.post_message(UiButton::RunSummary(r.summary()));
+                    }
+                    run::RunStage::Battle => {}
+                }
+            }
+        }
+
+        {
+            //Show the abilities the selected unit can use.
+            let this_team = if ggame.team == 0 {
+                &ggame.cats
+            } else {
+                &ggame.dogs
+            };
+
+            let selected = match testo.get_selection() {
+                Some(CellSelection::MoveSelection(ss, _)) => this_team.find_slow(ss.start()),
+                _ => None,
+            };
+
+            let show_orders = match &realtime {
+                Some(rt) => rt.selected().next().is_some(),
+                None => selected.is_some(),
+            };
+            if show_orders != last_orders {
+                w.post_message(UiButton::ShowOrders(show_orders));
+                last_orders = show_orders;
+            }
+
+            let has_route = match &realtime {
+                Some(rt) => rt.selected_orders().next().is_some(),
+                None => selected.as_ref().map(|a| !a.route.is_empty()).unwrap_or(false),
+            };
+            if has_route != last_route {
+                w.post_message(UiButton::ShowCancelRoute(has_route));
+                last_route = has_route;
+            }
+
+            let abilities = if let Some(unit) = selected {
+                this_team
+                    .get_abilities(&unit)
+                    .iter()
+                    .copied()
+                    .filter(|a| a.can_use(&unit, this_team, &ggame.economy.rules))
+                    .collect()
+            } else {
+                vec![]
+            };
+
+            if abilities != last_abilities {
+                w.post_message(UiButton::ShowAbilities(abilities.clone()));
+                last_abilities = abilities;
+            }
+
+            if this_team.resources != last_resources {
+                w.post_message(UiButton::Resources(this_team.resources));
+                last_resources = this_team.resources;
+            }
+
+            let score = ggame.survival.as_ref().map(|a| (a.survived, a.game_over));
+            if score != last_score {
+                if let Some((survived, game_over)) = score {
+                    w.post_message(UiButton::Score {
+                        survived,
+                        game_over,
+                    });
+                }
+                last_score = score;
+            }
         }
 
         scroll_manager.step();
 
         use matrix::*;
 
+        ggame.popups.tick();
+
         //Drawing below doesnt need mutable reference.
         //TODO move drawing to a function?
         let ggame = &ggame;
 
-        ctx.draw_clear([0.0, 0.0, 0.0, 0.0]);
+        match &hotseat {
+            Some(hs) => ctx.draw_clear(hs.profile(ggame.team).clear_colour()),
+            None => ctx.draw_clear([0.0, 0.0, 0.0, 0.0]),
+        }
+
+        //Don't show the board to the next player before they are ready.
+        if hidden {
+            ctx.flush();
+            continue;
+        }
 
         let [vvx, vvy] = get_world_rect(&matrix, &ggame.grid_matrix);
 
+        //Everything is drawn back to front. Each part sets up the pass of its models
+        //so opaque ones skip blending and blended ones don't write depth.
+        set_pass(&ctx, grass.pass());
+        //The whole visible board in one draw.
+        instances.clear();
         for a in (vvx[0]..vvx[1])
             .skip_while(|&a| a < 0)
             .take_while(|&a| a < ggame.grid_matrix.num_rows())
@@ -605,25 +1188,25 @@ pub async fn worker_entry() {
                 .skip_while(|&a| a < 0)
                 .take_while(|&a| a < ggame.grid_matrix.num_rows())
             {
-                use matrix::*;
                 let x1 = ggame.grid_matrix.spacing() * a as f32;
                 let y1 = ggame.grid_matrix.spacing() * b as f32;
-                let s = 0.99;
-                let mm = matrix
-                    .chain(translation(x1, y1, -1.0))
-                    .chain(scale(s, s, s))
-                    .generate();
-
-                let mut v = draw_sys.view(mm.as_ref());
-                grass.draw(&mut v);
+                instances.push_at([x1, y1, -1.0], 0.99, LIT);
             }
         }
+        instance_shader.draw(&ctx, &matrix, &grass, &instances);
 
-        let cat_draw = WarriorDraw::new(&ggame.cats.warriors[0], &cat, &drop_shadow);
-        let dog_draw = WarriorDraw::new(&ggame.dogs.warriors[0], &dog, &drop_shadow);
+        let cat_draw = WarriorDraw::new(&ggame.cats.warriors[0], &cat);
+        let dog_draw = WarriorDraw::new(&ggame.dogs.warriors[0], &dog);
 
         let animation_draw = if ggame.team == 0 { &cat } else { &dog };
 
+        set_pass(
+            &ctx,
+            select_model
+                .pass()
+                .max(attack_model.pass())
+                .max(road.pass()),
+        );
         disable_depth(&ctx, || {
             if let Some(a) = testo.get_selection() {
                 match a {
@@ -652,11 +1235,126 @@ pub async fn worker_entry() {
                             attack_model.draw_ext(&mut v, false, false, false, false);
                         }
                     }
-                    CellSelection::BuildSelection(_) => {}
+                    CellSelection::AbilitySelection(_, targets) => {
+                        for GridCoord(a) in targets.iter_coords() {
+                            let pos: [f32; 2] = ggame.grid_matrix.to_world_topleft(a.into()).into();
+                            let t = matrix::translation(pos[0], pos[1], 0.0);
+
+                            let m = matrix.chain(t).generate();
+
+                            let mut v = draw_sys.view(m.as_ref());
+                            select_model.draw_ext(&mut v, false, false, false, false);
+                        }
+                    }
+                    CellSelection::GroupSelection(units) => {
+                        for GridCoord(a) in units.iter() {
+                            let pos: [f32; 2] = ggame.grid_matrix.to_world_topleft(a.into()).into();
+                            let t = matrix::translation(pos[0], pos[1], 0.0);
+
+                            let m = matrix.chain(t).generate();
+
+                            let mut v = draw_sys.view(m.as_ref());
+                            select_model.draw_ext(&mut v, false, false, false, false);
+                        }
+                    }
+                    CellSelection::OrderSelection(_, cells) => {
+                        for GridCoord(a) in cells.iter_coords() {
+                            let pos: [f32; 2] = ggame.grid_matrix.to_world_topleft(a.into()).into();
+                            let t = matrix::translation(pos[0], pos[1], 0.0);
+
+                            let m = matrix.chain(t).generate();
+
+                            let mut v = draw_sys.view(m.as_ref());
+                            select_model.draw_ext(&mut v, false, false, false, false);
+                        }
+                    }
+                }
+            }
+
+            if realtime.is_none() {
+                //Routes of the team that is moving.
+                let this_team = if ggame.team == 0 {
+                    &ggame.cats
+                } else {
+                    &ggame.dogs
+                };
+                for GridCoord(a) in this_team.iter().flat_map(|a| a.inner.route.iter()) {
+                    let pos: [f32; 2] = ggame.grid_matrix.to_world_topleft(a.into()).into();
+                    let t = matrix::translation(pos[0], pos[1], 0.0);
+
+                    let m = matrix.chain(t).generate();
+
+                    let mut v = draw_sys.view(m.as_ref());
+                    select_model.draw_ext(&mut v, true, false, false, false);
+                }
+            }
+
+            if let Some(poly) = &dragging {
+                let this_team = if ggame.team == 0 {
+                    &ggame.cats
+                } else {
+                    &ggame.dogs
+                };
+                for GridCoord(a) in group::units_in_area(this_team, &ggame.grid_matrix, poly) {
+                    let pos: [f32; 2] = ggame.grid_matrix.to_world_topleft(a.into()).into();
+                    let t = matrix::translation(pos[0], pos[1], 0.0);
+
+                    let m = matrix.chain(t).generate();
+
+                    let mut v = draw_sys.view(m.as_ref());
+                    select_model.draw_ext(&mut v, true, false, false, false);
+                }
+            }
+
+            if let Some(rt) = &realtime {
+                for GridCoord(a) in rt.selected() {
+                    let pos: [f32; 2] = ggame.grid_matrix.to_world_topleft(a.into()).into();
+                    let t = matrix::translation(pos[0], pos[1], 0.0);
+
+                    let m = matrix.chain(t).generate();
+
+                    let mut v = draw_sys.view(m.as_ref());
+                    select_model.draw_ext(&mut v, false, false, false, false);
+                }
+
+                for o in rt.selected_orders() {
+                    let (GridCoord(a), model) = match o {
+                        realtime::Order::MoveTo(a) => (a, &select_model),
+                        realtime::Order::Attack(a) => (a, &attack_model),
+                    };
+                    let pos: [f32; 2] = ggame.grid_matrix.to_world_topleft(a.into()).into();
+                    let t = matrix::translation(pos[0], pos[1], 0.0);
+
+                    let m = matrix.chain(t).generate();
+
+                    let mut v = draw_sys.view(m.as_ref());
+                    model.draw_ext(&mut v, true, false, false, false);
+                }
+            }
+
+            for GridCoord(a) in ggame.economy.resource_cells.iter() {
+                let pos: [f32; 2] = ggame.grid_matrix.to_world_topleft(a.into()).into();
+                let t = matrix::translation(pos[0], pos[1], 0.0);
+
+                let m = matrix.chain(t).generate();
+
+                let mut v = draw_sys.view(m.as_ref());
+                select_model.draw_ext(&mut v, true, false, false, false);
+            }
+
+            if let Some(s) = &ggame.survival {
+                for GridCoord(a) in s.data.objectives.iter() {
+                    let pos: [f32; 2] = ggame.grid_matrix.to_world_topleft(a.into()).into();
+                    let t = matrix::translation(pos[0], pos[1], 0.0);
+
+                    let m = matrix.chain(t).generate();
+
+                    let mut v = draw_sys.view(m.as_ref());
+                    attack_model.draw_ext(&mut v, true, false, false, false);
                 }
             }
 
-            for GridCoord(a) in roads.pos.iter() {
+            for GridCoord(a) in ggame.roads.pos.iter() {
                 let pos: [f32; 2] = ggame.grid_matrix.to_world_topleft(a.into()).into();
                 let t = matrix::translation(pos[0], pos[1], 3.0);
 
@@ -667,53 +1365,103 @@ pub async fn worker_entry() {
             }
         });
 
+        set_pass(&ctx, drop_shadow.pass());
         disable_depth(&ctx, || {
-            //draw dropshadow
-
-            cat_draw.draw_shadow(&ggame.grid_matrix, &mut draw_sys, &matrix);
-            dog_draw.draw_shadow(&ggame.grid_matrix, &mut draw_sys, &matrix);
+            //Every drop shadow in one draw.
+            instances.clear();
+            cat_draw.push_shadows(&ggame.grid_matrix, &mut instances);
+            dog_draw.push_shadows(&ggame.grid_matrix, &mut instances);
 
             if let Some(a) = &testo.get_animation() {
                 let pos = a.calc_pos();
-                let t = matrix::translation(pos[0], pos[1], 1.0);
-
-                let m = matrix.chain(t).generate();
+                instances.push_at([pos[0], pos[1], 1.0], 1.0, LIT);
+            }
 
-                let mut v = draw_sys.view(m.as_ref());
-                drop_shadow.draw(&mut v);
+            for (_, a) in realtime.iter().flat_map(|a| a.animations()) {
+                let pos = a.calc_pos();
+                instances.push_at([pos[0], pos[1], 1.0], 1.0, LIT);
             }
+
+            instance_shader.draw(&ctx, &matrix, &drop_shadow, &instances);
         });
 
-        if let Some(a) = &testo.get_animation() {
-            let pos = a.calc_pos();
-            let t = matrix::translation(pos[0], pos[1], 0.0);
-            let s = matrix::scale(1.0, 1.0, 1.0);
-            let m = matrix.chain(t).chain(s).generate();
-            let mut v = draw_sys.view(m.as_ref());
+        //The units are the only models drawn over each other with depth testing, so
+        //they go in order of pass with opaque ones first.
+        let mut passes = vec![cat.pass(), dog.pass()];
+        passes.sort();
+        passes.dedup();
+        for pass in passes {
+            set_pass(&ctx, pass);
+            if let Some(a) = &testo.get_animation() {
+                if animation_draw.pass() == pass {
+                    let pos = a.calc_pos();
+                    instances.clear();
+                    instances.push_at([pos[0], pos[1], 0.0], 1.0, LIT);
+                    instance_shader.draw(&ctx, &matrix, animation_draw, &instances);
+                }
+            }
 
-            animation_draw.draw(&mut v);
-        }
+            for (team, a) in realtime.iter().flat_map(|a| a.animations()) {
+                let model = if team == 0 { &cat } else { &dog };
+                if model.pass() != pass {
+                    continue;
+                }
+                let pos = a.calc_pos();
+                instances.clear();
+                instances.push_at([pos[0], pos[1], 0.0], 1.0, LIT);
+                instance_shader.draw(&ctx, &matrix, model, &instances);
+            }
 
-        cat_draw.draw(&ggame.grid_matrix, &mut draw_sys, &matrix);
-        dog_draw.draw(&ggame.grid_matrix, &mut draw_sys, &matrix);
+            let gg = &ggame.grid_matrix;
+            if cat.pass() == pass {
+                cat_draw.draw(gg, &instance_shader, &ctx, &matrix, &mut instances);
+            }
+            if dog.pass() == pass {
+                dog_draw.draw(gg, &instance_shader, &ctx, &matrix, &mut instances);
+            }
+        }
 
+        set_pass(&ctx, model::Pass::Blend);
         disable_depth(&ctx, || {
             cat_draw.draw_health_text(
                 &ggame.grid_matrix,
-                &health_numbers,
+                &text,
                 &view_proj,
                 &proj,
                 &mut draw_sys,
             );
             dog_draw.draw_health_text(
                 &ggame.grid_matrix,
-                &health_numbers,
+                &text,
                 &view_proj,
                 &proj,
                 &mut draw_sys,
             );
+
+            if let Some(fc) = testo.get_forecast() {
+                draw_forecast_text(
+                    fc,
+                    &ggame.grid_matrix,
+                    &text,
+                    &view_proj,
+                    &proj,
+                    &mut draw_sys,
+                );
+            }
+
+            text.draw_popups(
+                &instance_shader,
+                &ggame.popups,
+                &view_proj,
+                &proj,
+                &mut instances,
+            );
         });
 
+        text.end_frame();
+
+        //The depth buffer is only cleared while depth writes are on.
+        ctx.depth_mask(true);
         ctx.flush();
     }
 
@@ -722,6 +1470,16 @@ pub async fn worker_entry() {
     log!("worker thread closing");
 }
 
+//Opaque models don't need blending. The instance shader discards the cut out pixels of
+//masked models but the simple2d one can't, so they still blend to make them vanish.
+fn set_pass(ctx: &WebGl2RenderingContext, pass: model::Pass) {
+    match pass {
+        model::Pass::Opaque => ctx.disable(WebGl2RenderingContext::BLEND),
+        _ => ctx.enable(WebGl2RenderingContext::BLEND),
+    }
+    ctx.depth_mask(pass != model::Pass::Blend);
+}
+
 fn disable_depth(ctx: &WebGl2RenderingContext, func: impl FnOnce()) {
     ctx.disable(WebGl2RenderingContext::DEPTH_TEST);
     ctx.disable(WebGl2RenderingContext::CULL_FACE);
@@ -732,121 +1490,102 @@ fn disable_depth(ctx: &WebGl2RenderingContext, func: impl FnOnce()) {
     ctx.enable(WebGl2RenderingContext::CULL_FACE);
 }
 
-//TODO just use reference???
-fn string_to_coords<'a>(st: &str) -> model::ModelData {
-    let num_rows = 16;
-    let num_columns = 16;
-
-    let mut tex_coords = vec![];
-    let mut counter = 0.0;
-    let dd = 20.0;
-    let mut positions = vec![];
-
-    let mut inds = vec![];
-    for (_, a) in st.chars().enumerate() {
-        let ascii = a as u8;
-        let index = (ascii - 0/*32*/) as u16;
-
-        //log!(format!("aaaa:{:?}",index));
-        let x = (index % num_rows) as f32 / num_rows as f32;
-        let y = (index / num_rows) as f32 / num_columns as f32;
-
-        let x1 = x;
-        let x2 = x1 + 1.0 / num_rows as f32;
-
-        let y1 = y;
-        let y2 = y + 1.0 / num_columns as f32;
-
-        let a = [[x1, y1], [x2, y1], [x1, y2], [x2, y2]];
-
-        tex_coords.extend(a);
-
-        let iii = [0u16, 1, 2, 2, 1, 3].map(|a| positions.len() as u16 + a);
-
-        let xx1 = counter;
-        let xx2 = counter + dd;
-        let yy1 = dd;
-        let yy2 = 0.0;
-
-        let zz = 0.0;
-        let y = [
-            [xx1, yy1, zz],
-            [xx2, yy1, zz],
-            [xx1, yy2, zz],
-            [xx2, yy2, zz],
-        ];
-
-        positions.extend(y);
-
-        inds.extend(iii);
-
-        assert!(ascii >= 32);
-        counter += dd;
-    }
-
-    let normals = positions.iter().map(|_| [0.0, 0.0, 1.0]).collect();
-
-    let cc = 1.0 / dd;
-    let mm = matrix::scale(cc, cc, cc).generate();
-
-    let positions = positions
-        .into_iter()
-        .map(|a| mm.transform_point(a.into()).into())
-        .collect();
-
-    model::ModelData {
-        positions,
-        tex_coords,
-        indices: Some(inds),
-        normals,
-        matrix: mm,
-    }
-}
-
 use web_sys::WebGl2RenderingContext;
 
 use crate::gameplay::GameStepper;
 use crate::movement::{Filter, MoveUnit};
 use crate::terrain::MoveCost;
 
-const SELECT_GLB: &'static [u8] = include_bytes!("../assets/select_model.glb");
-const DROP_SHADOW_GLB: &'static [u8] = include_bytes!("../assets/drop_shadow.glb");
-const ROAD_GLB: &'static [u8] = include_bytes!("../assets/road.glb");
-const ATTACK_GLB: &'static [u8] = include_bytes!("../assets/attack.glb");
+//Made from the files in assets by bake.sh.
+static SELECT_BAKED: &[u8] = model::include_baked!("../assets/baked/select_model.bake");
+static DROP_SHADOW_BAKED: &[u8] = model::include_baked!("../assets/baked/drop_shadow.bake");
+static ROAD_BAKED: &[u8] = model::include_baked!("../assets/baked/road.bake");
+static ATTACK_BAKED: &[u8] = model::include_baked!("../assets/baked/attack.bake");
 
 // const SHADED_GLB: &'static [u8] = include_bytes!("../assets/shaded.glb");
 // const KEY_GLB: &'static [u8] = include_bytes!("../assets/key.glb");
 // const PERSON_GLB: &'static [u8] = include_bytes!("../assets/person-v1.glb");
-const CAT_GLB: &'static [u8] = include_bytes!("../assets/donut.glb");
-const DOG_GLB: &'static [u8] = include_bytes!("../assets/cat_final.glb");
-
-const GRASS_GLB: &'static [u8] = include_bytes!("../assets/grass.glb");
-
-pub struct NumberTextManager<'a> {
-    numbers: Vec<model_parse::ModelGpu>,
-    texture: &'a model_parse::TextureGpu,
+static CAT_BAKED: &[u8] = model::include_baked!("../assets/baked/donut.bake");
+static DOG_BAKED: &[u8] = model::include_baked!("../assets/baked/cat_final.bake");
+
+static GRASS_BAKED: &[u8] = model::include_baked!("../assets/baked/grass.bake");
+
+static ASCII_BAKED: &[u8] = model::include_baked!("../assets/baked/ascii5.bake");
+
+///Draws any text in the game font. Each string is only laid out and uploaded once
+///while it keeps being drawn.
+pub struct TextManager<'a> {
+    ctx: &'a WebGl2RenderingContext,
+    font: model::text::Font,
+    texture: std::rc::Rc<model_parse::TextureGpu>,
+    cache: RefCell<model::text::TextCache<model_parse::ModelGpu>>,
+    //Popups fade so they go through the instance shader.
+    instanced: RefCell<model::text::TextCache<model_parse::InstancedModel>>,
 }
-impl<'a> NumberTextManager<'a> {
-    fn new(ctx: &WebGl2RenderingContext, texture: &'a model_parse::TextureGpu) -> Self {
-        let range = -10..=10;
-        fn generate_number(number: i8, ctx: &WebGl2RenderingContext) -> model_parse::ModelGpu {
-            let data = string_to_coords(&format!("{}", number));
-            model_parse::ModelGpu::new(ctx, &data)
+impl<'a> TextManager<'a> {
+    fn new(
+        ctx: &'a WebGl2RenderingContext,
+        texture: std::rc::Rc<model_parse::TextureGpu>,
+        font: model::text::Font,
+    ) -> Self {
+        Self {
+            ctx,
+            font,
+            texture,
+            cache: RefCell::new(model::text::TextCache::new()),
+            instanced: RefCell::new(model::text::TextCache::new()),
         }
+    }
 
-        let numbers = range.into_iter().map(|i| generate_number(i, ctx)).collect();
-        Self { numbers, texture }
+    //One unit of the view is one line of text.
+    fn draw(&self, view: &mut simple2d::View, text: &str, align: Align, grayscale: bool) {
+        let mut cache = self.cache.borrow_mut();
+        let model = cache.get_or_insert_with(text, align, || {
+            let data = model::text::layout(&self.font, text, align);
+            model_parse::ModelGpu::new(self.ctx, &data)
+        });
+
+        model_parse::Foo {
+            texture: &*self.texture,
+            model,
+        }
+        .draw_ext(view, grayscale, false, true, false);
     }
 
-    fn get_number(
+    //Popups are placed like the health numbers and float up in screen space.
+    fn draw_popups(
         &self,
-        num: i8,
-    ) -> model_parse::Foo<&model_parse::TextureGpu, &model_parse::ModelGpu> {
-        let gpu = &self.numbers[(num + 10) as usize];
+        shader: &model_parse::InstanceShader,
+        popups: &model::popup::Popups,
+        view_proj: &Matrix4<f32>,
+        proj: &Matrix4<f32>,
+        instances: &mut model::instance::Instances,
+    ) {
+        let mut cache = self.instanced.borrow_mut();
+        for p in popups.iter().filter(|p| p.is_visible()) {
+            let t = matrix::translation(p.pos[0], p.pos[1], p.pos[2]);
+            let jj = view_proj.chain(t).generate();
+            let jj: &[f32; 16] = jj.as_ref();
 
-        model_parse::Foo {
-            texture: &self.texture,
-            model: gpu,
+            let m = matrix::translation(jj[12], jj[13], jj[14])
+                .chain(matrix::scale(5.0, 5.0, 5.0))
+                .chain(matrix::translation(0.0, p.rise(), 0.0))
+                .generate();
+
+            let model = cache.get_or_insert_with(&p.text, Align::Centre, || {
+                let data = model::text::layout(&self.font, &p.text, Align::Centre);
+                model_parse::InstancedModel::new(self.ctx, &data, self.texture.clone())
+                    .unwrap_throw()
+            });
+            instances.clear();
+            instances.push_faded(&m, model::instance::Flags::default(), p.alpha());
+            shader.draw(self.ctx, proj, model, instances);
         }
     }
+
+    //Forget the text that wasn't drawn this frame.
+    fn end_frame(&self) {
+        self.cache.borrow_mut().end_frame();
+        self.instanced.borrow_mut().end_frame();
+    }
 }
@@ -0,0 +1,502 @@
+use super::*;
+use crate::state::Stuff;
+
+//Bumped whenever the format of a turn file changes.
+const VERSION: u32 = 1;
+
+//Give up on a turn file that doesn't end the turn after this many frames.
+const MAX_FRAMES: u32 = 1_000_000;
+
+///Something that went wrong importing a turn.
+#[derive(Debug, Clone, PartialEq)]
+pub enum MailError {
+    ///The text isn't a turn file.
+    Malformed,
+    ///The text was changed or cut off on the way.
+    Checksum,
+    ///Made with a different version of the game.
+    Version(u32),
+    ///Belongs to a game with a different seed.
+    WrongGame,
+    ///A turn was skipped or imported twice.
+    WrongTurn { expected: u32, found: u32 },
+    ///It isn't the other player's turn.
+    NotTheirTurn,
+    ///Applying the moves didn't give the same game as on the other side.
+    Desync,
+}
+
+impl std::fmt::Display for MailError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MailError::Malformed => write!(f, "not a turn file"),
+            MailError::Checksum => write!(f, "turn file is damaged"),
+            MailError::Version(a) => write!(f, "turn file is from version {}", a),
+            MailError::WrongGame => write!(f, "turn file is for a different game"),
+            MailError::WrongTurn { expected, found } => {
+                write!(f, "expected turn {} but got turn {}", expected, found)
+            }
+            MailError::NotTheirTurn => write!(f, "it is not the other player's turn"),
+            MailError::Desync => write!(f, "turn does not match this game"),
+        }
+    }
+}
+
+fn is_zero(a: &u32) -> bool {
+    *a == 0
+}
+
+fn is_false(a: &bool) -> bool {
+    !*a
+}
+
+///What the player did on one frame of their turn. Frames without input are only counted.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Input {
+    ///Frames that went by without input before this one.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub wait: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tap: Option<GridCoord>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub area: Option<Vec<[f32; 2]>>,
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub end_turn: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ability: Option<ability::Ability>,
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub recruit: bool,
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub upgrade: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub order: Option<orders::OrderKind>,
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub cancel_route: bool,
+}
+
+impl Input {
+    pub fn is_empty(&self) -> bool {
+        self.tap.is_none()
+            && self.area.is_none()
+            && !self.end_turn
+            && self.ability.is_none()
+            && !self.recruit
+            && !self.upgrade
+            && self.order.is_none()
+            && !self.cancel_route
+    }
+}
+
+///Everything one player did in one turn.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TurnFile {
+    pub version: u32,
+    pub seed: u64,
+    pub turn: u32,
+    pub team: usize,
+    ///Hash of the game before the turn was played.
+    pub before: u64,
+    ///Hash of the game after the turn was played.
+    pub after: u64,
+    pub inputs: Vec<Input>,
+}
+
+impl TurnFile {
+    ///Turn into a URL-safe string with a checksum at the end.
+    pub fn encode(&self) -> String {
+        let data = serde_json::to_vec(self).unwrap();
+        format!("{}.{:016x}", base64_encode(&data), fnv(&data))
+    }
+
+    pub fn decode(a: &str) -> Result<Self, MailError> {
+        let (data, check) = a.trim().split_once('.').ok_or(MailError::Malformed)?;
+        let data = base64_decode(data).ok_or(MailError::Malformed)?;
+        let check = u64::from_str_radix(check, 16).map_err(|_| MailError::Malformed)?;
+        if fnv(&data) != check {
+            return Err(MailError::Checksum);
+        }
+        let file: TurnFile = serde_json::from_slice(&data).map_err(|_| MailError::Malformed)?;
+        if file.version != VERSION {
+            return Err(MailError::Version(file.version));
+        }
+        Ok(file)
+    }
+}
+
+//FNV-1a
+fn fnv(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf2_9ce4_8422_2325, |acc, &a| {
+        (acc ^ a as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+//Base64 with the URL-safe alphabet and no padding.
+fn base64_encode(data: &[u8]) -> String {
+    let mut res = String::with_capacity(data.len() * 4 / 3 + 3);
+    for chunk in data.chunks(3) {
+        let b = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+        for i in 0..chunk.len() + 1 {
+            res.push(BASE64[(n >> (18 - 6 * i) & 63) as usize] as char);
+        }
+    }
+    res
+}
+
+fn base64_decode(a: &str) -> Option<Vec<u8>> {
+    let mut res = Vec::with_capacity(a.len() * 3 / 4);
+    let mut acc = 0u32;
+    let mut bits = 0;
+    for c in a.bytes() {
+        let v = BASE64.iter().position(|&b| b == c)? as u32;
+        acc = acc << 6 | v;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            res.push((acc >> bits) as u8);
+            acc &= (1 << bits) - 1;
+        }
+    }
+    //A lone character at the end can't be a whole byte and the encoder leaves the
+    //unused bits at zero.
+    (bits < 6 && acc == 0).then_some(res)
+}
+
+///Hash of everything in the game that a turn can change.
+pub fn game_hash(game: &Game) -> u64 {
+    fnv(game.save().to_json().as_bytes())
+}
+
+///Both players create the same game from the seed.
+pub fn new_game(seed: u64) -> Game {
+    let mut game = run::Run::new(seed).start_battle();
+    game.ai_team = None;
+    game
+}
+
+//Step the state machine one frame with the given input.
+fn step(game: &mut Game, machine: &mut impl GameStepper<state::GameHandle>, input: &Input) {
+    let mouse = input
+        .tap
+        .map(|a| game.grid_matrix.to_world_center(a.0.into()).into());
+    let [this_team, that_team] = state::team_view([&mut game.cats, &mut game.dogs], game.team);
+
+    let mut stuff = Stuff {
+        team: &mut game.team,
+        this_team,
+        that_team,
+        grid_matrix: &game.grid_matrix,
+        roads: &mut game.roads,
+        economy: &game.economy,
+        survival: game.survival.as_mut(),
+        ai_team: game.ai_team,
+        mouse,
+        end_turn: input.end_turn,
+        ability: input.ability,
+        recruit: input.recruit,
+        upgrade: input.upgrade,
+        order: input.order,
+        area: input.area.clone(),
+        cancel_route: input.cancel_route,
+        confirm_attack: game.confirm_attack,
+        popups: &mut game.popups,
+    };
+    machine.step(&mut stuff);
+    //A replayed frame is a frame like any other.
+    game.popups.tick();
+}
+
+///A game played by passing turn files back and forth.
+///Both sides keep the game as it was at the start of the current turn so the other player's turn can be replayed from it.
+pub struct Mail {
+    seed: u64,
+    //The team played on this device.
+    team: usize,
+    turn: u32,
+    start: save::SaveGame,
+    inputs: Vec<Input>,
+    wait: u32,
+}
+
+impl Mail {
+    pub fn new(seed: u64, team: usize) -> (Mail, Game) {
+        let game = new_game(seed);
+        let mail = Mail {
+            seed,
+            team,
+            turn: 0,
+            start: game.save(),
+            inputs: vec![],
+            wait: 0,
+        };
+        (mail, game)
+    }
+
+    ///Whether the player on this device is the one moving.
+    pub fn is_local_turn(&self, game: &Game) -> bool {
+        game.team == self.team
+    }
+
+    pub fn turn(&self) -> u32 {
+        self.turn
+    }
+
+    ///Call once for every frame the state machine was stepped during the local turn.
+    pub fn record(&mut self, mut input: Input) {
+        if input.is_empty() {
+            self.wait += 1;
+        } else {
+            input.wait = self.wait;
+            self.inputs.push(input);
+            self.wait = 0;
+        }
+    }
+
+    //The game is now at the start of the next turn.
+    fn next_turn(&mut self, game: &Game) {
+        self.turn += 1;
+        self.start = game.save();
+        self.inputs.clear();
+        self.wait = 0;
+    }
+
+    ///Call once the local turn has ended. Returns the turn file to send to the other player.
+    pub fn end_turn(&mut self, game: &Game) -> String {
+        let file = TurnFile {
+            version: VERSION,
+            seed: self.seed,
+            turn: self.turn,
+            team: self.team,
+            before: fnv(self.start.to_json().as_bytes()),
+            after: game_hash(game),
+            inputs: std::mem::take(&mut self.inputs),
+        };
+        self.next_turn(game);
+        file.encode()
+    }
+
+    ///Check a turn file from the other player and replay it.
+    ///Returns the game at the start of the local turn. Nothing changes if the file is rejected.
+    pub fn import(&mut self, a: &str) -> Result<Game, MailError> {
+        let file = TurnFile::decode(a)?;
+        if file.seed != self.seed {
+            return Err(MailError::WrongGame);
+        }
+        if file.turn != self.turn {
+            return Err(MailError::WrongTurn {
+                expected: self.turn,
+                found: file.turn,
+            });
+        }
+        if file.team == self.team || file.team != self.start.team {
+            return Err(MailError::NotTheirTurn);
+        }
+        if file.before != fnv(self.start.to_json().as_bytes()) {
+            return Err(MailError::Desync);
+        }
+
+        let mut game = Game::load(self.start.clone());
+        let mut machine = state::create_state_machine();
+        let idle = Input::default();
+        let mut frames: u32 = 0;
+        for input in file.inputs.iter() {
+            //The turn has to end with the last input and not before.
+            if game.team != file.team {
+                return Err(MailError::Desync);
+            }
+            if input.wait >= MAX_FRAMES {
+                return Err(MailError::Malformed);
+            }
+            frames = frames
+                .checked_add(input.wait + 1)
+                .filter(|&a| a <= MAX_FRAMES)
+                .ok_or(MailError::Malformed)?;
+            for _ in 0..input.wait {
+                step(&mut game, &mut machine, &idle);
+            }
+            step(&mut game, &mut machine, input);
+        }
+
+        if game.team == file.team || game_hash(&game) != file.after {
+            return Err(MailError::Desync);
+        }
+
+        self.next_turn(&game);
+        Ok(game)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn base64_round_trips() {
+        let data: Vec<u8> = (0..=255).collect();
+        for len in 0..8 {
+            let a = base64_encode(&data[..len]);
+            assert_eq!(base64_decode(&a).as_deref(), Some(&data[..len]));
+        }
+        let a = base64_encode(&data);
+        assert_eq!(base64_decode(&a), Some(data));
+
+        assert_eq!(base64_encode(b"hello"), "aGVsbG8");
+        assert_eq!(base64_encode(&[0xfb, 0xff]), "-_8");
+    }
+
+    #[test]
+    fn base64_rejects_garbage() {
+        assert_eq!(base64_decode("aGVs+G8"), None);
+        assert_eq!(base64_decode("aGVsbG8="), None);
+        //One character too many to be a whole number of bytes.
+        assert_eq!(base64_decode("aGVsb"), None);
+        //The unused bits of the last character are set.
+        assert_eq!(base64_decode("aGVsbG9"), None);
+    }
+
+    fn file() -> TurnFile {
+        TurnFile {
+            version: VERSION,
+            seed: 42,
+            turn: 3,
+            team: 1,
+            before: 7,
+            after: 9,
+            inputs: vec![
+                Input {
+                    wait: 12,
+                    tap: Some(GridCoord([2, 3])),
+                    ..Input::default()
+                },
+                Input {
+                    end_turn: true,
+                    ..Input::default()
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn turn_file_round_trips() {
+        let a = file().encode();
+        assert_eq!(TurnFile::decode(&a), Ok(file()));
+        //Pasting often picks up whitespace around it.
+        assert_eq!(TurnFile::decode(&format!("\n {} \n", a)), Ok(file()));
+    }
+
+    #[test]
+    fn damaged_turn_files_are_rejected() {
+        let a = file().encode();
+        let (data, check) = a.split_once('.').unwrap();
+
+        //Swap one character of the data for another valid one.
+        let mut flipped = data.as_bytes().to_vec();
+        flipped[10] = if flipped[10] == b'A' { b'B' } else { b'A' };
+        let flipped = format!("{}.{}", String::from_utf8(flipped).unwrap(), check);
+        assert_eq!(TurnFile::decode(&flipped), Err(MailError::Checksum));
+
+        let wrong_check = format!(
+            "{}.{:016x}",
+            data,
+            u64::from_str_radix(check, 16).unwrap() ^ 1
+        );
+        assert_eq!(TurnFile::decode(&wrong_check), Err(MailError::Checksum));
+        assert_eq!(
+            TurnFile::decode(&format!("{}.xyz", data)),
+            Err(MailError::Malformed)
+        );
+    }
+
+    #[test]
+    fn truncated_turn_files_are_rejected() {
+        let a = file().encode();
+        let dot = a.find('.').unwrap();
+        for len in [0, 1, dot / 2, dot, dot + 1, a.len() - 1] {
+            assert!(TurnFile::decode(&a[..len]).is_err(), "{}", len);
+        }
+        assert_eq!(TurnFile::decode(&a[..dot / 2]), Err(MailError::Malformed));
+
+        //Cut short on a whole number of bytes with a matching checksum it is still not a turn.
+        let data = serde_json::to_vec(&file()).unwrap();
+        let cut = &data[..data.len() / 2];
+        let a = format!("{}.{:016x}", base64_encode(cut), fnv(cut));
+        assert_eq!(TurnFile::decode(&a), Err(MailError::Malformed));
+    }
+
+    #[test]
+    fn other_versions_are_rejected() {
+        let old = TurnFile {
+            version: VERSION + 1,
+            ..file()
+        };
+        assert_eq!(
+            TurnFile::decode(&old.encode()),
+            Err(MailError::Version(VERSION + 1))
+        );
+    }
+
+    #[test]
+    fn turns_replay_on_the_other_side() {
+        let (mut a, mut game) = Mail::new(5, 0);
+        let (mut b, _) = Mail::new(5, 1);
+        assert!(a.is_local_turn(&game));
+
+        let mut machine = state::create_state_machine();
+        for frame in 0.. {
+            assert!(frame < 1000, "the turn never ended");
+            let input = Input {
+                end_turn: frame >= 3,
+                ..Input::default()
+            };
+            step(&mut game, &mut machine, &input);
+            a.record(input);
+            if game.team != 0 {
+                break;
+            }
+        }
+        let file = a.end_turn(&game);
+
+        let replayed = b.import(&file).unwrap();
+        assert_eq!(game_hash(&replayed), game_hash(&game));
+        assert_eq!((a.turn(), b.turn()), (1, 1));
+        assert!(b.is_local_turn(&replayed));
+        assert!(matches!(
+            b.import(&file),
+            Err(MailError::WrongTurn {
+                expected: 1,
+                found: 0
+            })
+        ));
+    }
+
+    #[test]
+    fn long_waits_are_rejected() {
+        let (mut b, _) = Mail::new(5, 1);
+        let before = fnv(b.start.to_json().as_bytes());
+        for wait in [u32::MAX, MAX_FRAMES] {
+            let file = TurnFile {
+                version: VERSION,
+                seed: 5,
+                turn: 0,
+                team: 0,
+                before,
+                after: 0,
+                inputs: vec![Input {
+                    wait,
+                    ..Input::default()
+                }],
+            };
+            assert!(matches!(
+                b.import(&file.encode()),
+                Err(MailError::Malformed)
+            ));
+        }
+        assert_eq!(b.turn(), 0);
+    }
+}
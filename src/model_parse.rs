@@ -6,10 +6,74 @@ pub struct TextureGpu {
     texture: simple2d::TextureBuffer,
 }
 impl TextureGpu {
-    pub fn new(ctx: &web_sys::WebGl2RenderingContext, tt: &model::Img) -> Self {
-        let mut texture = simple2d::TextureBuffer::new(&ctx);
+    ///Upload a texture along with the mip levels its sampling asks for.
+    pub fn new(
+        ctx: &web_sys::WebGl2RenderingContext,
+        tt: &model::Img,
+        sampling: model::mipmap::Sampling,
+    ) -> Self {
+        let levels = match sampling.mipmaps {
+            Some(filter) => model::mipmap::mipmaps(tt, filter, usize::MAX),
+            None => vec![],
+        };
+        Self::upload(ctx, tt, &levels, sampling)
+    }
+
+    ///Upload an atlas. Its mip levels stop once the padding between textures runs out.
+    pub fn from_atlas(
+        ctx: &web_sys::WebGl2RenderingContext,
+        atlas: &model::atlas::Atlas,
+        textures: &[model::Img],
+        sampling: model::mipmap::Sampling,
+    ) -> Self {
+        let levels = match sampling.mipmaps {
+            Some(filter) => atlas.mipmaps(textures, filter),
+            None => vec![],
+        };
+        Self::upload(ctx, &atlas.image, &levels, sampling)
+    }
 
+    fn upload(
+        ctx: &web_sys::WebGl2RenderingContext,
+        tt: &model::Img,
+        levels: &[model::Img],
+        sampling: model::mipmap::Sampling,
+    ) -> Self {
+        use model::mipmap::Filter;
+        use WebGl2RenderingContext as GL;
+
+        let mut texture = simple2d::TextureBuffer::new(&ctx);
         texture.update(tt.width as usize, tt.height as usize, &tt.data);
+
+        ctx.bind_texture(GL::TEXTURE_2D, Some(texture.texture()));
+        for (k, level) in (1..).zip(levels.iter()) {
+            ctx.tex_image_2d_with_i32_and_i32_and_i32_and_format_and_type_and_opt_u8_array(
+                GL::TEXTURE_2D,
+                k,
+                GL::RGBA as i32,
+                level.width as i32,
+                level.height as i32,
+                0,
+                GL::RGBA,
+                GL::UNSIGNED_BYTE,
+                Some(&level.data),
+            )
+            .unwrap_throw();
+        }
+
+        let linear = sampling.filter == Filter::Linear;
+        let mag = if linear { GL::LINEAR } else { GL::NEAREST };
+        let min = match (linear, levels.is_empty()) {
+            (true, true) => GL::LINEAR,
+            (false, true) => GL::NEAREST,
+            (true, false) => GL::LINEAR_MIPMAP_LINEAR,
+            //Sharp up close but still blends between levels when far away.
+            (false, false) => GL::NEAREST_MIPMAP_LINEAR,
+        };
+        ctx.tex_parameteri(GL::TEXTURE_2D, GL::TEXTURE_MAG_FILTER, mag as i32);
+        ctx.tex_parameteri(GL::TEXTURE_2D, GL::TEXTURE_MIN_FILTER, min as i32);
+        ctx.tex_parameteri(GL::TEXTURE_2D, GL::TEXTURE_MAX_LEVEL, levels.len() as i32);
+
         TextureGpu { texture }
     }
 }
@@ -19,21 +83,12 @@ pub struct Foo<A, B> {
     pub model: B,
 }
 impl<A: Borrow<TextureGpu>, B: Borrow<ModelGpu>> Foo<A, B> {
+    ///Which pass the model wants to be drawn in, from the alpha mode of its material.
+    pub fn pass(&self) -> model::Pass {
+        self.model.borrow().pass
+    }
     pub fn draw(&self, view: &mut simple2d::View) {
-        let model = self.model.borrow();
-        let tex = self.texture.borrow();
-        view.draw(
-            WebGl2RenderingContext::TRIANGLES,
-            &tex.texture,
-            &model.tex_coord,
-            &model.position,
-            model.index.as_ref(),
-            &model.normals,
-            false,
-            false,
-            false,
-            true,
-        );
+        self.draw_ext(view, false, false, false, true);
     }
     pub fn draw_ext(
         &self,
@@ -43,53 +98,409 @@ impl<A: Borrow<TextureGpu>, B: Borrow<ModelGpu>> Foo<A, B> {
         linear: bool,
         lighting: bool,
     ) {
-        let model = self.model.borrow();
         let tex = self.texture.borrow();
-        view.draw(
-            WebGl2RenderingContext::TRIANGLES,
-            &tex.texture,
-            &model.tex_coord,
-            &model.position,
-            model.index.as_ref(),
-            &model.normals,
-            grayscale,
-            text,
-            false,
-            lighting,
-        );
+        for part in self.model.borrow().parts.iter() {
+            view.draw(
+                WebGl2RenderingContext::TRIANGLES,
+                &tex.texture,
+                &part.tex_coord,
+                &part.position,
+                Some(&part.index),
+                &part.normals,
+                grayscale,
+                text,
+                false,
+                lighting,
+            );
+        }
     }
 }
 
-pub struct ModelGpu {
-    index: Option<simple2d::IndexBuffer>,
+struct ModelPart {
+    index: simple2d::IndexBuffer,
     tex_coord: simple2d::TextureCoordBuffer,
     position: simple2d::DynamicBuffer,
     normals: simple2d::DynamicBuffer,
 }
+
+pub struct ModelGpu {
+    parts: Vec<ModelPart>,
+    pass: model::Pass,
+}
 impl ModelGpu {
     pub fn new(ctx: &web_sys::WebGl2RenderingContext, data: &model::ModelData) -> Self {
-        let index = if let Some(indices) = &data.indices {
-            let mut index = simple2d::IndexBuffer::new(&ctx).unwrap_throw();
-            index.update(&indices);
-            Some(index)
-        } else {
-            None
-        };
+        //Shogo only takes 16 bit indices and draws a whole buffer at a time, so every
+        //range gets buffers of its own.
+        let parts = data
+            .chunks()
+            .iter()
+            .map(|chunk| {
+                let mut index = simple2d::IndexBuffer::new(&ctx).unwrap_throw();
+                index.update(&chunk.indices);
 
-        let mut tex_coord = simple2d::TextureCoordBuffer::new(&ctx).unwrap_throw();
-        tex_coord.update(&data.tex_coords);
+                let mut tex_coord = simple2d::TextureCoordBuffer::new(&ctx).unwrap_throw();
+                tex_coord.update(&chunk.tex_coords);
 
-        let mut position = simple2d::DynamicBuffer::new(&ctx).unwrap_throw();
-        position.update_no_clear(&data.positions);
+                let mut position = simple2d::DynamicBuffer::new(&ctx).unwrap_throw();
+                position.update_no_clear(&chunk.positions);
 
-        let mut normals = simple2d::DynamicBuffer::new(&ctx).unwrap_throw();
-        normals.update_no_clear(&data.normals);
+                let mut normals = simple2d::DynamicBuffer::new(&ctx).unwrap_throw();
+                normals.update_no_clear(&chunk.normals);
+
+                ModelPart {
+                    index,
+                    tex_coord,
+                    position,
+                    normals,
+                }
+            })
+            .collect();
 
         ModelGpu {
-            index,
-            tex_coord,
-            position,
-            normals,
+            parts,
+            pass: data.pass(),
         }
     }
+
+    ///Upload a baked model straight from the file.
+    pub fn from_baked(ctx: &web_sys::WebGl2RenderingContext, data: &model::baked::Baked) -> Self {
+        Self::new(ctx, &data.to_model())
+    }
+
+    ///Upload a baked model whose texture was packed into an atlas.
+    pub fn from_baked_atlas(
+        ctx: &web_sys::WebGl2RenderingContext,
+        data: &model::baked::Baked,
+        atlas: &model::atlas::Atlas,
+        texture: usize,
+    ) -> Self {
+        let mut model = data.to_model();
+        model.tex_coords = atlas.remap(texture, data.tex_coords);
+        Self::new(ctx, &model)
+    }
+}
+
+const INSTANCE_VERTEX: &str = r#"#version 300 es
+layout(location = 0) in vec3 position;
+layout(location = 1) in vec2 tex_coord;
+layout(location = 2) in vec3 normal;
+layout(location = 3) in mat4 model;
+layout(location = 7) in float flags;
+layout(location = 8) in float alpha;
+
+uniform mat4 view_proj;
+
+out vec2 v_tex_coord;
+out float v_light;
+out float v_alpha;
+flat out int v_flags;
+
+void main() {
+    int f = int(flags + 0.5);
+    v_flags = f;
+    v_tex_coord = tex_coord;
+    v_alpha = alpha;
+
+    //Light comes from above and a little to the side.
+    vec3 n = normalize(mat3(model) * normal);
+    float diffuse = max(dot(n, normalize(vec3(0.3, -0.3, 1.0))), 0.0);
+    v_light = (f & 2) != 0 ? 0.6 + 0.4 * diffuse : 1.0;
+
+    gl_Position = view_proj * model * vec4(position, 1.0);
+}
+"#;
+
+const INSTANCE_FRAGMENT: &str = r#"#version 300 es
+precision mediump float;
+
+uniform sampler2D tex;
+uniform float cutoff;
+
+in vec2 v_tex_coord;
+in float v_light;
+in float v_alpha;
+flat in int v_flags;
+
+out vec4 out_colour;
+
+void main() {
+    vec4 c = texture(tex, v_tex_coord);
+    //Nothing is below a cutoff of 0, which is what models that aren't masked get.
+    if (c.a < cutoff) {
+        discard;
+    }
+    if ((v_flags & 1) != 0) {
+        c.rgb = vec3(dot(c.rgb, vec3(0.299, 0.587, 0.114)));
+    }
+    //Premultiplied so only the colour is lit, but fading takes all of it.
+    out_colour = vec4(c.rgb * v_light, c.a) * v_alpha;
+}
+"#;
+
+fn compile_shader(
+    ctx: &WebGl2RenderingContext,
+    kind: u32,
+    source: &str,
+) -> Result<web_sys::WebGlShader, String> {
+    let shader = ctx.create_shader(kind).ok_or("could not create a shader")?;
+    ctx.shader_source(&shader, source);
+    ctx.compile_shader(&shader);
+    if ctx
+        .get_shader_parameter(&shader, WebGl2RenderingContext::COMPILE_STATUS)
+        .as_bool()
+        .unwrap_or(false)
+    {
+        Ok(shader)
+    } else {
+        Err(ctx.get_shader_info_log(&shader).unwrap_or_default())
+    }
+}
+
+///A shader that draws every instance of a model in one call.
+pub struct InstanceShader {
+    program: web_sys::WebGlProgram,
+    view_proj: web_sys::WebGlUniformLocation,
+    tex: web_sys::WebGlUniformLocation,
+    cutoff: web_sys::WebGlUniformLocation,
+}
+impl InstanceShader {
+    pub fn new(ctx: &WebGl2RenderingContext) -> Result<Self, String> {
+        let vertex = compile_shader(ctx, WebGl2RenderingContext::VERTEX_SHADER, INSTANCE_VERTEX)?;
+        let fragment = compile_shader(
+            ctx,
+            WebGl2RenderingContext::FRAGMENT_SHADER,
+            INSTANCE_FRAGMENT,
+        )?;
+
+        let program = ctx.create_program().ok_or("could not create a program")?;
+        ctx.attach_shader(&program, &vertex);
+        ctx.attach_shader(&program, &fragment);
+        ctx.link_program(&program);
+        if !ctx
+            .get_program_parameter(&program, WebGl2RenderingContext::LINK_STATUS)
+            .as_bool()
+            .unwrap_or(false)
+        {
+            return Err(ctx.get_program_info_log(&program).unwrap_or_default());
+        }
+
+        let uniform = |name: &str| {
+            ctx.get_uniform_location(&program, name)
+                .ok_or(format!("no uniform {}", name))
+        };
+        Ok(InstanceShader {
+            view_proj: uniform("view_proj")?,
+            tex: uniform("tex")?,
+            cutoff: uniform("cutoff")?,
+            program,
+        })
+    }
+
+    ///Draw a copy of the model for every instance. `view_proj` is applied after the
+    ///matrix of each instance.
+    pub fn draw(
+        &self,
+        ctx: &WebGl2RenderingContext,
+        view_proj: &cgmath::Matrix4<f32>,
+        model: &InstancedModel,
+        instances: &model::instance::Instances,
+    ) {
+        use WebGl2RenderingContext as GL;
+        if instances.is_empty() {
+            return;
+        }
+
+        ctx.use_program(Some(&self.program));
+        let m: &[f32; 16] = view_proj.as_ref();
+        ctx.uniform_matrix4fv_with_f32_array(Some(&self.view_proj), false, m);
+        ctx.active_texture(GL::TEXTURE0);
+        ctx.bind_texture(GL::TEXTURE_2D, Some(model.texture.texture.texture()));
+        ctx.uniform1i(Some(&self.tex), 0);
+
+        ctx.bind_vertex_array(Some(&model.vao));
+        ctx.bind_buffer(GL::ARRAY_BUFFER, Some(&model.instances));
+        ctx.buffer_data_with_u8_array(GL::ARRAY_BUFFER, instances.as_bytes(), GL::DYNAMIC_DRAW);
+
+        let n = instances.len() as i32;
+        for &(start, count, cutoff) in model.ranges.iter() {
+            ctx.uniform1f(Some(&self.cutoff), cutoff);
+            match model.index_type {
+                Some((kind, size)) => ctx.draw_elements_instanced_with_i32(
+                    GL::TRIANGLES,
+                    count,
+                    kind,
+                    start * size,
+                    n,
+                ),
+                None => ctx.draw_arrays_instanced(GL::TRIANGLES, start, count, n),
+            }
+        }
+
+        //Leave the default vertex array to the other shaders.
+        ctx.bind_vertex_array(None);
+    }
+}
+
+///A model set up to be drawn with [`InstanceShader`]. Its vertices live in a vertex
+///array of their own so the per instance attributes don't leak into other draws.
+pub struct InstancedModel {
+    texture: std::rc::Rc<TextureGpu>,
+    vao: web_sys::WebGlVertexArrayObject,
+    instances: web_sys::WebGlBuffer,
+    //The type of the indices and how many bytes each one takes.
+    index_type: Option<(u32, i32)>,
+    //Start, count and alpha cutoff of every range, drawn one at a time.
+    ranges: Vec<(i32, i32, f32)>,
+    pass: model::Pass,
+}
+impl InstancedModel {
+    ///Upload a baked model whose texture was packed into an atlas.
+    pub fn from_baked_atlas(
+        ctx: &WebGl2RenderingContext,
+        data: &model::baked::Baked,
+        atlas: &model::atlas::Atlas,
+        texture: usize,
+        atlas_texture: std::rc::Rc<TextureGpu>,
+    ) -> Result<Self, String> {
+        let tex_coords = atlas.remap(texture, data.tex_coords);
+        Self::upload(
+            ctx,
+            data.positions,
+            data.normals,
+            &tex_coords,
+            data.indices,
+            &data.ranges,
+            atlas_texture,
+        )
+    }
+
+    ///Upload a model made at runtime, like laid out text.
+    pub fn new(
+        ctx: &WebGl2RenderingContext,
+        data: &model::ModelData,
+        texture: std::rc::Rc<TextureGpu>,
+    ) -> Result<Self, String> {
+        use model::baked::BakedIndices;
+        let indices = match &data.indices {
+            Some(model::Indices::U16(a)) => BakedIndices::U16(a),
+            Some(model::Indices::U32(a)) => BakedIndices::U32(a),
+            None => BakedIndices::None,
+        };
+        Self::upload(
+            ctx,
+            &data.positions,
+            &data.normals,
+            &data.tex_coords,
+            indices,
+            &data.ranges,
+            texture,
+        )
+    }
+
+    pub fn pass(&self) -> model::Pass {
+        self.pass
+    }
+
+    fn upload(
+        ctx: &WebGl2RenderingContext,
+        positions: &[[f32; 3]],
+        normals: &[[f32; 3]],
+        tex_coords: &[[f32; 2]],
+        indices: model::baked::BakedIndices,
+        ranges: &[model::DrawRange],
+        texture: std::rc::Rc<TextureGpu>,
+    ) -> Result<Self, String> {
+        use model::instance::{ALPHA_OFFSET, FLAGS_OFFSET, STRIDE};
+        use WebGl2RenderingContext as GL;
+
+        let buffer = |target: u32, data: &[u8]| {
+            let b = ctx.create_buffer().ok_or("could not create a buffer")?;
+            ctx.bind_buffer(target, Some(&b));
+            ctx.buffer_data_with_u8_array(target, data, GL::STATIC_DRAW);
+            Ok::<_, String>(b)
+        };
+
+        let vao = ctx
+            .create_vertex_array()
+            .ok_or("could not create a vertex array")?;
+        ctx.bind_vertex_array(Some(&vao));
+
+        let vertices: [(u32, i32, &[u8]); 3] = [
+            (0, 3, bytemuck::cast_slice(positions)),
+            (1, 2, bytemuck::cast_slice(tex_coords)),
+            (2, 3, bytemuck::cast_slice(normals)),
+        ];
+        for (location, size, data) in vertices {
+            buffer(GL::ARRAY_BUFFER, data)?;
+            ctx.enable_vertex_attrib_array(location);
+            ctx.vertex_attrib_pointer_with_i32(location, size, GL::FLOAT, false, 0, 0);
+        }
+
+        let index_type = match indices {
+            model::baked::BakedIndices::U16(a) => {
+                buffer(GL::ELEMENT_ARRAY_BUFFER, bytemuck::cast_slice(a))?;
+                Some((GL::UNSIGNED_SHORT, 2))
+            }
+            //WebGL 2 takes 32 bit indices so big meshes keep theirs.
+            model::baked::BakedIndices::U32(a) => {
+                buffer(GL::ELEMENT_ARRAY_BUFFER, bytemuck::cast_slice(a))?;
+                Some((GL::UNSIGNED_INT, 4))
+            }
+            model::baked::BakedIndices::None => None,
+        };
+
+        //The matrix takes four locations, one per column.
+        let instances = buffer(GL::ARRAY_BUFFER, &[])?;
+        for column in 0..4 {
+            let location = 3 + column;
+            ctx.enable_vertex_attrib_array(location);
+            ctx.vertex_attrib_pointer_with_i32(
+                location,
+                4,
+                GL::FLOAT,
+                false,
+                STRIDE as i32,
+                column as i32 * 16,
+            );
+            ctx.vertex_attrib_divisor(location, 1);
+        }
+        ctx.enable_vertex_attrib_array(7);
+        ctx.vertex_attrib_pointer_with_i32(
+            7,
+            1,
+            GL::FLOAT,
+            false,
+            STRIDE as i32,
+            FLAGS_OFFSET as i32,
+        );
+        ctx.vertex_attrib_divisor(7, 1);
+
+        ctx.enable_vertex_attrib_array(8);
+        ctx.vertex_attrib_pointer_with_i32(
+            8,
+            1,
+            GL::FLOAT,
+            false,
+            STRIDE as i32,
+            ALPHA_OFFSET as i32,
+        );
+        ctx.vertex_attrib_divisor(8, 1);
+
+        ctx.bind_vertex_array(None);
+
+        Ok(InstancedModel {
+            texture,
+            vao,
+            instances,
+            index_type,
+            ranges: ranges
+                .iter()
+                .map(|r| {
+                    let cutoff = model::mask_cutoff(std::slice::from_ref(r));
+                    (r.start as i32, r.count as i32, cutoff)
+                })
+                .collect(),
+            pass: model::ranges_pass(ranges),
+        })
+    }
 }
@@ -38,6 +38,13 @@ impl Moves {
             UpRight => [1, 1],
         })
     }
+    pub fn cost(&self) -> MoveUnit {
+        use Moves::*;
+        match self {
+            UpLeft | DownLeft | UpRight | DownRight => MoveUnit(3),
+            _ => MoveUnit(2),
+        }
+    }
 }
 #[derive(Copy, Clone, Debug)]
 pub struct Path {
@@ -81,15 +88,11 @@ impl Path {
         MoveUnit(total)
     }
     fn move_cost(&self, m: Moves) -> MoveUnit {
-        use Moves::*;
-        match m {
-            UpLeft | DownLeft | UpRight | DownRight => MoveUnit(3),
-            _ => MoveUnit(2),
-        }
+        m.cost()
     }
 }
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
 pub struct GridCoord(pub [i16; 2]);
 impl GridCoord {
     fn advance(self, m: Moves) -> GridCoord {
@@ -102,7 +105,7 @@ impl GridCoord {
     }
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Serialize, Deserialize, Debug, Copy, Clone)]
 pub struct MoveUnit(pub i8);
 impl MoveUnit {
     pub fn add(self, a: MoveUnit) -> Self {
@@ -144,6 +147,8 @@ pub struct PossibleMoves {
     //TODO start with the remainder when determining attack squares
     moves: Vec<(GridCoord, Path, MoveUnit)>,
     start: GridCoord,
+    //What the moves were explored with.
+    budget: MoveUnit,
 }
 
 impl PossibleMoves {
@@ -159,6 +164,7 @@ impl PossibleMoves {
         let mut p = PossibleMoves {
             moves: vec![],
             start: coord,
+            budget: remaining_moves,
         };
         p.explore_path(movement, filter, mo, Path::new(), remaining_moves);
         p
@@ -168,6 +174,13 @@ impl PossibleMoves {
         self.moves.iter().find(|a| &a.0 == g).map(|a| (&a.1, &a.2))
     }
 
+    ///What it costs to get to a cell, using the same move costs the moves were explored
+    ///with. Terrain and status effects are included unlike [`Path::total_cost`].
+    pub fn cost_to(&self, g: &GridCoord) -> Option<MoveUnit> {
+        let (_, remaining) = self.get_path_data(g)?;
+        Some(self.budget.sub(*remaining))
+    }
+
     pub fn start(&self) -> &GridCoord {
         &self.start
     }
@@ -176,6 +189,11 @@ impl PossibleMoves {
         self.moves.iter().map(|a| &a.0)
     }
 
+    //Only keep the moves that end on a coord that passes the filter.
+    pub fn retain<F: Filter>(&mut self, filter: &F) {
+        self.moves.retain(|a| filter.filter(&a.0));
+    }
+
     fn explore_path<K: MoveStrategy, F: Filter, M: MoveCost>(
         &mut self,
         movement: &K,
@@ -285,3 +303,91 @@ impl PossibleMoves {
 // fn terrain_cost(a: GridCoord) -> MoveUnit {
 //     MoveUnit(2)
 // }
+
+///Find the cheapest route to a cell that may be many turns away.
+///Unlike PossibleMoves this isn't limited by stamina or path length.
+///The filter must keep the search bounded, for example by including the grid filter.
+///Returns every cell along the way, not including the start.
+pub fn find_route<K: MoveStrategy, F: Filter, M: MoveCost>(
+    _movement: &K,
+    filter: &F,
+    mo: &M,
+    start: GridCoord,
+    goal: GridCoord,
+) -> Option<Vec<GridCoord>> {
+    use std::cmp::Reverse;
+    use std::collections::{BinaryHeap, HashMap};
+
+    let key = |a: GridCoord| (a.0[0], a.0[1]);
+
+    let mut best: HashMap<(i16, i16), (i32, GridCoord)> = HashMap::new();
+    let mut heap = BinaryHeap::new();
+    best.insert(key(start), (0, start));
+    heap.push(Reverse((0, key(start))));
+
+    while let Some(Reverse((cost, (x, y)))) = heap.pop() {
+        let curr = GridCoord([x, y]);
+        if curr == goal {
+            let mut route = vec![];
+            let mut c = goal;
+            while c != start {
+                route.push(c);
+                c = best[&key(c)].1;
+            }
+            route.reverse();
+            return Some(route);
+        }
+        if cost > best[&key(curr)].0 {
+            continue;
+        }
+
+        for a in K::adjacent() {
+            let next = curr.advance(a);
+            if !filter.filter(&next) {
+                continue;
+            }
+            let step = mo.foop(next, a.cost()).0.max(1) as i32;
+            let c = cost + step;
+            if best.get(&key(next)).map(|b| c < b.0).unwrap_or(true) {
+                best.insert(key(next), (c, curr));
+                heap.push(Reverse((c, key(next))));
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    ///Lets moves go anywhere, for testing.
+    pub(crate) struct Anywhere;
+    impl Filter for Anywhere {
+        fn filter(&self, _: &GridCoord) -> bool {
+            true
+        }
+    }
+
+    #[test]
+    fn roads_cost_less_stamina() {
+        let roads = terrain::roads(vec![GridCoord([1, 0])]);
+        let moves = PossibleMoves::new(
+            &WarriorMovement,
+            &Anywhere,
+            &terrain::Grass.chain(roads.foo()),
+            GridCoord([0, 0]),
+            MoveUnit(4),
+        );
+
+        //Half price onto the road, then full price onto the grass after it.
+        assert_eq!(moves.cost_to(&GridCoord([1, 0])).unwrap().0, 1);
+        assert_eq!(moves.cost_to(&GridCoord([2, 0])).unwrap().0, 3);
+        assert_eq!(moves.cost_to(&GridCoord([-1, 0])).unwrap().0, 2);
+
+        //The path alone doesn't know about the road.
+        let (path, _) = moves.get_path_data(&GridCoord([2, 0])).unwrap();
+        assert_eq!(path.total_cost().0, 4);
+        assert!(moves.cost_to(&GridCoord([9, 9])).is_none());
+    }
+}
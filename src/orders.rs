@@ -0,0 +1,271 @@
+use super::*;
+use crate::state::{GameHandle, PlayerCellAskRes, Stuff};
+
+///What a unit does on its own at the start of its tribe's turn.
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+pub enum StandingOrder {
+    ///Stay put and attack anything in range.
+    Hold,
+    ///Chase enemies that come close to the center but don't leave the area.
+    Guard { center: GridCoord, radius: i16 },
+    ///Walk back and forth between two cells.
+    Patrol {
+        a: GridCoord,
+        b: GridCoord,
+        to_b: bool,
+    },
+    ///Go after the closest enemy.
+    AttackNearest,
+    ///Stay next to another unit. Units don't have ids, so this is the last cell the leader was seen on.
+    Follow { leader: GridCoord },
+}
+
+///The order buttons. Patrol and Follow need a cell to be picked afterwards.
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+pub enum OrderKind {
+    Hold,
+    Guard,
+    Patrol,
+    AttackNearest,
+    Follow,
+    Clear,
+}
+
+const GUARD_RADIUS: i16 = 3;
+//How far a leader can get in one turn before a follower loses track of it.
+const FOLLOW_SEARCH: i16 = 5;
+//How far away a patrol point can be picked, in stamina units.
+const PATROL_RANGE: MoveUnit = MoveUnit(24);
+
+impl OrderKind {
+    pub const ALL: [OrderKind; 6] = [
+        OrderKind::Hold,
+        OrderKind::Guard,
+        OrderKind::Patrol,
+        OrderKind::AttackNearest,
+        OrderKind::Follow,
+        OrderKind::Clear,
+    ];
+
+    pub fn button_id(&self) -> &'static str {
+        match self {
+            OrderKind::Hold => "order_hold",
+            OrderKind::Guard => "order_guard",
+            OrderKind::Patrol => "order_patrol",
+            OrderKind::AttackNearest => "order_attack",
+            OrderKind::Follow => "order_follow",
+            OrderKind::Clear => "order_clear",
+        }
+    }
+
+    ///Whether a second cell has to be picked before the order can be given.
+    pub fn needs_target(&self) -> bool {
+        matches!(self, OrderKind::Patrol | OrderKind::Follow)
+    }
+
+    ///Create the order for a unit at the given position.
+    pub fn create(&self, pos: GridCoord, target: Option<GridCoord>) -> Option<StandingOrder> {
+        match self {
+            OrderKind::Hold => Some(StandingOrder::Hold),
+            OrderKind::Guard => Some(StandingOrder::Guard {
+                center: pos,
+                radius: GUARD_RADIUS,
+            }),
+            OrderKind::Patrol => Some(StandingOrder::Patrol {
+                a: pos,
+                b: target?,
+                to_b: true,
+            }),
+            OrderKind::AttackNearest => Some(StandingOrder::AttackNearest),
+            OrderKind::Follow => Some(StandingOrder::Follow { leader: target? }),
+            OrderKind::Clear => None,
+        }
+    }
+
+    ///The cells that can be picked for orders that need a target.
+    pub fn targets(&self, unit: &WarriorPointer<&Warrior>, game: &Stuff) -> movement::PossibleMoves {
+        let mut cells = movement::PossibleMoves::new(
+            &movement::WarriorMovement,
+            &game
+                .grid_matrix
+                .filter()
+                .chain(SingleFilter { a: unit.get_pos() }),
+            &terrain::Grass,
+            unit.position,
+            PATROL_RANGE,
+        );
+        match self {
+            OrderKind::Follow => cells.retain(&FriendFilter {
+                this_team: game.this_team,
+            }),
+            _ => cells.retain(&game.this_team.filter().chain(game.that_team.filter())),
+        }
+        cells
+    }
+}
+
+struct FriendFilter<'a> {
+    this_team: &'a Tribe,
+}
+impl<'a> movement::Filter for FriendFilter<'a> {
+    fn filter(&self, a: &GridCoord) -> bool {
+        self.this_team.find_slow(a).is_some()
+    }
+}
+
+///Update an order based on where things are now. Patrols turn around and followers find their leader.
+pub fn refresh(order: StandingOrder, pos: GridCoord, game: &Stuff) -> Option<StandingOrder> {
+    match order {
+        StandingOrder::Patrol { a, b, to_b } => {
+            let target = if to_b { b } else { a };
+            let to_b = if pos == target { !to_b } else { to_b };
+            Some(StandingOrder::Patrol { a, b, to_b })
+        }
+        StandingOrder::Follow { leader } => {
+            let friends: Vec<_> = game
+                .this_team
+                .iter()
+                .map(|a| a.position)
+                .filter(|a| *a != pos && ai::distance(a, &leader) <= FOLLOW_SEARCH)
+                .collect();
+            //Leader is gone.
+            let leader = ai::closest(&leader, friends.iter())?;
+            Some(StandingOrder::Follow { leader })
+        }
+        a => Some(a),
+    }
+}
+
+fn attack_in_range(
+    unit: &WarriorPointer<&Warrior>,
+    att: &movement::PossibleMoves,
+    game: &Stuff,
+) -> Option<PlayerCellAskRes> {
+    if unit.attacked {
+        return None;
+    }
+    att.iter_coords()
+        .find_map(|a| game.that_team.find_slow(a))
+        .map(|a| PlayerCellAskRes::Attack(a.slim()))
+}
+
+fn move_toward(
+    unit: &WarriorPointer<&Warrior>,
+    goal: GridCoord,
+    ss: &movement::PossibleMoves,
+    allowed: impl Fn(&GridCoord) -> bool,
+) -> Option<PlayerCellAskRes> {
+    let cells: Vec<_> = ss.iter_coords().filter(|a| allowed(a)).copied().collect();
+    let best = ai::closest(&goal, cells.iter())?;
+
+    //Only move if it gets us closer.
+    if ai::distance(&best, &goal) < ai::distance(&unit.position, &goal) {
+        Some(PlayerCellAskRes::MoveTo(best))
+    } else {
+        None
+    }
+}
+
+///Work out the next thing a unit does to carry out its standing order.
+pub fn decide(
+    unit: &WarriorPointer<&Warrior>,
+    order: StandingOrder,
+    cc: &CellSelection,
+    game: &Stuff,
+) -> Option<PlayerCellAskRes> {
+    let CellSelection::MoveSelection(ss, att) = cc else {
+        unreachable!()
+    };
+
+    if let Some(a) = attack_in_range(unit, att, game) {
+        return Some(a);
+    }
+
+    match order {
+        StandingOrder::Hold => None,
+        StandingOrder::Guard { center, radius } => {
+            let intruders: Vec<_> = game
+                .that_team
+                .iter()
+                .map(|a| a.position)
+                .filter(|a| ai::distance(a, &center) <= radius)
+                .collect();
+
+            let in_area = |a: &GridCoord| ai::distance(a, &center) <= radius;
+            if let Some(enemy) = ai::closest(&unit.position, intruders.iter()) {
+                move_toward(unit, enemy, ss, in_area)
+            } else if !in_area(&unit.position) {
+                move_toward(unit, center, ss, |_| true)
+            } else {
+                None
+            }
+        }
+        StandingOrder::Patrol { a, b, to_b } => {
+            let target = if to_b { b } else { a };
+            move_toward(unit, target, ss, |_| true)
+        }
+        StandingOrder::AttackNearest => {
+            let enemies: Vec<_> = game.that_team.iter().map(|a| a.position).collect();
+            let enemy = ai::closest(&unit.position, enemies.iter())?;
+            move_toward(unit, enemy, ss, |_| true)
+        }
+        StandingOrder::Follow { leader } => move_toward(unit, leader, ss, |_| true),
+    }
+}
+
+//Refresh the order of the unit and work out what it should do.
+fn next_action(
+    unit: WarriorPointer<GridCoord>,
+    stuff: &mut Stuff,
+) -> Option<(CellSelection, PlayerCellAskRes)> {
+    let w = stuff.this_team.find_slow(&unit.inner)?;
+    let order = refresh(w.standing?, w.position, stuff);
+    stuff.this_team.lookup_mut(&unit).standing = order;
+
+    let w = stuff.this_team.lookup(unit);
+    let cc = state::generate_unit_possible_moves(&w, stuff);
+    let res = decide(&w, order?, &cc, stuff)?;
+    Some((cc, res))
+}
+
+///Let every unit with a standing order carry it out, one after the other.
+pub fn handle_standing_orders() -> impl GameStepper<GameHandle, Result = ()> {
+    gameplay::next::<GameHandle>()
+        .map(move |_, stuff: &mut Stuff| {
+            let units: Vec<_> = stuff
+                .this_team
+                .iter()
+                .filter(|a| a.standing.is_some())
+                .map(|a| a.slim())
+                .collect();
+
+            gameplay::looper(units, move |mut units, stuff| {
+                let next = units.pop().and_then(|unit| {
+                    let (cc, res) = next_action(unit, stuff)?;
+                    Some(state::handle_one_execution(unit, cc, res, stuff))
+                });
+
+                GameStepper::map(next, move |res, _| {
+                    //Keep going until the unit has nothing left to do.
+                    if let Some(Some(pos)) = res {
+                        units.push(pos);
+                    }
+
+                    if units.is_empty() {
+                        gameplay::LooperRes::Finish(())
+                    } else {
+                        gameplay::LooperRes::Loop(units)
+                    }
+                })
+            })
+        })
+        .flatten()
+}
+
+///Used by the real-time mode, where units act on their own continuously.
+pub fn next_realtime_action(
+    unit: WarriorPointer<GridCoord>,
+    stuff: &mut Stuff,
+) -> Option<PlayerCellAskRes> {
+    next_action(unit, stuff).map(|(_, res)| res)
+}
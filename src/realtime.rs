@@ -0,0 +1,634 @@
+use super::*;
+use crate::state::{PlayerCellAskRes, Stuff};
+use std::collections::VecDeque;
+
+//Number of frames in between stamina regeneration ticks.
+const REGEN_FRAMES: usize = 60;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Order {
+    MoveTo(GridCoord),
+    Attack(GridCoord),
+}
+
+struct Queued {
+    team: usize,
+    unit: GridCoord,
+    orders: VecDeque<Order>,
+}
+
+enum Outcome {
+    Move,
+    Kill {
+        target: WarriorPointer<GridCoord>,
+        damage: i8,
+    },
+    Attack {
+        target: WarriorPointer<GridCoord>,
+        fc: combat::Forecast,
+        cost: MoveUnit,
+    },
+}
+
+//A unit that is in the middle of doing something.
+//It is taken out of its tribe until the animation finishes.
+struct Action {
+    team: usize,
+    from: GridCoord,
+    to: GridCoord,
+    anim: animation::Animation<WarriorPointer<Warrior>>,
+    outcome: Outcome,
+}
+impl Action {
+    fn target(&self) -> Option<GridCoord> {
+        match &self.outcome {
+            Outcome::Move => None,
+            Outcome::Kill { target, .. } | Outcome::Attack { target, .. } => Some(target.inner),
+        }
+    }
+}
+
+//Cells that units in flight will end up on.
+struct Reserved<'a>(&'a [GridCoord]);
+impl movement::Filter for Reserved<'_> {
+    fn filter(&self, a: &GridCoord) -> bool {
+        !self.0.contains(a)
+    }
+}
+
+///Real-time mode. Instead of taking turns, stamina regenerates over time and
+///every unit works through its queued orders on its own. Any number of units
+///can be animating at the same time.
+pub struct Realtime {
+    frames: usize,
+    queued: Vec<Queued>,
+    actions: Vec<Action>,
+    selected: Vec<GridCoord>,
+    //A standing order waiting for its target cell to be tapped.
+    pending: Option<orders::OrderKind>,
+    //The team the player controls. The other team is controlled by the computer.
+    player: usize,
+}
+
+impl Realtime {
+    ///The player gets the team the computer isn't playing. Without one, like in hotseat,
+    ///they keep the team that was about to move.
+    pub fn new(game: &Game) -> Self {
+        let player = match game.ai_team {
+            Some(ai) => 1 - ai,
+            None => game.team,
+        };
+        Realtime {
+            frames: 0,
+            queued: vec![],
+            actions: vec![],
+            selected: vec![],
+            pending: None,
+            player,
+        }
+    }
+
+    pub fn player(&self) -> usize {
+        self.player
+    }
+
+    pub fn selected(&self) -> impl Iterator<Item = &GridCoord> {
+        self.selected.iter()
+    }
+
+    ///The queued orders of the selected units.
+    pub fn selected_orders(&self) -> impl Iterator<Item = &Order> {
+        self.queued
+            .iter()
+            .filter(move |a| a.team == self.player && self.selected.contains(&a.unit))
+            .flat_map(|a| a.orders.iter())
+    }
+
+    ///Drop the queued orders of the selected units.
+    pub fn cancel_orders(&mut self) {
+        let sel = &self.selected;
+        let player = self.player;
+        self.queued
+            .retain(|q| !(q.team == player && sel.contains(&q.unit)));
+    }
+
+    ///Select every player unit inside an area in world coordinates.
+    pub fn select_area(&mut self, game: &Game, poly: &[[f32; 2]]) {
+        let tribe = if self.player == 0 {
+            &game.cats
+        } else {
+            &game.dogs
+        };
+        self.selected = group::units_in_area(tribe, &game.grid_matrix, poly);
+    }
+
+    fn queue(&mut self, unit: GridCoord, order: Order) {
+        if let Some(q) = self
+            .queued
+            .iter_mut()
+            .find(|a| a.team == self.player && a.unit == unit)
+        {
+            q.orders.push_back(order);
+        } else {
+            self.queued.push(Queued {
+                team: self.player,
+                unit,
+                orders: vec![order].into(),
+            });
+        }
+    }
+
+    ///Every unit that is currently animating along with its team.
+    pub fn animations(
+        &self,
+    ) -> impl Iterator<Item = (usize, &animation::Animation<WarriorPointer<Warrior>>)> {
+        self.actions.iter().map(|a| (a.team, &a.anim))
+    }
+
+    ///No unit is in the middle of an action, so every unit is in its tribe.
+    pub fn is_idle(&self) -> bool {
+        self.actions.is_empty()
+    }
+
+    ///Give the selected units a standing order.
+    pub fn give_order(&mut self, game: &mut Game, kind: orders::OrderKind) {
+        if kind.needs_target() {
+            self.pending = Some(kind);
+        } else {
+            self.set_standing(game, kind, None);
+        }
+    }
+
+    fn set_standing(&mut self, game: &mut Game, kind: orders::OrderKind, target: Option<GridCoord>) {
+        let [this_team, _] = state::team_view([&mut game.cats, &mut game.dogs], self.player);
+        for &s in self.selected.iter() {
+            if let Some(mut w) = this_team.find_slow_mut(&s) {
+                w.standing = kind.create(s, target);
+            }
+        }
+        //Standing orders replace anything that was queued.
+        self.cancel_orders();
+    }
+
+    ///Advance by one frame.
+    pub fn tick(&mut self, game: &mut Game, mouse: Option<[f32; 2]>) {
+        if let Some(mouse) = mouse {
+            self.handle_click(game, mouse);
+        }
+
+        self.frames += 1;
+        if self.frames >= REGEN_FRAMES {
+            self.frames = 0;
+            self.regen(game);
+        }
+
+        let mut i = 0;
+        while i < self.actions.len() {
+            if self.actions[i].anim.animate_step().is_some() {
+                i += 1;
+            } else {
+                let a = self.actions.swap_remove(i);
+                self.resolve(game, a);
+            }
+        }
+
+        for team in [0, 1] {
+            self.start_actions(game, team);
+        }
+    }
+
+    ///Instantly finish everything that is in flight. Used when going back to turn-based mode.
+    pub fn finish_all(&mut self, game: &mut Game) {
+        for a in std::mem::take(&mut self.actions) {
+            self.resolve(game, a);
+        }
+    }
+
+    fn handle_click(&mut self, game: &mut Game, mouse: [f32; 2]) {
+        let cell = GridCoord(game.grid_matrix.to_grid(mouse.into()).into());
+
+        if let Some(kind) = self.pending.take() {
+            self.set_standing(game, kind, Some(cell));
+            return;
+        }
+
+        let [this_team, that_team] =
+            state::team_view([&mut game.cats, &mut game.dogs], self.player);
+
+        if this_team.find_slow(&cell).is_some() {
+            //Tapping the selected unit again deselects it.
+            self.selected = if self.selected == [cell] {
+                vec![]
+            } else {
+                vec![cell]
+            };
+            return;
+        }
+
+        if that_team.find_slow(&cell).is_some() {
+            for unit in self.selected.clone() {
+                self.queue(unit, Order::Attack(cell));
+            }
+            return;
+        }
+
+        //Spread a group out in formation around the cell.
+        let units = self.selected.clone();
+        let free = |a: &GridCoord| {
+            game.grid_matrix.filter().filter(a)
+                && (units.contains(a) || this_team.find_slow(a).is_none())
+                && that_team.find_slow(a).is_none()
+        };
+        for (unit, dest) in group::formation(&units, cell, free) {
+            self.queue(unit, Order::MoveTo(dest));
+        }
+    }
+
+    fn regen(&mut self, game: &mut Game) {
+        for t in [&mut game.cats, &mut game.dogs] {
+            t.replenish_stamina();
+            t.reset_attacked();
+        }
+
+        //Effects can kill units, so only apply them when nobody is about to be attacked.
+        if self.is_idle() {
+            for t in [&mut game.cats, &mut game.dogs] {
+                t.apply_effects();
+                t.tick_effects();
+            }
+
+            let Game { cats, dogs, .. } = game;
+            self.queued.retain(|q| {
+                let tribe = if q.team == 0 { &*cats } else { &*dogs };
+                tribe.find_slow(&q.unit).is_some()
+            });
+            let own = if self.player == 0 { &*cats } else { &*dogs };
+            self.selected.retain(|s| own.find_slow(s).is_some());
+        }
+    }
+
+    fn start_actions(&mut self, game: &mut Game, team: usize) {
+        //Where units in flight will end up, and who they are attacking.
+        let reserved: Vec<_> = self.actions.iter().map(|a| a.to).collect();
+        let locked: Vec<_> = self.actions.iter().filter_map(|a| a.target()).collect();
+
+        let ai = team != self.player;
+        let mut started = vec![];
+
+        with_stuff(game, team, |stuff| {
+            let units: Vec<_> = stuff
+                .this_team
+                .iter()
+                .filter(|a| !locked.contains(&a.position))
+                .map(|a| a.slim())
+                .collect();
+
+            for unit in units {
+                let Some(w) = stuff.this_team.find_slow(&unit.inner) else {
+                    continue;
+                };
+
+                let mut cc = state::generate_unit_possible_moves(&w, stuff);
+                let CellSelection::MoveSelection(ss, att) = &mut cc else {
+                    unreachable!()
+                };
+                let taken: Vec<_> = reserved
+                    .iter()
+                    .chain(locked.iter())
+                    .chain(started.iter())
+                    .copied()
+                    .collect();
+                ss.retain(&Reserved(&taken));
+                att.retain(&Reserved(&taken));
+
+                let orders = self
+                    .queued
+                    .iter_mut()
+                    .find(|a| a.team == team && a.unit == unit.inner)
+                    .map(|a| &mut a.orders);
+
+                let res = match orders {
+                    Some(orders) => follow_order(&w, orders, ss, att, stuff),
+                    None if w.standing.is_some() => orders::next_realtime_action(unit, stuff)
+                        .filter(|res| match res {
+                            PlayerCellAskRes::MoveTo(a) => ss.get_path_data(a).is_some(),
+                            PlayerCellAskRes::Attack(a) => att.get_path_data(&a.inner).is_some(),
+                            _ => false,
+                        }),
+                    None => {
+                        let goal = if ai {
+                            let enemies: Vec<_> = stuff.that_team.iter().map(|a| a.position).collect();
+                            ai::closest(&unit.inner, enemies.iter())
+                        } else {
+                            //Idle player units only defend themselves.
+                            None
+                        };
+                        ai::decide(&w, goal, stuff)
+                            .map(|(_, res)| res)
+                            .filter(|res| match res {
+                                PlayerCellAskRes::MoveTo(a) => ss.get_path_data(a).is_some(),
+                                PlayerCellAskRes::Attack(a) => att.get_path_data(&a.inner).is_some(),
+                                _ => false,
+                            })
+                    }
+                };
+
+                let Some(res) = res else {
+                    continue;
+                };
+
+                if let Some(a) = begin(team, unit, &cc, res, stuff) {
+                    started.push(a.to);
+                    if let Some(t) = a.target() {
+                        started.push(t);
+                    }
+                    self.actions.push(a);
+                }
+            }
+        });
+    }
+
+    fn resolve(&mut self, game: &mut Game, a: Action) {
+        let Action {
+            team,
+            from,
+            to,
+            anim,
+            outcome,
+        } = a;
+        let mut warrior = anim.into_data();
+
+        let [this_team, that_team] = state::team_view([&mut game.cats, &mut game.dogs], team);
+        let popups = &mut game.popups;
+        let gg = &game.grid_matrix;
+
+        match outcome {
+            Outcome::Move => {
+                warrior.position = to;
+                this_team.add(warrior);
+            }
+            Outcome::Kill { target, damage } => {
+                combat_text::damage(popups, gg, target.inner, damage);
+                combat_text::ko(popups, gg, target.inner);
+                that_team.kill(target);
+                warrior.position = to;
+                warrior.gain_experience(experience::kill_xp(damage));
+                warrior.attacked = true;
+                this_team.add(warrior);
+            }
+            Outcome::Attack { target, fc, cost } => {
+                let mut target_cat = that_team.lookup_mut(&target);
+                target_cat.health -= fc.damage;
+                combat_text::damage(popups, gg, target.inner, fc.damage);
+                if fc.counter_damage > 0 {
+                    combat_text::damage(popups, gg, warrior.position, fc.counter_damage);
+                }
+                if fc.kills_self {
+                    combat_text::ko(popups, gg, warrior.position);
+                    target_cat.gain_experience(experience::kill_xp(fc.counter_damage));
+                    //Already taken out of the tribe.
+                    this_team.lost += 1;
+                } else {
+                    target_cat.gain_experience(experience::damage_xp(fc.counter_damage));
+                    warrior.gain_experience(experience::damage_xp(fc.damage));
+                    warrior.attacked = true;
+                    warrior.health -= fc.counter_damage;
+                    warrior.spend_stamina(cost);
+                    this_team.add(warrior);
+                }
+            }
+        }
+
+        //Orders and selection follow the unit.
+        if from != to {
+            for q in self.queued.iter_mut() {
+                if q.team == team && q.unit == from {
+                    q.unit = to;
+                }
+            }
+            if team == self.player {
+                for s in self.selected.iter_mut().filter(|s| **s == from) {
+                    *s = to;
+                }
+            }
+        }
+    }
+}
+
+//Work out what a unit with queued orders should do next.
+fn follow_order(
+    unit: &WarriorPointer<&Warrior>,
+    orders: &mut VecDeque<Order>,
+    ss: &movement::PossibleMoves,
+    att: &movement::PossibleMoves,
+    stuff: &Stuff,
+) -> Option<PlayerCellAskRes> {
+    loop {
+        let goal = match *orders.front()? {
+            Order::MoveTo(cell) => {
+                if unit.position == cell {
+                    orders.pop_front();
+                    continue;
+                }
+                cell
+            }
+            Order::Attack(cell) => {
+                let Some(enemy) = stuff.that_team.find_slow(&cell) else {
+                    //Target is gone.
+                    orders.pop_front();
+                    continue;
+                };
+                if att.get_path_data(&cell).is_some() {
+                    if unit.attacked {
+                        return None;
+                    }
+                    orders.pop_front();
+                    return Some(PlayerCellAskRes::Attack(enemy.slim()));
+                }
+                cell
+            }
+        };
+
+        let best = ai::closest(&goal, ss.iter_coords())?;
+        return if ai::distance(&best, &goal) < ai::distance(&unit.position, &goal) {
+            Some(PlayerCellAskRes::MoveTo(best))
+        } else {
+            None
+        };
+    }
+}
+
+//Take the unit out of its tribe and start animating it.
+fn begin(
+    team: usize,
+    unit: WarriorPointer<GridCoord>,
+    cc: &CellSelection,
+    res: PlayerCellAskRes,
+    stuff: &mut Stuff,
+) -> Option<Action> {
+    let CellSelection::MoveSelection(ss, att) = cc else {
+        unreachable!()
+    };
+
+    match res {
+        PlayerCellAskRes::MoveTo(target) => {
+            let (path, _) = ss.get_path_data(&target)?;
+            let cost = ss.cost_to(&target)?;
+            let mut w = stuff.this_team.lookup_take(unit);
+            w.spend_stamina(cost);
+            let anim = animation::Animation::new(w.position, path, stuff.grid_matrix, w);
+            Some(Action {
+                team,
+                from: unit.inner,
+                to: target,
+                anim,
+                outcome: Outcome::Move,
+            })
+        }
+        PlayerCellAskRes::Attack(target) => {
+            let (path, _) = att.get_path_data(&target.inner)?;
+            let fc = combat::forecast(
+                &stuff.this_team.lookup(unit),
+                &stuff.that_team.lookup(target),
+            );
+            let cost = att.cost_to(&target.inner)?;
+            let mut w = stuff.this_team.lookup_take(unit);
+
+            let (to, outcome) = if fc.kills_target {
+                w.spend_stamina(cost);
+                let damage = fc.damage;
+                (target.inner, Outcome::Kill { target, damage })
+            } else {
+                (unit.inner, Outcome::Attack { target, fc, cost })
+            };
+
+            let anim = animation::Animation::new(w.position, path, stuff.grid_matrix, w);
+            Some(Action {
+                team,
+                from: unit.inner,
+                to,
+                anim,
+                outcome,
+            })
+        }
+        _ => None,
+    }
+}
+
+///Look at the game from the side of a team without any player input.
+pub fn with_stuff<R>(game: &mut Game, team: usize, func: impl FnOnce(&mut Stuff) -> R) -> R {
+    let mut team = team;
+    let [this_team, that_team] = state::team_view([&mut game.cats, &mut game.dogs], team);
+
+    let mut stuff = Stuff {
+        team: &mut team,
+        this_team,
+        that_team,
+        grid_matrix: &game.grid_matrix,
+        roads: &mut game.roads,
+        economy: &game.economy,
+        survival: game.survival.as_mut(),
+        ai_team: game.ai_team,
+        mouse: None,
+        end_turn: false,
+        ability: None,
+        recruit: false,
+        upgrade: false,
+        order: None,
+        area: None,
+        cancel_route: false,
+        confirm_attack: false,
+        popups: &mut game.popups,
+    };
+    func(&mut stuff)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn player_gets_the_human_team() {
+        let mut game = survival::new_game().unwrap();
+        assert_eq!(Realtime::new(&game).player(), 1 - survival::AI_TEAM);
+
+        game.ai_team = Some(0);
+        assert_eq!(Realtime::new(&game).player(), 1);
+
+        //Hotseat keeps whoever was about to move.
+        game.ai_team = None;
+        game.team = 1;
+        assert_eq!(Realtime::new(&game).player(), 1);
+        game.team = 0;
+        assert_eq!(Realtime::new(&game).player(), 0);
+    }
+
+    fn tap(rt: &mut Realtime, game: &mut Game, a: [i16; 2]) {
+        let mouse = game.grid_matrix.to_world_center(a.into()).into();
+        rt.tick(game, Some(mouse));
+    }
+
+    fn ready(game: &mut Game, a: [i16; 2]) {
+        game.cats.find_slow_mut(&GridCoord(a)).unwrap().stamina = MoveUnit(6);
+    }
+
+    //Tick until every unit is done animating.
+    fn settle(rt: &mut Realtime, game: &mut Game) {
+        for _ in 0..REGEN_FRAMES {
+            if rt.is_idle() {
+                return;
+            }
+            rt.tick(game, None);
+        }
+        panic!("still animating");
+    }
+
+    #[test]
+    fn stamina_regens_every_regen_frames() {
+        let mut game = ability::tests::game(&[[2, 2]], &[[30, 30]]);
+        let mut rt = Realtime::new(&game);
+        let stamina = |game: &Game| game.cats.find_slow(&GridCoord([2, 2])).unwrap().stamina.0;
+
+        for _ in 0..REGEN_FRAMES - 1 {
+            rt.tick(&mut game, None);
+        }
+        assert_eq!(stamina(&game), 0);
+        rt.tick(&mut game, None);
+        assert_eq!(stamina(&game), 2);
+    }
+
+    #[test]
+    fn queued_order_runs_on_its_own() {
+        let mut game = ability::tests::game(&[[2, 2]], &[[30, 30]]);
+        ready(&mut game, [2, 2]);
+        let mut rt = Realtime::new(&game);
+
+        tap(&mut rt, &mut game, [2, 2]);
+        assert_eq!(rt.selected, [GridCoord([2, 2])]);
+        tap(&mut rt, &mut game, [4, 2]);
+        assert!(!rt.is_idle());
+
+        settle(&mut rt, &mut game);
+        assert!(game.cats.find_slow(&GridCoord([4, 2])).is_some());
+        assert_eq!(rt.selected, [GridCoord([4, 2])]);
+        assert_eq!(rt.selected_orders().count(), 0);
+    }
+
+    #[test]
+    fn units_animate_at_the_same_time() {
+        let mut game = ability::tests::game(&[[2, 2], [2, 5]], &[[30, 30]]);
+        ready(&mut game, [2, 2]);
+        ready(&mut game, [2, 5]);
+        let mut rt = Realtime::new(&game);
+        rt.queue(GridCoord([2, 2]), Order::MoveTo(GridCoord([4, 2])));
+        rt.queue(GridCoord([2, 5]), Order::MoveTo(GridCoord([4, 5])));
+
+        rt.tick(&mut game, None);
+        assert_eq!(rt.animations().count(), 2);
+
+        settle(&mut rt, &mut game);
+        for a in [[4, 2], [4, 5]] {
+            assert!(game.cats.find_slow(&GridCoord(a)).is_some());
+        }
+    }
+}
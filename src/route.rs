@@ -0,0 +1,86 @@
+use super::*;
+use crate::state::{GameHandle, PlayerCellAskRes, Stuff};
+
+///Plan a route to a cell that may take several turns to reach.
+///Enemies block the way. Friendly units don't since they will probably have moved.
+pub fn plan(unit: &WarriorPointer<&Warrior>, dest: GridCoord, game: &Stuff) -> Option<Vec<GridCoord>> {
+    if game.this_team.find_slow(&dest).is_some() || game.that_team.find_slow(&dest).is_some() {
+        return None;
+    }
+
+    movement::find_route(
+        &movement::WarriorMovement,
+        &game.grid_matrix.filter().chain(game.that_team.filter()),
+        &terrain::Grass.chain(game.roads.foo()),
+        unit.position,
+        dest,
+    )
+}
+
+///Work out how far along its route a unit can get this turn.
+///The cells it will pass are removed from the route.
+pub fn next_step(
+    unit: WarriorPointer<GridCoord>,
+    stuff: &mut Stuff,
+) -> Option<(CellSelection, PlayerCellAskRes)> {
+    let w = stuff.this_team.find_slow(&unit.inner)?;
+    let dest = *w.route.last()?;
+
+    let cc = state::generate_unit_possible_moves(&w, stuff);
+    let CellSelection::MoveSelection(ss, _) = &cc else {
+        unreachable!()
+    };
+
+    let mut route = w.route.clone();
+    let mut furthest = route
+        .iter()
+        .rposition(|a| movement::contains_coord(ss.iter_coords(), a));
+
+    //Something is in the way. Try to find another way around.
+    if furthest.is_none() {
+        if let Some(r) = plan(&w, dest, stuff) {
+            furthest = r
+                .iter()
+                .rposition(|a| movement::contains_coord(ss.iter_coords(), a));
+            route = r;
+        }
+    }
+
+    let Some(i) = furthest else {
+        stuff.this_team.lookup_mut(&unit).route = route;
+        return None;
+    };
+
+    let target = route[i];
+    stuff.this_team.lookup_mut(&unit).route = route.split_off(i + 1);
+    Some((cc, PlayerCellAskRes::MoveTo(target)))
+}
+
+///Move every unit that has a route as far along it as its stamina allows.
+pub fn handle_routes() -> impl GameStepper<GameHandle, Result = ()> {
+    gameplay::next::<GameHandle>()
+        .map(move |_, stuff: &mut Stuff| {
+            let units: Vec<_> = stuff
+                .this_team
+                .iter()
+                .filter(|a| !a.route.is_empty())
+                .map(|a| a.slim())
+                .collect();
+
+            gameplay::looper(units, move |mut units, stuff| {
+                let next = units.pop().and_then(|unit| {
+                    let (cc, res) = next_step(unit, stuff)?;
+                    Some(state::handle_one_execution(unit, cc, res, stuff))
+                });
+
+                GameStepper::map(next, move |_, _| {
+                    if units.is_empty() {
+                        gameplay::LooperRes::Finish(())
+                    } else {
+                        gameplay::LooperRes::Loop(units)
+                    }
+                })
+            })
+        })
+        .flatten()
+}
@@ -0,0 +1,331 @@
+use super::*;
+
+///Small deterministic random number generator (splitmix64) so a run can be replayed from its seed.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Rng(u64);
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Rng(seed)
+    }
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+    pub fn range(&mut self, a: std::ops::Range<i16>) -> i16 {
+        a.start + (self.next_u64() % (a.end - a.start) as u64) as i16
+    }
+}
+
+///What the player can pick from in between battles.
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Reward {
+    ///Bring every warrior back to full health.
+    HealAll,
+    ///Add a new warrior to the roster.
+    Recruit,
+    ///Start the next battle with extra resources.
+    Resources,
+}
+impl Reward {
+    pub const ALL: [Reward; 3] = [Reward::HealAll, Reward::Recruit, Reward::Resources];
+
+    pub fn button_id(&self) -> &'static str {
+        match self {
+            Reward::HealAll => "reward_heal",
+            Reward::Recruit => "reward_recruit",
+            Reward::Resources => "reward_resources",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RunSummary {
+    pub seed: u64,
+    pub battles_won: u32,
+    pub num_battles: u32,
+    pub kills: u32,
+    pub fallen: u32,
+    pub survivors: u32,
+    pub victory: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum RunStage {
+    Battle,
+    ChooseReward(Vec<Reward>),
+    Finished,
+}
+
+const NUM_BATTLES: u32 = 5;
+const ROSTER_SIZE: usize = 4;
+const REWARD_RESOURCES: i16 = 10;
+
+///A short series of battles. Warriors that survive a battle carry over to the next one.
+///Warriors that die are gone for the rest of the run.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Run {
+    pub seed: u64,
+    rng: Rng,
+    pub num_battles: u32,
+    //Index of the current battle.
+    pub battle: u32,
+    pub roster: Vec<Warrior>,
+    pub kills: u32,
+    pub fallen: u32,
+    bonus_resources: i16,
+    pub stage: RunStage,
+}
+
+impl Run {
+    pub fn new(seed: u64) -> Self {
+        let roster = (0..ROSTER_SIZE)
+            .map(|_| Warrior::new(GridCoord([0, 0])))
+            .collect();
+
+        Run {
+            seed,
+            rng: Rng::new(seed),
+            num_battles: NUM_BATTLES,
+            battle: 0,
+            roster,
+            kills: 0,
+            fallen: 0,
+            bonus_resources: 0,
+            stage: RunStage::Battle,
+        }
+    }
+
+    //Every battle gets its own generator so its layout doesn't depend on the rewards picked.
+    fn battle_rng(&self) -> Rng {
+        Rng::new(self.seed ^ (self.battle as u64 + 1).wrapping_mul(0x9E37_79B9_7F4A_7C15))
+    }
+
+    ///Create the game for the current battle.
+    pub fn start_battle(&mut self) -> Game {
+        let mut rng = self.battle_rng();
+
+        let players = self
+            .roster
+            .iter()
+            .enumerate()
+            .map(|(i, a)| {
+                let mut w = a.clone();
+                w.position = GridCoord([3 + (i % 8) as i16, 6 + (i / 8) as i16]);
+                w.stamina = MoveUnit(0);
+                w.attacked = false;
+                w.effects = status::StatusEffects::default();
+                w
+            })
+            .collect();
+
+        let num_enemies = 2 + self.battle as usize + rng.range(0..2) as usize;
+        let mut enemies: Vec<Warrior> = vec![];
+        while enemies.len() < num_enemies {
+            let cell = GridCoord([rng.range(1..11), rng.range(0..3)]);
+            if enemies.iter().any(|a| a.position == cell) {
+                continue;
+            }
+            let mut w = Warrior::new(cell);
+            w.health = (4 + self.battle as i8 + rng.range(0..3) as i8).min(Warrior::MAX_HEALTH);
+            enemies.push(w);
+        }
+
+        let resource_cells = (0..2)
+            .map(|_| GridCoord([rng.range(0..12), rng.range(3..6)]))
+            .collect();
+
+        let rules = economy::EconomyRules::default();
+        let resources = rules.starting_resources + self.bonus_resources;
+        self.bonus_resources = 0;
+
+        Game {
+            team: 0,
+            cats: Tribe::new(
+                vec![UnitCollection::new(players)],
+                GridCoord([4, 8]),
+                resources,
+            ),
+            dogs: Tribe::new(vec![UnitCollection::new(enemies)], GridCoord([4, 0]), 0),
+            grid_matrix: grids::GridMatrix::new(),
+            roads: terrain::roads(vec![]),
+            economy: economy::Economy::new(rules, resource_cells),
+            survival: None,
+            ai_team: Some(1),
+            confirm_attack: true,
+            popups: model::popup::Popups::new(),
+        }
+    }
+
+    ///Call once one side of the battle has been wiped out.
+    pub fn finish_battle(&mut self, game: &Game) {
+        self.roster = game.cats.iter().map(|a| a.inner.clone()).collect();
+        self.kills += game.dogs.lost as u32;
+        self.fallen += game.cats.lost as u32;
+
+        if self.roster.is_empty() {
+            self.stage = RunStage::Finished;
+            return;
+        }
+
+        self.battle += 1;
+        if self.battle >= self.num_battles {
+            self.stage = RunStage::Finished;
+            return;
+        }
+
+        //Offer two different rewards.
+        let mut choices = Reward::ALL.to_vec();
+        let skip = self.rng.range(0..choices.len() as i16) as usize;
+        choices.remove(skip);
+        self.stage = RunStage::ChooseReward(choices);
+    }
+
+    pub fn choose_reward(&mut self, a: Reward) {
+        match a {
+            Reward::HealAll => {
+                for w in self.roster.iter_mut() {
+                    w.health = Warrior::MAX_HEALTH;
+                }
+            }
+            Reward::Recruit => {
+                self.roster.push(Warrior::new(GridCoord([0, 0])));
+            }
+            Reward::Resources => {
+                self.bonus_resources += REWARD_RESOURCES;
+            }
+        }
+        self.stage = RunStage::Battle;
+    }
+
+    pub fn summary(&self) -> RunSummary {
+        RunSummary {
+            seed: self.seed,
+            battles_won: self.battle,
+            num_battles: self.num_battles,
+            kills: self.kills,
+            fallen: self.fallen,
+            survivors: self.roster.len() as u32,
+            victory: !self.roster.is_empty(),
+        }
+    }
+}
+
+///Returns the team that won if one side has been wiped out.
+pub fn battle_winner(game: &Game) -> Option<usize> {
+    if game.dogs.iter().next().is_none() {
+        Some(0)
+    } else if game.cats.iter().next().is_none() {
+        Some(1)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kill(tribe: &mut Tribe, num: usize) {
+        let units: Vec<_> = tribe.iter().map(|a| a.slim()).take(num).collect();
+        for a in units {
+            tribe.kill(a);
+        }
+    }
+
+    //Lose this many warriors and kill every enemy. Returns the number of kills.
+    fn win_battle(run: &mut Run, fallen: usize) -> u32 {
+        let mut game = run.start_battle();
+        kill(&mut game.cats, fallen);
+        kill(&mut game.dogs, usize::MAX);
+        run.finish_battle(&game);
+        game.dogs.lost as u32
+    }
+
+    fn enemies(game: &Game) -> Vec<(GridCoord, i8)> {
+        game.dogs.iter().map(|a| (a.position, a.health)).collect()
+    }
+
+    #[test]
+    fn same_seed_same_battles() {
+        let (mut a, mut b) = (Run::new(7), Run::new(7));
+        for _ in 0..3 {
+            assert_eq!(enemies(&a.start_battle()), enemies(&b.start_battle()));
+            win_battle(&mut a, 0);
+            win_battle(&mut b, 0);
+            let (RunStage::ChooseReward(x), RunStage::ChooseReward(y)) = (&a.stage, &b.stage)
+            else {
+                panic!("expected a reward to pick");
+            };
+            assert_eq!(x, y);
+            let pick = x[0];
+            a.choose_reward(pick);
+            b.choose_reward(pick);
+        }
+        assert_ne!(
+            enemies(&Run::new(7).start_battle()),
+            enemies(&Run::new(8).start_battle())
+        );
+    }
+
+    #[test]
+    fn dead_warriors_are_gone() {
+        let mut run = Run::new(1);
+        win_battle(&mut run, 1);
+        assert_eq!((run.roster.len(), run.fallen), (ROSTER_SIZE - 1, 1));
+
+        run.choose_reward(Reward::Resources);
+        let game = run.start_battle();
+        assert_eq!(game.cats.iter().count(), ROSTER_SIZE - 1);
+
+        //Losing everyone ends the run.
+        win_battle(&mut run, ROSTER_SIZE - 1);
+        assert!(matches!(run.stage, RunStage::Finished));
+        assert!(!run.summary().victory);
+    }
+
+    #[test]
+    fn rewards_apply() {
+        let mut run = Run::new(2);
+        run.roster[0].health = 1;
+        run.choose_reward(Reward::HealAll);
+        assert_eq!(run.roster[0].health, Warrior::MAX_HEALTH);
+
+        run.choose_reward(Reward::Recruit);
+        assert_eq!(run.roster.len(), ROSTER_SIZE + 1);
+
+        //Extra resources only last for the next battle.
+        let start = economy::EconomyRules::default().starting_resources;
+        run.choose_reward(Reward::Resources);
+        assert_eq!(run.start_battle().cats.resources, start + REWARD_RESOURCES);
+        assert_eq!(run.start_battle().cats.resources, start);
+    }
+
+    #[test]
+    fn summary_adds_up() {
+        let mut run = Run::new(3);
+        let mut kills = 0;
+        for fallen in [1, 0, 1, 0, 0] {
+            kills += win_battle(&mut run, fallen);
+            if let RunStage::ChooseReward(choices) = &run.stage {
+                let pick = choices[0];
+                run.choose_reward(pick);
+            }
+        }
+        assert!(matches!(run.stage, RunStage::Finished));
+        assert_eq!(
+            run.summary(),
+            RunSummary {
+                seed: 3,
+                battles_won: NUM_BATTLES,
+                num_battles: NUM_BATTLES,
+                kills,
+                fallen: 2,
+                survivors: run.roster.len() as u32,
+                victory: true,
+            }
+        );
+    }
+}
@@ -0,0 +1,80 @@
+use super::*;
+
+///Everything needed to restore a game.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SaveGame {
+    pub team: usize,
+    pub dogs: Tribe,
+    pub cats: Tribe,
+    pub roads: Vec<GridCoord>,
+    pub economy: economy::Economy,
+    pub survival: Option<survival::Survival>,
+    pub ai_team: Option<usize>,
+}
+
+impl SaveGame {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+    pub fn from_json(a: &str) -> Result<Self, serde_json::Error> {
+        serde_json::from_str(a)
+    }
+}
+
+impl Game {
+    pub fn save(&self) -> SaveGame {
+        SaveGame {
+            team: self.team,
+            dogs: self.dogs.clone(),
+            cats: self.cats.clone(),
+            roads: self.roads.pos.clone(),
+            economy: self.economy.clone(),
+            survival: self.survival.clone(),
+            ai_team: self.ai_team,
+        }
+    }
+
+    pub fn load(a: SaveGame) -> Game {
+        Game {
+            team: a.team,
+            grid_matrix: grids::GridMatrix::new(),
+            dogs: a.dogs,
+            cats: a.cats,
+            roads: terrain::roads(a.roads),
+            economy: a.economy,
+            survival: a.survival,
+            ai_team: a.ai_team,
+            confirm_attack: true,
+            popups: model::popup::Popups::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let mut game = survival::new_game().unwrap();
+        game.cats.add_recruit(Warrior::new(GridCoord([0, 0])));
+        game.cats.resources = 3;
+        game.roads = terrain::roads(vec![GridCoord([1, 1])]);
+        game.team = 1;
+
+        let json = game.save().to_json();
+        let loaded = Game::load(SaveGame::from_json(&json).unwrap());
+
+        assert_eq!(loaded.save().to_json(), json);
+        assert_eq!(loaded.team, 1);
+        assert_eq!(loaded.cats.iter().count(), game.cats.iter().count());
+        assert!(loaded.cats.find_slow(&GridCoord([0, 0])).is_some());
+        assert!(loaded.survival.is_some());
+    }
+
+    #[test]
+    fn rejects_garbage() {
+        assert!(SaveGame::from_json("").is_err());
+        assert!(SaveGame::from_json("{\"team\":0}").is_err());
+    }
+}
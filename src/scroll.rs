@@ -44,9 +44,22 @@ enum Foo {
     MouseActive {
         canvas_pos: [f32; 2],
     },
+    //Dragging out a selection area instead of scrolling.
+    //No touch id means the mouse is doing it.
+    AreaActive {
+        touch_id: Option<i32>,
+    },
     None,
 }
 
+///What dragging does.
+#[derive(Debug, Serialize, Deserialize, Copy, Clone, PartialEq, Eq)]
+pub enum DragMode {
+    Scroll,
+    Box,
+    Lasso,
+}
+
 #[derive(Copy, Clone)]
 struct RotDelta {
     starting_rot: f32,
@@ -76,11 +89,22 @@ impl ZoomDelta {
     }
 }
 
+///Everything needed to put the camera back where it was.
+#[derive(Debug, Copy, Clone)]
+pub struct CameraState {
+    camera: [f32; 2],
+    zoom: f32,
+    rot: f32,
+}
+
 pub struct TouchController {
     inner: ScrollController,
     foo: Foo,
     persistent_zoom: f32,
     persistent_rot: f32,
+    mode: DragMode,
+    //Canvas positions of the area being dragged out.
+    area: Vec<[f32; 2]>,
 }
 
 fn compute_middle(touches: &Touches, first: i32, second: i32) -> (f32, [f32; 2], f32) {
@@ -101,11 +125,92 @@ impl TouchController {
             foo: Foo::None,
             persistent_zoom: 0.0,
             persistent_rot: 0.0,
+            mode: DragMode::Scroll,
+            area: vec![],
+        }
+    }
+
+    pub fn camera_state(&self) -> CameraState {
+        CameraState {
+            camera: self.camera(),
+            zoom: self.persistent_zoom,
+            rot: self.persistent_rot,
+        }
+    }
+
+    pub fn set_camera_state(&mut self, a: CameraState) {
+        self.inner.set_camera(a.camera);
+        self.persistent_zoom = a.zoom;
+        self.persistent_rot = a.rot;
+    }
+
+    pub fn set_mode(&mut self, mode: DragMode) {
+        self.mode = mode;
+    }
+
+    pub fn mode(&self) -> DragMode {
+        self.mode
+    }
+
+    fn start_area(&mut self, pos: [f32; 2], touch_id: Option<i32>) {
+        //Still let a tap through as a regular select.
+        self.inner.handle_mouse_down(pos);
+        self.area = vec![pos];
+        self.foo = Foo::AreaActive { touch_id };
+    }
+
+    fn extend_area(&mut self, pos: [f32; 2]) {
+        match self.mode {
+            DragMode::Lasso => self.area.push(pos),
+            _ => {
+                self.area.truncate(1);
+                self.area.push(pos);
+            }
+        }
+    }
+
+    fn finish_area(&mut self) -> MouseUp {
+        self.foo = Foo::None;
+        let res = self.inner.handle_mouse_up();
+
+        let area = std::mem::take(&mut self.area);
+        let start: Vector2<f32> = area[0].into();
+        let dragged = area
+            .iter()
+            .any(|&a| (Vector2::from(a) - start).magnitude2() >= TOUCH_RAD * TOUCH_RAD);
+
+        if dragged {
+            MouseUp::Area(self.polygon_of(area))
+        } else {
+            res
+        }
+    }
+
+    fn polygon_of(&self, area: Vec<[f32; 2]>) -> Vec<[f32; 2]> {
+        match self.mode {
+            DragMode::Box => {
+                let a = area[0];
+                let b = *area.last().unwrap();
+                vec![a, [b[0], a[1]], b, [a[0], b[1]]]
+            }
+            _ => area,
+        }
+    }
+
+    ///The area currently being dragged out in canvas coordinates.
+    pub fn area(&self) -> Option<Vec<[f32; 2]>> {
+        if let Foo::AreaActive { .. } = self.foo {
+            Some(self.polygon_of(self.area.clone()))
+        } else {
+            None
         }
     }
 
     pub fn on_mouse_down(&mut self, canvas_pos: [f32; 2]) {
         match self.foo {
+            Foo::None if self.mode != DragMode::Scroll => {
+                self.start_area(canvas_pos, None);
+            }
             Foo::None => {
                 self.inner.handle_mouse_down(canvas_pos);
                 self.foo = Foo::MouseActive { canvas_pos }
@@ -119,6 +224,7 @@ impl TouchController {
                 self.foo = Foo::None;
                 self.inner.handle_mouse_up()
             }
+            Foo::AreaActive { touch_id: None } => self.finish_area(),
             _ => MouseUp::NoSelect,
         }
     }
@@ -128,6 +234,9 @@ impl TouchController {
                 self.inner
                     .handle_mouse_move(TOUCH_RAD, pos, view_projection, dim);
             }
+            Foo::AreaActive { touch_id: None } => {
+                self.extend_area(pos);
+            }
             _ => {}
         }
     }
@@ -156,9 +265,14 @@ impl TouchController {
                     second_touch_id,
                 }
             }
-            Foo::TwoTouchActive { .. } => {
+            Foo::TwoTouchActive { .. } | Foo::AreaActive { .. } => {
                 //ignore new touches. do nothing.
             }
+            Foo::None if self.mode != DragMode::Scroll => {
+                let touch_id = touches.select_lowest_touch().unwrap();
+                let pos = touches.get_pos(touch_id).unwrap();
+                self.start_area(pos, Some(touch_id));
+            }
             Foo::None => {
                 //Guarenteed to exist because this function is called on new touch.
                 let touch_id = touches.select_lowest_touch().unwrap();
@@ -206,6 +320,16 @@ impl TouchController {
                     second_touch_id,
                 }
             }
+            Foo::AreaActive {
+                touch_id: Some(touch_id),
+            } => {
+                if let Some(pos) = touches.get_pos(touch_id) {
+                    self.extend_area(pos);
+                }
+            }
+            Foo::AreaActive { touch_id: None } => {
+                //ignore touch mouse active
+            }
             Foo::None => {
                 //A touch moved that we don't care about.
             }
@@ -273,6 +397,16 @@ impl TouchController {
                     }
                 }
             }
+            Foo::AreaActive {
+                touch_id: Some(touch_id),
+            } => {
+                if touches.get_pos(touch_id).is_none() {
+                    self.finish_area()
+                } else {
+                    MouseUp::NoSelect
+                }
+            }
+            Foo::AreaActive { touch_id: None } => MouseUp::NoSelect,
             Foo::None => {
                 //Touch up for a touch we don't care about.
                 MouseUp::NoSelect
@@ -337,6 +471,8 @@ pub enum MouseUp {
     Select,
     /// This was a scroll mouse up
     NoSelect,
+    /// An area was dragged out. Polygon in canvas coordinates.
+    Area(Vec<[f32; 2]>),
 }
 
 #[derive(PartialEq, Debug)]
@@ -377,6 +513,13 @@ impl ScrollController {
         [self.camera[0], self.camera[1]]
     }
 
+    //Jump to a position without any momentum.
+    pub fn set_camera(&mut self, camera: [f32; 2]) {
+        self.camera = camera.into();
+        self.last_camera = self.camera;
+        self.scrolling = Scrollin::NotScrolling;
+    }
+
     pub fn handle_mouse_move(
         &mut self,
         buffer_radius: f32,
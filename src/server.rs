@@ -0,0 +1,339 @@
+use super::*;
+use crate::state::{GameHandle, PlayerCellAskRes, Stuff};
+
+///What a client can ask the server to do.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum Command {
+    ///Look at the whole board.
+    State,
+    ///List where a unit can go and what it can attack.
+    Moves {
+        unit: GridCoord,
+    },
+    Move {
+        unit: GridCoord,
+        to: GridCoord,
+    },
+    Attack {
+        unit: GridCoord,
+        target: GridCoord,
+    },
+    EndTurn,
+}
+
+///One line sent by a client.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Request {
+    pub team: usize,
+    pub command: Command,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct UnitView {
+    pub position: GridCoord,
+    pub health: i8,
+    pub stamina: i8,
+    pub attacked: bool,
+}
+
+///What clients get to see of the game.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct View {
+    pub turn: u32,
+    ///The team that is moving.
+    pub team: usize,
+    pub units: [Vec<UnitView>; 2],
+    pub resources: [i16; 2],
+    pub winner: Option<usize>,
+}
+
+///One line sent back to a client.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum Response {
+    State(View),
+    Moves {
+        moves: Vec<GridCoord>,
+        attacks: Vec<GridCoord>,
+    },
+    ///The command was carried out.
+    Ok {
+        team: usize,
+        winner: Option<usize>,
+    },
+    ///Sent first on a connection that plays one team.
+    Joined {
+        team: usize,
+    },
+    Rejected(String),
+}
+
+///Why a command was not carried out.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Illegal {
+    NoSuchTeam(usize),
+    ///The client only plays the other team.
+    NotYourTeam(usize),
+    GameOver,
+    NotYourTurn,
+    NoUnit(GridCoord),
+    ///The unit has nothing left to do this turn.
+    Exhausted(GridCoord),
+    OutOfReach(GridCoord),
+    AlreadyAttacked(GridCoord),
+}
+
+impl std::fmt::Display for Illegal {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Illegal::NoSuchTeam(a) => write!(f, "there is no team {}", a),
+            Illegal::NotYourTeam(a) => write!(f, "you are not playing team {}", a),
+            Illegal::GameOver => write!(f, "the game is over"),
+            Illegal::NotYourTurn => write!(f, "it is not your turn"),
+            Illegal::NoUnit(a) => write!(f, "you have no unit at {:?}", a.0),
+            Illegal::Exhausted(a) => write!(f, "the unit at {:?} can't do anything else", a.0),
+            Illegal::OutOfReach(a) => write!(f, "{:?} is out of reach", a.0),
+            Illegal::AlreadyAttacked(a) => write!(f, "the unit at {:?} already attacked", a.0),
+        }
+    }
+}
+
+//Step something that needs no input until it is done. Animations play out instantly.
+fn run_to_end<G: GameStepper<GameHandle>>(mut g: G, stuff: &mut Stuff) -> G::Result {
+    loop {
+        if let gameplay::Stage::NextStage(a) = g.step(stuff) {
+            return g.consume(stuff, a);
+        }
+    }
+}
+
+//The cells a unit can move to and the enemies it can attack, using the same rules as tapping in the game.
+fn legal_moves(
+    unit: GridCoord,
+    stuff: &Stuff,
+) -> Result<(Vec<GridCoord>, Vec<GridCoord>), Illegal> {
+    let w = stuff
+        .this_team
+        .find_slow(&unit)
+        .ok_or(Illegal::NoUnit(unit))?;
+    if !w.selectable() {
+        return Err(Illegal::Exhausted(unit));
+    }
+
+    let CellSelection::MoveSelection(ss, att) = state::generate_unit_possible_moves(&w, stuff)
+    else {
+        unreachable!()
+    };
+
+    let empty = |a: &&GridCoord| {
+        stuff.this_team.find_slow(a).is_none() && stuff.that_team.find_slow(a).is_none()
+    };
+    let moves = ss.iter_coords().filter(empty).copied().collect();
+
+    let attacks = if w.attacked {
+        vec![]
+    } else {
+        att.iter_coords()
+            .filter(|a| stuff.that_team.find_slow(a).is_some())
+            .copied()
+            .collect()
+    };
+    Ok((moves, attacks))
+}
+
+///A game owned by the server. Clients can only change it through commands that follow the rules.
+pub struct Match {
+    game: Game,
+    turn: u32,
+}
+
+impl Match {
+    ///Both teams are played by clients. The board is the same as a play-by-mail game with the same seed.
+    pub fn new(seed: u64) -> Self {
+        let mut m = Match {
+            game: mail::new_game(seed),
+            turn: 0,
+        };
+        m.start_turn();
+        m
+    }
+
+    pub fn winner(&self) -> Option<usize> {
+        run::battle_winner(&self.game)
+    }
+
+    pub fn view(&self) -> View {
+        let units = |tribe: &Tribe| {
+            tribe
+                .iter()
+                .map(|a| UnitView {
+                    position: a.position,
+                    health: a.health,
+                    stamina: a.stamina.0,
+                    attacked: a.attacked,
+                })
+                .collect()
+        };
+        View {
+            turn: self.turn,
+            team: self.game.team,
+            units: [units(&self.game.cats), units(&self.game.dogs)],
+            resources: [self.game.cats.resources, self.game.dogs.resources],
+            winner: self.winner(),
+        }
+    }
+
+    fn start_turn(&mut self) {
+        let team = self.game.team;
+        realtime::with_stuff(&mut self.game, team, |stuff| {
+            run_to_end(state::start_player_turn(), stuff)
+        });
+        //Nobody sees the popups here.
+        self.game.popups.clear();
+    }
+
+    fn end_turn(&mut self) {
+        let team = self.game.team;
+        realtime::with_stuff(&mut self.game, team, state::end_player_turn);
+        self.game.team = 1 - team;
+        self.turn += 1;
+        self.start_turn();
+    }
+
+    fn execute(&mut self, team: usize, command: Command) -> Result<(), Illegal> {
+        if self.winner().is_some() {
+            return Err(Illegal::GameOver);
+        }
+        if team != self.game.team {
+            return Err(Illegal::NotYourTurn);
+        }
+
+        let end_turn = command == Command::EndTurn;
+        realtime::with_stuff(&mut self.game, team, |stuff| {
+            let (unit, res) = match command {
+                Command::Move { unit, to } => {
+                    let (moves, _) = legal_moves(unit, stuff)?;
+                    if !moves.contains(&to) {
+                        return Err(Illegal::OutOfReach(to));
+                    }
+                    (unit, PlayerCellAskRes::MoveTo(to))
+                }
+                Command::Attack { unit, target } => {
+                    let (_, attacks) = legal_moves(unit, stuff)?;
+                    if stuff.this_team.find_slow(&unit).unwrap().attacked {
+                        return Err(Illegal::AlreadyAttacked(unit));
+                    }
+                    if !attacks.contains(&target) {
+                        return Err(Illegal::OutOfReach(target));
+                    }
+                    let target = stuff.that_team.find_slow(&target).unwrap().slim();
+                    (unit, PlayerCellAskRes::Attack(target))
+                }
+                _ => return Ok(()),
+            };
+
+            let w = stuff.this_team.find_slow(&unit).unwrap();
+            let ptr = w.slim();
+            let cc = state::generate_unit_possible_moves(&w, stuff);
+            run_to_end(state::handle_one_execution(ptr, cc, res, stuff), stuff);
+            Ok(())
+        })?;
+        self.game.popups.clear();
+
+        if end_turn {
+            self.end_turn();
+        }
+        Ok(())
+    }
+
+    pub fn handle(&mut self, req: Request) -> Response {
+        if req.team > 1 {
+            return Response::Rejected(Illegal::NoSuchTeam(req.team).to_string());
+        }
+        match req.command {
+            Command::State => Response::State(self.view()),
+            Command::Moves { unit } => {
+                match realtime::with_stuff(&mut self.game, req.team, |stuff| {
+                    legal_moves(unit, stuff)
+                }) {
+                    Ok((moves, attacks)) => Response::Moves { moves, attacks },
+                    Err(e) => Response::Rejected(e.to_string()),
+                }
+            }
+            command => match self.execute(req.team, command) {
+                Ok(()) => Response::Ok {
+                    team: self.game.team,
+                    winner: self.winner(),
+                },
+                Err(e) => Response::Rejected(e.to_string()),
+            },
+        }
+    }
+
+    ///Handle one line of the protocol and return the line to send back.
+    pub fn handle_line(&mut self, line: &str) -> String {
+        self.respond(line, None)
+    }
+
+    ///Like [`Match::handle_line`] for a client that may only play `team`.
+    pub fn handle_line_for(&mut self, team: usize, line: &str) -> String {
+        self.respond(line, Some(team))
+    }
+
+    fn respond(&mut self, line: &str, team: Option<usize>) -> String {
+        let res = match serde_json::from_str::<Request>(line) {
+            Ok(req) if team.is_some_and(|a| a != req.team) => {
+                Response::Rejected(Illegal::NotYourTeam(req.team).to_string())
+            }
+            Ok(req) => self.handle(req),
+            Err(e) => Response::Rejected(format!("bad request: {}", e)),
+        };
+        serde_json::to_string(&res).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn popups_dont_pile_up() {
+        let mut m = Match::new(7);
+        assert!(m.game.popups.is_empty());
+        for turn in 0..6 {
+            let res = m.handle(Request {
+                team: turn % 2,
+                command: Command::EndTurn,
+            });
+            assert!(matches!(res, Response::Ok { .. }), "{:?}", res);
+            assert!(m.game.popups.is_empty());
+        }
+    }
+
+    #[test]
+    fn clients_only_play_their_team() {
+        let mut m = Match::new(7);
+        let end_turn = |team| {
+            serde_json::to_string(&Request {
+                team,
+                command: Command::EndTurn,
+            })
+            .unwrap()
+        };
+        let res = |a: String| serde_json::from_str::<Response>(&a).unwrap();
+
+        assert_eq!(
+            res(m.handle_line_for(1, &end_turn(0))),
+            Response::Rejected(Illegal::NotYourTeam(0).to_string())
+        );
+        assert_eq!(m.view().team, 0);
+        assert!(matches!(
+            res(m.handle_line_for(0, &end_turn(0))),
+            Response::Ok { team: 1, .. }
+        ));
+        //Anyone on stdin plays both.
+        assert!(matches!(
+            res(m.handle_line(&end_turn(1))),
+            Response::Ok { team: 0, .. }
+        ));
+    }
+}
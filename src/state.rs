@@ -15,8 +15,21 @@ pub struct Stuff<'a> {
     pub grid_matrix: &'a grids::GridMatrix,
     pub this_team: &'a mut Tribe,
     pub that_team: &'a mut Tribe,
+    pub roads: &'a mut terrain::Roads,
+    pub economy: &'a economy::Economy,
+    pub survival: Option<&'a mut survival::Survival>,
+    pub ai_team: Option<usize>,
     pub mouse: Option<[f32; 2]>,
     pub end_turn: bool,
+    pub ability: Option<ability::Ability>,
+    pub recruit: bool,
+    pub upgrade: bool,
+    pub order: Option<orders::OrderKind>,
+    //An area dragged out by the player in world coordinates.
+    pub area: Option<Vec<[f32; 2]>>,
+    pub cancel_route: bool,
+    pub confirm_attack: bool,
+    pub popups: &'a mut model::popup::Popups,
 }
 
 fn select_unit() -> impl GameStepper<GameHandle, Result = WarriorPointer<GridCoord>> {
@@ -46,38 +59,32 @@ fn attack_init(
     current: &WarriorPointer<GridCoord>,
     target: &WarriorPointer<GridCoord>,
 ) -> impl GameStepper<GameHandle, Result = Option<WarriorPointer<GridCoord>>> {
-    //Only counter if non neg
-    // let counter_damage = if g1.this_team.lookup_mut(current).move_bank.0>=0{
-    //     5
-    // }else{
-    //     0
-    // };
-    // let damage = 5;
-    // let counter_damage = 5;
-    let damage = 5;
-    let counter_damage = 5;
+    let fc = combat::forecast(&g1.this_team.lookup(*current), &g1.that_team.lookup(*target));
+    let damage = fc.damage;
+    let counter_damage = fc.counter_damage;
 
     let cc = *current;
 
-    let kill_self = g1.this_team.lookup_mut(current).health <= counter_damage;
-
-    let (path, _) = ss.get_path_data(target).unwrap();
+    let kill_self = fc.kills_self;
 
     //let attack_stamina_cost=2;
-    let total_cost = path.total_cost();
+    let total_cost = ss.cost_to(target).unwrap();
     log!(format!("total_cost:{:?}", total_cost));
-    if g1.that_team.lookup_mut(target).health <= damage {
+    if fc.kills_target {
         let c = g1.this_team.lookup_take(*current);
 
         //TODO pass path instead!!!
         kill_animator(ss, c, target, g1)
             .map(move |this_unit, g1| {
                 let target = this_unit.slim();
-                g1.that_team.lookup_take(target);
+                g1.that_team.kill(target);
                 g1.this_team.add(this_unit);
 
                 let mut current_cat = g1.this_team.lookup_mut(&target);
 
+                combat_text::damage(g1.popups, g1.grid_matrix, target.inner, damage);
+                combat_text::ko(g1.popups, g1.grid_matrix, target.inner);
+                current_cat.gain_experience(experience::kill_xp(damage));
                 current_cat.attacked = true;
                 //dont need to double sub because we moved there
                 //current_cat.stamina.0-=attack_stamina_cost;
@@ -94,15 +101,28 @@ fn attack_init(
                 g1.this_team.add(this_unit);
                 let mut target_cat = g1.that_team.lookup_mut(&target);
                 target_cat.health -= damage;
+                combat_text::damage(g1.popups, g1.grid_matrix, target.inner, damage);
+
+                //The defender earns experience from hitting back.
+                if kill_self {
+                    target_cat.gain_experience(experience::kill_xp(counter_damage));
+                } else {
+                    target_cat.gain_experience(experience::damage_xp(counter_damage));
+                }
 
                 let mut current_cat = g1.this_team.lookup_mut(&cc);
 
+                if counter_damage > 0 {
+                    combat_text::damage(g1.popups, g1.grid_matrix, cc.inner, counter_damage);
+                }
                 if kill_self {
-                    g1.this_team.lookup_take(cc);
+                    combat_text::ko(g1.popups, g1.grid_matrix, cc.inner);
+                    g1.this_team.kill(cc);
                 } else {
+                    current_cat.gain_experience(experience::damage_xp(damage));
                     current_cat.attacked = true;
                     current_cat.health -= counter_damage;
-                    current_cat.stamina.0 -= total_cost.0;
+                    current_cat.spend_stamina(total_cost);
                     //current_cat.stamina.0 -= attack_stamina_cost;
                 }
                 None
@@ -149,8 +169,8 @@ fn move_animator(
     target: &GridCoord,
     g1: &mut Stuff,
 ) -> impl GameStepper<GameHandle, Result = WarriorPointer<Warrior>> {
-    let (dd, aa) = ss.get_path_data(target).unwrap();
-    start.stamina.0 -= dd.total_cost().0;
+    let (dd, _) = ss.get_path_data(target).unwrap();
+    start.spend_stamina(ss.cost_to(target).unwrap());
 
     //let extra=dd.diag_move_cost();
     //start.move_bank.0-=extra.0;
@@ -166,24 +186,25 @@ fn move_animator(
 }
 
 //Execute a player move. Return whether or not the unit moved as a result.
-fn handle_one_execution(
+pub fn handle_one_execution(
     sss: WarriorPointer<GridCoord>,
     c: CellSelection,
     cell: PlayerCellAskRes,
     g1: &mut Stuff,
 ) -> impl GameStepper<GameHandle, Result = Option<WarriorPointer<GridCoord>>> {
-    let (ss, att) = match c {
-        CellSelection::MoveSelection(ss, a) => (ss, a),
-        _ => unreachable!(),
-    };
-
     match cell {
         PlayerCellAskRes::Attack(cell) => {
+            let CellSelection::MoveSelection(_, att) = c else {
+                unreachable!()
+            };
             //If attack handle attack.
             let n = attack_init(&att, g1, &sss, &cell);
             n.either_a()
         }
         PlayerCellAskRes::MoveTo(target) => {
+            let CellSelection::MoveSelection(ss, _) = c else {
+                unreachable!()
+            };
             let doop = g1.this_team.lookup_take(sss);
 
             let aaa = move_animator(&ss, doop, &target, g1).map(|target, game| {
@@ -191,19 +212,36 @@ fn handle_one_execution(
                 game.this_team.add(target);
                 Some(ooo)
             });
-            aaa.either_b()
+            aaa.either_a().either_b()
+        }
+        PlayerCellAskRes::Ability(ab, target) => {
+            let CellSelection::AbilitySelection(_, targets) = c else {
+                unreachable!()
+            };
+            ability::execute(ab, &targets, sss, target, g1)
+                .either_b()
+                .either_b()
         }
     }
     .map(|a, _| match a {
         gameplay::Either::A(a) => a,
-        gameplay::Either::B(a) => a,
+        gameplay::Either::B(gameplay::Either::A(a)) => a,
+        gameplay::Either::B(gameplay::Either::B(a)) => a,
     })
 }
 
 fn handle_player_move_inner() -> impl GameStepper<GameHandle, Result = Option<()>> {
 
     select_unit()
+        .map(|c, _| gameplay::Either::A(c))
+        .or(group::WaitArea.map(|poly, _| gameplay::Either::B(poly)))
         .map(move |c, stuff| {
+            let c = match c {
+                gameplay::Either::A(c) => c,
+                gameplay::Either::B(poly) => {
+                    return group::handle_group_select(&poly, stuff).either_b();
+                }
+            };
             gameplay::looper(c, |c, stuff| {
                 let unit = stuff.this_team.lookup(c);
                 let cc = generate_unit_possible_moves(&unit, stuff);
@@ -237,6 +275,7 @@ fn handle_player_move_inner() -> impl GameStepper<GameHandle, Result = Option<()
                     }
                 })
             })
+            .either_a()
         })
         .flatten()
         .map(|a, _| Some(()))
@@ -249,12 +288,11 @@ fn handle_player_move() -> impl GameStepper<GameHandle, Result = ()> {
         handle_player_move_inner()
             .map(|_, _| false)
             .or(wait_end_turn_button().map(|_, _| true))
+            .or(WaitEconomyButton.map(|_, _| false))
     };
 
-    gameplay::next::<GameHandle>()
-        .map(move |_, stuff: &mut Stuff| {
-            stuff.this_team.replenish_stamina();
-
+    start_player_turn()
+        .map(move |_, _| {
             gameplay::looper((), move |_, _| {
                 loops().map(|res, _| {
                     if res {
@@ -266,14 +304,53 @@ fn handle_player_move() -> impl GameStepper<GameHandle, Result = ()> {
             })
         })
         .flatten()
-        .map(|_, stuff| {
-            stuff.this_team.reset_attacked();
+        .map(|_, stuff| end_player_turn(stuff))
+}
+
+///Everything that happens at the start of a player's turn before they get to move.
+pub fn start_player_turn() -> impl GameStepper<GameHandle, Result = ()> {
+    gameplay::next::<GameHandle>()
+        .map(move |_, stuff: &mut Stuff| {
+            let income = stuff.economy.income(stuff.this_team);
+            stuff.this_team.resources += income;
+            start_turn(stuff);
+
+            orders::handle_standing_orders()
         })
+        .flatten()
+        .map(|_, _| route::handle_routes())
+        .flatten()
+}
+
+///Refill stamina and apply status effects at the start of any team's turn.
+pub fn start_turn(stuff: &mut Stuff) {
+    let before = combat_text::Snapshot::new(stuff.this_team);
+    stuff.this_team.replenish_stamina();
+    stuff.this_team.apply_effects();
+    combat_text::turn_start(stuff, before);
+}
+
+pub fn end_player_turn(stuff: &mut Stuff) {
+    stuff.this_team.reset_attacked();
+    stuff.this_team.tick_effects();
 }
 
 pub fn create_state_machine() -> impl GameStepper<GameHandle> {
-    gameplay::looper((), move |_, _| {
-        handle_player_move().map(|_, stuff| {
+    gameplay::looper((), move |_, stuff| {
+        if survival::is_game_over(stuff) {
+            WaitForever.either_a()
+        } else if stuff.ai_team == Some(*stuff.team) {
+            if stuff.survival.is_some() {
+                survival::handle_wave_turn().either_a()
+            } else {
+                ai::handle_ai_turn().either_b()
+            }
+            .either_a()
+            .either_b()
+        } else {
+            handle_player_move().either_b().either_b()
+        }
+        .map(|_, stuff| {
             *stuff.team += 1;
             if *stuff.team > 1 {
                 *stuff.team = 0;
@@ -283,6 +360,19 @@ pub fn create_state_machine() -> impl GameStepper<GameHandle> {
     })
 }
 
+//Used once the game is over. Nothing happens anymore.
+struct WaitForever;
+impl GameStepper<GameHandle> for WaitForever {
+    type Result = ();
+    type Int = ();
+    fn step(&mut self, _: &mut Stuff<'_>) -> gameplay::Stage<()> {
+        gameplay::Stage::Stay
+    }
+    fn consume(self, _: &mut Stuff<'_>, _: ()) -> Self::Result {
+        unreachable!()
+    }
+}
+
 struct WaitResetButton;
 impl GameStepper<GameHandle> for WaitResetButton {
     type Result = ();
@@ -299,6 +389,28 @@ impl GameStepper<GameHandle> for WaitResetButton {
     }
 }
 
+//Wait for the recruit or upgrade button and spend resources on it.
+struct WaitEconomyButton;
+impl GameStepper<GameHandle> for WaitEconomyButton {
+    type Result = ();
+    type Int = ();
+    fn step(&mut self, game: &mut Stuff<'_>) -> gameplay::Stage<()> {
+        if game.recruit || game.upgrade {
+            gameplay::Stage::NextStage(())
+        } else {
+            gameplay::Stage::Stay
+        }
+    }
+    fn consume(self, game: &mut Stuff<'_>, _: ()) -> Self::Result {
+        if game.recruit && !economy::recruit(game) {
+            log!("could not recruit");
+        }
+        if game.upgrade && !economy::upgrade(game) {
+            log!("could not upgrade");
+        }
+    }
+}
+
 struct WaitMouseInput;
 impl GameStepper<GameHandle> for WaitMouseInput {
     type Result = [f32; 2];
@@ -315,7 +427,7 @@ impl GameStepper<GameHandle> for WaitMouseInput {
     }
 }
 
-struct AnimationTicker {
+pub struct AnimationTicker {
     a: animation::Animation<WarriorPointer<Warrior>>,
 }
 impl AnimationTicker {
@@ -346,16 +458,23 @@ struct PlayerCellAsk {
     a: CellSelection,
     //We know what type of warrior is selected at this point.
     stuff: WarriorPointer<GridCoord>,
+    //An attack that was tapped once and is waiting for a second tap to confirm.
+    pending: Option<combat::Forecast>,
 }
 
 impl PlayerCellAsk {
     pub fn new(a: CellSelection, stuff: WarriorPointer<GridCoord>) -> Self {
-        Self { a, stuff }
+        Self {
+            a,
+            stuff,
+            pending: None,
+        }
     }
 }
-enum PlayerCellAskRes {
+pub enum PlayerCellAskRes {
     Attack(WarriorPointer<GridCoord>),
     MoveTo(GridCoord),
+    Ability(ability::Ability, GridCoord),
 }
 impl GameStepper<GameHandle> for PlayerCellAsk {
     type Result = (
@@ -367,13 +486,51 @@ impl GameStepper<GameHandle> for PlayerCellAsk {
     fn get_selection(&self) -> Option<&CellSelection> {
         Some(&self.a)
     }
+    fn get_forecast(&self) -> Option<&combat::Forecast> {
+        self.pending.as_ref()
+    }
     fn consume(self, _: &mut Stuff<'_>, grid_coord: Self::Int) -> Self::Result {
         (self.stuff, self.a, grid_coord)
     }
     fn step(&mut self, g1: &mut Stuff<'_>) -> gameplay::Stage<Self::Int> {
+        if let Some(ab) = g1.ability {
+            let unit = g1.this_team.lookup(self.stuff);
+            if g1.this_team.get_abilities(&unit).contains(&ab)
+                && ab.can_use(&unit, g1.this_team, &g1.economy.rules)
+            {
+                self.pending = None;
+                self.a = CellSelection::AbilitySelection(ab, ab.targets(&unit, g1));
+            }
+            return gameplay::Stage::Stay;
+        }
+
+        if let Some(kind) = g1.order {
+            self.pending = None;
+            let unit = g1.this_team.lookup(self.stuff);
+            if kind.needs_target() {
+                self.a = CellSelection::OrderSelection(kind, kind.targets(&unit, g1));
+            } else {
+                let order = kind.create(unit.position, None);
+                log!(format!("standing order:{:?}", order));
+                let mut unit = g1.this_team.lookup_mut(&self.stuff);
+                unit.standing = order;
+                unit.route.clear();
+            }
+            return gameplay::Stage::Stay;
+        }
+
+        if g1.cancel_route {
+            let mut unit = g1.this_team.lookup_mut(&self.stuff);
+            unit.route.clear();
+            return gameplay::Stage::Stay;
+        }
+
         if let Some(mouse_world) = g1.mouse {
             let cell: GridCoord = GridCoord(g1.grid_matrix.to_grid((mouse_world).into()).into());
 
+            //Any tap clears the preview. Tapping the same enemy again confirms it.
+            let pending = self.pending.take();
+
             match &self.a {
                 CellSelection::MoveSelection(ss, attack) => {
                     let target_cat_pos = &cell;
@@ -388,6 +545,18 @@ impl GameStepper<GameHandle> for PlayerCellAsk {
                         if !current_attack
                             && movement::contains_coord(attack.iter_coords(), target_cat_pos)
                         {
+                            let confirmed = pending.map(|p| p.defender == *target_cat_pos);
+
+                            if g1.confirm_attack && confirmed != Some(true) {
+                                let fc = combat::forecast(
+                                    &g1.this_team.lookup(self.stuff),
+                                    &g1.that_team.lookup(aaa),
+                                );
+                                log!(format!("forecast:{:?}", fc));
+                                self.pending = Some(fc);
+                                return gameplay::Stage::Stay;
+                            }
+
                             Some(PlayerCellAskRes::Attack(aaa))
                         } else {
                             None
@@ -406,6 +575,16 @@ impl GameStepper<GameHandle> for PlayerCellAsk {
                             self.a = generate_unit_possible_moves(&va, g1);
                             self.stuff = va.slim();
                             return gameplay::Stage::Stay;
+                        } else if let Some(r) = route::plan(&g1.this_team.lookup(self.stuff), cell, g1) {
+                            //Too far to get to this turn. Remember the way and start walking.
+                            let mut unit = g1.this_team.lookup_mut(&self.stuff);
+                            unit.route = r;
+                            unit.standing = None;
+
+                            match route::next_step(self.stuff, g1) {
+                                Some((_, res)) => Some(res),
+                                None => return gameplay::Stage::Stay,
+                            }
                         } else {
                             None
                         }
@@ -413,9 +592,30 @@ impl GameStepper<GameHandle> for PlayerCellAsk {
 
                     gameplay::Stage::NextStage(aa)
                 }
-                _ => {
-                    todo!()
+                CellSelection::AbilitySelection(ab, targets) => {
+                    if movement::contains_coord(targets.iter_coords(), &cell) {
+                        gameplay::Stage::NextStage(Some(PlayerCellAskRes::Ability(*ab, cell)))
+                    } else {
+                        //Tapping anywhere else cancels the ability.
+                        let unit = g1.this_team.lookup(self.stuff);
+                        self.a = generate_unit_possible_moves(&unit, g1);
+                        gameplay::Stage::Stay
+                    }
+                }
+                CellSelection::OrderSelection(kind, cells) => {
+                    //Tapping anywhere else cancels the order.
+                    if movement::contains_coord(cells.iter_coords(), &cell) {
+                        let mut unit = g1.this_team.lookup_mut(&self.stuff);
+                        unit.standing = kind.create(unit.position, Some(cell));
+                        unit.route.clear();
+                        log!(format!("standing order:{:?}", unit.standing));
+                    }
+                    let unit = g1.this_team.lookup(self.stuff);
+                    self.a = generate_unit_possible_moves(&unit, g1);
+                    gameplay::Stage::Stay
                 }
+                //Groups are handled by the group module.
+                CellSelection::GroupSelection(_) => unreachable!(),
             }
         } else {
             gameplay::Stage::Stay
@@ -453,7 +653,7 @@ pub fn generate_unit_possible_moves(unit: &WarriorPointer<&Warrior>, game: &Stuf
         let mm = movement::PossibleMoves::new(
             &movement::WarriorMovement,
             &gg.filter().chain(cat_filter),
-            &terrain::Grass.chain(roads),
+            &terrain::Grass.chain(roads).chain(cat.effects.move_cost()),
             cat.position,
             mm,
         );
@@ -479,7 +679,7 @@ pub fn generate_unit_possible_moves(unit: &WarriorPointer<&Warrior>, game: &Stuf
         data,
         &unit,
         game.this_team.filter().chain(game.that_team.filter()),
-        terrain::Grass,
+        game.roads.foo(),
         &game.grid_matrix,
         true,
     )
@@ -0,0 +1,168 @@
+use super::*;
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+pub enum EffectKind {
+    ///Lose health at the start of every turn.
+    Poison,
+    ///Can't move or attack.
+    Stun,
+    ///Every step costs more stamina.
+    Slow,
+    ///Take less damage.
+    Shield,
+    ///Deal more damage.
+    Rally,
+}
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone)]
+pub struct StatusEffect {
+    pub kind: EffectKind,
+    //Number of the owners turns left before this wears off.
+    pub turns: u8,
+}
+
+const POISON_DAMAGE: i8 = 1;
+const SLOW_COST: i8 = 1;
+const SHIELD_BLOCK: i8 = 2;
+const RALLY_BONUS: i8 = 2;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct StatusEffects {
+    effects: Vec<StatusEffect>,
+}
+impl StatusEffects {
+    ///Apply an effect. If the unit already has it, keep whichever duration is longer.
+    pub fn add(&mut self, kind: EffectKind, turns: u8) {
+        if let Some(a) = self.effects.iter_mut().find(|a| a.kind == kind) {
+            a.turns = a.turns.max(turns);
+        } else {
+            self.effects.push(StatusEffect { kind, turns });
+        }
+    }
+    pub fn has(&self, kind: EffectKind) -> bool {
+        self.effects.iter().any(|a| a.kind == kind)
+    }
+    pub fn iter(&self) -> impl Iterator<Item = &StatusEffect> {
+        self.effects.iter()
+    }
+
+    ///Count down every effect by one turn and drop the ones that wore off.
+    pub fn tick(&mut self) {
+        for a in self.effects.iter_mut() {
+            a.turns = a.turns.saturating_sub(1);
+        }
+        self.effects.retain(|a| a.turns > 0);
+    }
+
+    pub fn turn_damage(&self) -> i8 {
+        if self.has(EffectKind::Poison) {
+            POISON_DAMAGE
+        } else {
+            0
+        }
+    }
+    pub fn damage_dealt(&self, damage: i8) -> i8 {
+        if self.has(EffectKind::Rally) {
+            damage + RALLY_BONUS
+        } else {
+            damage
+        }
+    }
+    pub fn damage_taken(&self, damage: i8) -> i8 {
+        if self.has(EffectKind::Shield) {
+            (damage - SHIELD_BLOCK).max(0)
+        } else {
+            damage
+        }
+    }
+    pub fn move_cost(&self) -> EffectMoveCost {
+        EffectMoveCost {
+            slow: self.has(EffectKind::Slow),
+        }
+    }
+}
+
+pub struct EffectMoveCost {
+    slow: bool,
+}
+impl MoveCost for EffectMoveCost {
+    fn foop(&self, _: GridCoord, z: MoveUnit) -> MoveUnit {
+        if self.slow {
+            z.add(MoveUnit(SLOW_COST))
+        } else {
+            z
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn effects_wear_off() {
+        let mut a = StatusEffects::default();
+        a.add(EffectKind::Poison, 2);
+        a.add(EffectKind::Stun, 1);
+
+        a.tick();
+        assert!(a.has(EffectKind::Poison));
+        assert!(!a.has(EffectKind::Stun));
+
+        a.tick();
+        assert_eq!(a.iter().count(), 0);
+        //Nothing left to count down.
+        a.tick();
+        assert_eq!(a.iter().count(), 0);
+    }
+
+    #[test]
+    fn stacking_keeps_the_longest() {
+        let mut a = StatusEffects::default();
+        a.add(EffectKind::Shield, 3);
+        a.add(EffectKind::Shield, 1);
+        assert_eq!(a.iter().count(), 1);
+        assert_eq!(a.iter().next().unwrap().turns, 3);
+
+        a.tick();
+        a.add(EffectKind::Shield, 4);
+        assert_eq!(a.iter().next().unwrap().turns, 4);
+    }
+
+    #[test]
+    fn damage_modifiers() {
+        let mut a = StatusEffects::default();
+        assert_eq!(
+            (a.turn_damage(), a.damage_dealt(3), a.damage_taken(3)),
+            (0, 3, 3)
+        );
+
+        a.add(EffectKind::Poison, 1);
+        a.add(EffectKind::Rally, 1);
+        a.add(EffectKind::Shield, 1);
+        assert_eq!(a.turn_damage(), POISON_DAMAGE);
+        assert_eq!(a.damage_dealt(3), 3 + RALLY_BONUS);
+        assert_eq!(a.damage_taken(3), 3 - SHIELD_BLOCK);
+        assert_eq!(a.damage_taken(1), 0);
+    }
+
+    #[test]
+    fn slow_costs_more_stamina() {
+        use movement::tests::Anywhere;
+        let cost = |a: &StatusEffects| {
+            let moves = movement::PossibleMoves::new(
+                &movement::WarriorMovement,
+                &Anywhere,
+                &terrain::Grass.chain(a.move_cost()),
+                GridCoord([0, 0]),
+                MoveUnit(6),
+            );
+            moves.cost_to(&GridCoord([1, 0])).unwrap().0
+        };
+
+        let mut a = StatusEffects::default();
+        assert_eq!(cost(&a), 2);
+        a.add(EffectKind::Slow, 1);
+        assert_eq!(cost(&a), 2 + SLOW_COST);
+    }
+}
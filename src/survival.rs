@@ -0,0 +1,371 @@
+use super::*;
+use crate::state::{GameHandle, Stuff};
+
+//The team that is controlled by the computer in survival mode.
+pub const AI_TEAM: usize = 1;
+
+const WAVES_JSON: &str = include_str!("../assets/waves.json");
+
+///Something wrong with the wave data.
+#[derive(Debug)]
+pub enum WaveError {
+    Json(serde_json::Error),
+    ///A cycle has to be at least one turn long.
+    NoCycle,
+    ///A wave is set to show up on a turn outside of the cycle.
+    TurnOutOfCycle {
+        wave: usize,
+        turn: u16,
+    },
+    ///Units have to spawn on the edge of the grid.
+    SpawnOffEdge {
+        wave: usize,
+        cell: GridCoord,
+    },
+}
+
+impl std::fmt::Display for WaveError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WaveError::Json(e) => write!(f, "invalid wave data: {}", e),
+            WaveError::NoCycle => write!(f, "cycle_turns has to be at least 1"),
+            WaveError::TurnOutOfCycle { wave, turn } => {
+                write!(
+                    f,
+                    "wave {} is on turn {} which is outside of the cycle",
+                    wave, turn
+                )
+            }
+            WaveError::SpawnOffEdge { wave, cell } => {
+                write!(
+                    f,
+                    "wave {} spawns at {:?} which is not on the edge of the grid",
+                    wave, cell.0
+                )
+            }
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SpawnDef {
+    pub cell: GridCoord,
+    pub health: i8,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WaveDef {
+    //Turn of the cycle this wave shows up on. Starts at 1.
+    pub turn: u16,
+    pub spawns: Vec<SpawnDef>,
+}
+
+///Describes the waves. Once all the waves are done they repeat, but stronger.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WaveData {
+    ///Cells the player has to keep the enemy off of.
+    pub objectives: Vec<GridCoord>,
+    pub cycle_turns: u16,
+    ///Extra health every unit gets each time the waves repeat.
+    pub health_growth: i8,
+    pub waves: Vec<WaveDef>,
+}
+impl WaveData {
+    pub fn load(grid: &grids::GridMatrix) -> Result<Self, WaveError> {
+        Self::from_json(WAVES_JSON, grid)
+    }
+
+    pub fn from_json(a: &str, grid: &grids::GridMatrix) -> Result<Self, WaveError> {
+        let data: WaveData = serde_json::from_str(a).map_err(WaveError::Json)?;
+        if data.cycle_turns == 0 {
+            return Err(WaveError::NoCycle);
+        }
+        for (wave, a) in data.waves.iter().enumerate() {
+            if a.turn == 0 || a.turn > data.cycle_turns {
+                return Err(WaveError::TurnOutOfCycle { wave, turn: a.turn });
+            }
+            let last = grid.num_rows() - 1;
+            let on_edge = |GridCoord(a): GridCoord| {
+                grid.filter().filter(&GridCoord(a)) && a.iter().any(|&b| b == 0 || b == last)
+            };
+            if let Some(s) = a.spawns.iter().find(|s| !on_edge(s.cell)) {
+                return Err(WaveError::SpawnOffEdge { wave, cell: s.cell });
+            }
+        }
+        Ok(data)
+    }
+
+    //All the units to spawn on this turn along with their health.
+    fn spawns(&self, turn: u16) -> Vec<(GridCoord, i8)> {
+        //Loaded data is checked but a save could still have been edited.
+        let Some(cycle) = turn.saturating_sub(1).checked_div(self.cycle_turns) else {
+            return vec![];
+        };
+        let cycle_turn = turn.saturating_sub(1) % self.cycle_turns + 1;
+
+        let bonus = self.health_growth.saturating_mul(cycle.min(i8::MAX as u16) as i8);
+
+        self.waves
+            .iter()
+            .filter(|a| a.turn == cycle_turn)
+            .flat_map(|a| a.spawns.iter())
+            .map(|a| {
+                (
+                    a.cell,
+                    a.health.saturating_add(bonus).min(Warrior::MAX_HEALTH),
+                )
+            })
+            .collect()
+    }
+
+    //Turns from a wave on this turn until the next one shows up, wrapping around the cycle.
+    fn window(&self, turn: u16) -> u16 {
+        let cycle = u32::from(self.cycle_turns.max(1));
+        let cycle_turn = u32::from(turn.saturating_sub(1)) % cycle + 1;
+        self.waves
+            .iter()
+            .map(|a| (u32::from(a.turn) + cycle - cycle_turn - 1) % cycle + 1)
+            .min()
+            .unwrap_or(cycle) as u16
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Survival {
+    pub data: WaveData,
+    //Number of turns the computer has taken.
+    pub turn: u16,
+    pub spawned: u16,
+    ///The score. Number of waves that were defeated or held off until the next one.
+    pub survived: u16,
+    pub game_over: bool,
+    //Waves not survived yet, with the turn they count as held off on.
+    #[serde(default)]
+    open: Vec<(u16, u16)>,
+}
+impl Survival {
+    pub fn new(data: WaveData) -> Self {
+        Survival {
+            data,
+            turn: 0,
+            spawned: 0,
+            survived: 0,
+            game_over: false,
+            open: vec![],
+        }
+    }
+}
+
+pub fn new_game() -> Result<Game, WaveError> {
+    let grid_matrix = grids::GridMatrix::new();
+    let data = WaveData::load(&grid_matrix)?;
+    let rules = economy::EconomyRules::default();
+    let starting_resources = rules.starting_resources;
+
+    let defenders = UnitCollection::new(vec![
+        Warrior::new(GridCoord([4, 5])),
+        Warrior::new(GridCoord([5, 4])),
+        Warrior::new(GridCoord([3, 3])),
+        Warrior::new(GridCoord([6, 6])),
+    ]);
+
+    Ok(Game {
+        team: 0,
+        cats: Tribe::new(vec![defenders], GridCoord([5, 5]), starting_resources),
+        dogs: Tribe::new(vec![UnitCollection::new(vec![])], GridCoord([0, 0]), 0),
+        grid_matrix,
+        roads: terrain::roads(vec![]),
+        economy: economy::Economy::new(rules, vec![]),
+        survival: Some(Survival::new(data)),
+        ai_team: Some(AI_TEAM),
+        confirm_attack: true,
+        popups: model::popup::Popups::new(),
+    })
+}
+
+pub fn is_game_over(stuff: &Stuff) -> bool {
+    stuff.survival.as_ref().map(|a| a.game_over).unwrap_or(false)
+}
+
+//Head for the closest objective.
+fn objective_goal(unit: &WarriorPointer<&Warrior>, stuff: &Stuff) -> Option<GridCoord> {
+    let s = stuff.survival.as_ref()?;
+    ai::closest(&unit.position, s.data.objectives.iter())
+}
+
+///The computers turn. Spawns any waves that are due and attacks.
+pub fn handle_wave_turn() -> impl GameStepper<GameHandle, Result = ()> {
+    gameplay::next::<GameHandle>()
+        .map(|_, stuff: &mut Stuff| {
+            state::start_turn(stuff);
+            start_wave_turn(stuff);
+            ai::handle_ai_move(objective_goal)
+        })
+        .flatten()
+        .map(|_, stuff| {
+            stuff.this_team.reset_attacked();
+            stuff.this_team.tick_effects();
+            end_wave_turn(stuff);
+        })
+}
+
+fn start_wave_turn(stuff: &mut Stuff) {
+    let Some(s) = stuff.survival.as_deref_mut() else {
+        return;
+    };
+
+    s.turn += 1;
+
+    //A wave is survived once all its units are gone or the next one is due.
+    let (turn, team) = (s.turn, &*stuff.this_team);
+    let open = s.open.len();
+    s.open
+        .retain(|&(wave, until)| turn < until && team.iter().any(|a| a.wave == Some(wave)));
+    s.survived += (open - s.open.len()) as u16;
+
+    let spawns = s.data.spawns(s.turn);
+    if !spawns.is_empty() {
+        s.spawned += 1;
+        let until = s.turn.saturating_add(s.data.window(s.turn));
+        s.open.push((s.spawned, until));
+    }
+    let wave = s.spawned;
+
+    for (cell, health) in spawns {
+        if stuff.this_team.find_slow(&cell).is_some() || stuff.that_team.find_slow(&cell).is_some()
+        {
+            log!(format!("spawn blocked:{:?}", cell));
+            continue;
+        }
+
+        let mut w = Warrior::new(cell);
+        w.health = health;
+        w.stamina = MoveUnit(2);
+        w.wave = Some(wave);
+        stuff.this_team.add_recruit(w);
+    }
+}
+
+fn end_wave_turn(stuff: &mut Stuff) {
+    let Some(s) = stuff.survival.as_deref_mut() else {
+        return;
+    };
+
+    let objective_taken = s
+        .data
+        .objectives
+        .iter()
+        .any(|a| stuff.this_team.find_slow(a).is_some());
+
+    let defenders_gone = stuff.that_team.iter().next().is_none();
+
+    if objective_taken || defenders_gone {
+        log!(format!("game over! waves survived:{:?}", s.survived));
+        s.game_over = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bundled_waves_load() {
+        let data = WaveData::load(&grids::GridMatrix::new()).unwrap();
+        assert!(data.cycle_turns > 0);
+    }
+
+    #[test]
+    fn rejects_bad_waves() {
+        let gg = grids::GridMatrix::new();
+        let wave = |cycle_turns, turn| {
+            format!(
+                r#"{{"objectives": [], "cycle_turns": {}, "health_growth": 1,
+                "waves": [{{"turn": {}, "spawns": [{{"cell": [0, 0], "health": 4}}]}}]}}"#,
+                cycle_turns, turn
+            )
+        };
+        assert!(WaveData::from_json(&wave(3, 3), &gg).is_ok());
+        assert!(matches!(
+            WaveData::from_json(&wave(0, 1), &gg),
+            Err(WaveError::NoCycle)
+        ));
+        assert!(matches!(
+            WaveData::from_json(&wave(3, 4), &gg),
+            Err(WaveError::TurnOutOfCycle { wave: 0, turn: 4 })
+        ));
+        assert!(matches!(
+            WaveData::from_json(&wave(3, 0), &gg),
+            Err(WaveError::TurnOutOfCycle { wave: 0, turn: 0 })
+        ));
+        assert!(matches!(
+            WaveData::from_json("{", &gg),
+            Err(WaveError::Json(_))
+        ));
+
+        let spawn = |cell: [i16; 2]| {
+            format!(
+                r#"{{"objectives": [], "cycle_turns": 3, "health_growth": 1,
+                "waves": [{{"turn": 1, "spawns": [{{"cell": {:?}, "health": 4}}]}}]}}"#,
+                cell
+            )
+        };
+        let last = gg.num_rows() - 1;
+        assert!(WaveData::from_json(&spawn([last, 5]), &gg).is_ok());
+        for cell in [[5, 5], [-1, 0], [0, last + 1]] {
+            assert!(matches!(
+                WaveData::from_json(&spawn(cell), &gg),
+                Err(WaveError::SpawnOffEdge { wave: 0, cell: GridCoord(a) }) if a == cell
+            ));
+        }
+    }
+
+    #[test]
+    fn waves_repeat_stronger() {
+        let data = WaveData::load(&grids::GridMatrix::new()).unwrap();
+        let first = data.waves[0].turn;
+        let spawns = data.spawns(first);
+        assert_eq!(spawns.len(), data.waves[0].spawns.len());
+
+        let again = data.spawns(first + data.cycle_turns);
+        assert_eq!(
+            again[0].1,
+            (spawns[0].1 + data.health_growth).min(Warrior::MAX_HEALTH)
+        );
+
+        //An edited save can't make it divide by zero.
+        let broken = WaveData {
+            cycle_turns: 0,
+            ..data
+        };
+        assert!(broken.spawns(first).is_empty());
+    }
+
+    //Run the computers turns up to this one without it moving.
+    fn wave_turns(game: &mut Game, to: u16) -> u16 {
+        realtime::with_stuff(game, AI_TEAM, |stuff| {
+            while stuff.survival.as_ref().unwrap().turn < to {
+                start_wave_turn(stuff);
+            }
+            stuff.survival.as_ref().unwrap().survived
+        })
+    }
+
+    #[test]
+    fn waves_survived_when_gone_or_held_off() {
+        let mut g = new_game().unwrap();
+        let data = g.survival.as_ref().unwrap().data.clone();
+        let [first, third] = [0, 2].map(|a| data.waves[a].turn);
+
+        assert_eq!(wave_turns(&mut g, first + 1), 0);
+
+        //Every unit of the first wave is gone.
+        g.dogs = Tribe::new(vec![UnitCollection::new(vec![])], GridCoord([0, 0]), 0);
+        assert_eq!(wave_turns(&mut g, first + 2), 1);
+
+        //The second wave is still around when the third shows up.
+        assert_eq!(wave_turns(&mut g, third - 1), 1);
+        assert!(g.dogs.iter().count() >= data.waves[1].spawns.len());
+        assert_eq!(wave_turns(&mut g, third), 2);
+    }
+}
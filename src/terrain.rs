@@ -37,7 +37,25 @@ pub struct TerrainCollection<F> {
     pub pos: Vec<GridCoord>,
     pub func: F,
 }
+
+pub type Roads = TerrainCollection<fn(MoveUnit) -> MoveUnit>;
+
+//Roads halve the cost of moving onto them.
+pub fn roads(pos: Vec<GridCoord>) -> Roads {
+    TerrainCollection {
+        pos,
+        func: |a: MoveUnit| MoveUnit(a.0 / 2),
+    }
+}
+
+//Stamina it takes to build one road.
+pub const ROAD_COST: MoveUnit = MoveUnit(2);
+
 impl<F> TerrainCollection<F> {
+    //Allows cells that don't have this terrain on them yet.
+    pub fn filter(&self) -> TerrainFilter<'_> {
+        TerrainFilter { a: &self.pos }
+    }
     pub fn find_mut(&mut self, a: &GridCoord) -> Option<&mut GridCoord> {
         self.pos.iter_mut().find(|b| *b == a)
     }
@@ -49,6 +67,15 @@ impl<F> TerrainCollection<F> {
     }
 }
 
+pub struct TerrainFilter<'a> {
+    a: &'a [GridCoord],
+}
+impl<'a> movement::Filter for TerrainFilter<'a> {
+    fn filter(&self, g: &GridCoord) -> bool {
+        !self.a.contains(g)
+    }
+}
+
 pub struct TerrainCollectionFoo<'a, F> {
     a: &'a [GridCoord],
     func: &'a F,